@@ -6,10 +6,12 @@ use serde::{Serialize, Deserialize};
 
 use clio::{Input, Output};
 
+use crate::types::{PenType, PenColor};
+
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
-    /// Extract a single reMarkable page-file to SVG.
+    /// Extract a reMarkable page-file, or a whole document, to SVG, Markdown, JSON and more.
     Extract(ExtractArgs),
 
     /// Draw a new shape into a reMarkable file
@@ -19,11 +21,12 @@ pub enum Commands {
     // Stats(StatsArgs),
 }
 
-#[derive(Debug, Subcommand, Clone, ValueEnum, Serialize, Deserialize)]
+#[derive(Debug, Subcommand, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
 pub enum OutputFormat {
     Markdown,
     JSON,
     SVG,
+    PDF,
     Debug,
     Bytes,
 }
@@ -49,7 +52,17 @@ pub struct ExtractArgs {
     #[arg(short, long, group = "inargs")]
     pub input: Option<Input>,
 
-    /// SVG file to extract into. If none is given, dump SVG to STDOUT.
+    /// Document in RM_DIR to extract from, by its path on the tablet
+    /// (e.g. `Work/Meetings/2024-03`) or its id
+    #[arg(short, long, group = "inargs")]
+    pub document: Option<String>,
+
+    /// Page of `--document` to extract, counting from 1. Without it, every page
+    /// is extracted into one output.
+    #[arg(long, requires = "document", value_parser = clap::value_parser!(u32).range(1..))]
+    pub page: Option<u32>,
+
+    /// File to extract into. If none is given, write to STDOUT.
     #[clap(value_parser)]
     #[arg(short, long)]
     pub output: Option<Output>,
@@ -64,14 +77,36 @@ pub struct ExtractArgs {
     #[arg(long)]
     pub border: bool,
 
-    //#[arg(short='t', long, default_value="debug")]
-    //pub format: OutputFormat,
+    /// What to extract as. Defaults to SVG.
+    #[arg(short='t', long, value_enum)]
+    pub format: Option<OutputFormat>,
 
+    /// Leave typed text out of the SVG, Markdown or JSON
     #[arg(short='x', long)]
     pub skip_text: bool,
 
+    /// Leave strokes out of the SVG or JSON
     #[arg(short='X', long)]
     pub skip_lines: bool,
+
+    /// Only extract the layer with this name, or this id (e.g. `0:11`). Without it,
+    /// every layer is extracted as its own SVG group.
+    #[arg(long)]
+    pub layer: Option<String>,
+
+    /// Also extract strokes that were erased but are still in the file, drawn
+    /// dashed. Useful for recovering things erased by mistake.
+    #[arg(long)]
+    pub include_deleted: bool,
+
+    /// With `--document` and PDF output, draw onto the document's original PDF
+    /// rather than blank pages. Only for PDFs and EPUBs.
+    #[arg(long, requires = "document")]
+    pub annotate: bool,
+
+    /// Indent JSON output so it's readable
+    #[arg(long)]
+    pub pretty: bool,
 }
 
 #[derive(Debug, Args)]
@@ -83,17 +118,31 @@ pub struct DrawArgs {
     #[command(flatten)]
     pub input: DrawInput,
 
+    /// Page of `--document` to draw into, counting from 1. Defaults to the page
+    /// it was last opened at.
+    #[arg(long, requires = "document", value_parser = clap::value_parser!(u32).range(1..))]
+    pub page: Option<u32>,
+
+    /// Width to draw with, in page units (the page is 1404 wide)
     #[arg(long)]
-    pub width: Option<String>,
+    pub width: Option<f64>,
 
+    /// Pen to draw with, e.g. `fineliner`, `ballpoint` or `highlighter`
     #[arg(long)]
-    pub pen: Option<String>,
+    pub pen: Option<PenType>,
 
+    /// Layer to draw into, by name or id (e.g. `0:11`). Defaults to the bottom layer.
     #[arg(long)]
     pub layer: Option<String>,
 
+    /// Color to draw in, e.g. `black`, `blue` or `red`
     #[arg(long)]
-    pub color: Option<String>,
+    pub color: Option<PenColor>,
+
+    /// Take each stroke's color, width and opacity from the SVG being drawn.
+    /// `--pen`, `--color` and `--width` still win where they're given.
+    #[arg(long)]
+    pub style_from_svg: bool,
 }
 
 #[derive(Debug, Args)]
@@ -115,14 +164,19 @@ pub struct DrawInput {
 #[derive(Debug, Args)]
 #[group(required = true, multiple = false)]
 pub struct DrawTarget {
-    /// reMarkable file to target.
+    /// reMarkable file to target. It is read, then replaced with the new drawing.
     #[arg(short,long)]
-    pub output: Option<Output>,
+    pub output: Option<PathBuf>,
     
     /// Attempt to target the last opened reMarkable page file (slow). 
     /// Will fail if no file can be found.
     #[arg(short,long)]
     pub last: bool,
+
+    /// Document in RM_DIR to draw into, by its path on the tablet
+    /// (e.g. `Work/Meetings/2024-03`) or its id
+    #[arg(short, long)]
+    pub document: Option<String>,
 }
 
 
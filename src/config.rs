@@ -28,6 +28,10 @@ pub struct BytesCfg { }
 #[derive(Serialize, Deserialize)]
 pub struct JsonCfg { }
 
+#[allow(unused)]
+#[derive(Serialize, Deserialize)]
+pub struct PdfCfg { }
+
 #[allow(unused)]
 #[derive(Serialize, Deserialize)]
 pub struct OutputCfg {
@@ -37,6 +41,7 @@ pub struct OutputCfg {
     pub debug: DebugCfg,
     pub bytes: BytesCfg,
     pub json: JsonCfg,
+    pub pdf: PdfCfg,
 }
 
 
@@ -52,12 +57,13 @@ impl Settings {
     pub fn empty() -> Self {
         Settings{
             output: OutputCfg{
-                default: OutputFormat::Markdown,
+                default: OutputFormat::SVG,
                 markdown: MarkdownCfg {  },
                 svg: SvgCfg {  },
                 debug: DebugCfg {  },
                 bytes: BytesCfg {  },
                 json: JsonCfg {  },
+                pdf: PdfCfg {  },
             },
             rm_path: PathBuf::new(),
         }
@@ -0,0 +1,408 @@
+
+//! The `.content` file that sits next to each document's page directory.
+//!
+//! It holds the document's settings (text, zoom, templates) and, most importantly,
+//! its list of pages. There are two layouts of that list:
+//!
+//! - `pages`: a plain array of page ids, in order. Written by firmware 2.x.
+//! - `cPages`: written by firmware 3.x. Every setting on it is a CRDT register, a
+//!   value with the `author:counter` timestamp of its last change, so that edits
+//!   made on different devices can be merged. Pages are ordered by their `idx`
+//!   value, which is a string that sorts between its neighbours.
+//!
+//! Fields this model doesn't know are kept in `other`, and numbers stay as the
+//! file wrote them (`1` isn't saved as `1.0`), so a file can be read and saved
+//! again without losing anything.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Number, Value};
+
+use super::types::*;
+
+type Result<T> = std::result::Result<T, RMError>;
+
+/// A value, with the timestamp of the change that set it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timestamped<T> {
+    #[serde(with = "crdt_string")]
+    pub timestamp: CrdtId,
+    pub value: T,
+}
+
+impl<T> Timestamped<T> {
+    pub fn new(timestamp: CrdtId, value: T) -> Self {
+        Timestamped { timestamp, value }
+    }
+}
+
+/// CRDT ids as `"1:25"`, the way `.content` writes timestamps.
+mod crdt_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use crate::types::CrdtId;
+
+    pub fn serialize<S: Serializer>(id: &CrdtId, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(id)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CrdtId, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileType {
+    Notebook,
+    Pdf,
+    Epub,
+    #[serde(untagged)]
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    /// The old page list: page ids in order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pages: Option<Vec<String>>,
+    /// The new page list
+    #[serde(rename = "cPages", default, skip_serializing_if = "Option::is_none")]
+    pub c_pages: Option<CPages>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_type: Option<FileType>,
+    /// 1 for the `pages` layout, 2 for `cPages`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format_version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_count: Option<u32>,
+    /// Which page to show as the cover: 0 for the first, -1 for the last opened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_page_number: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<String>,
+    /// The size of the whole document, as a string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_in_bytes: Option<String>,
+
+    // text settings, for typed text and EPUBs
+    /// Empty for the default font
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub font_name: Option<String>,
+    /// -1 for the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_height: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margins: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_alignment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_scale: Option<Number>,
+
+    // zoom
+    /// `bestFit`, `fitToWidth`, `fitToHeight` or `customFit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zoom_mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_zoom_center_x: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_zoom_center_y: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_zoom_orientation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_zoom_page_height: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_zoom_page_width: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_zoom_scale: Option<Number>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<Tag>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_tags: Option<Vec<PageTag>>,
+
+    /// Title, authors etc. of PDFs and EPUBs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_metadata: Option<Map<String, Value>>,
+    /// The last pen, color and size used with each tool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_metadata: Option<BTreeMap<String, String>>,
+
+    /// Keys read as `null`; see `null_keys`
+    #[serde(skip)]
+    pub nulls: Vec<String>,
+
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CPages {
+    /// Id of the page the document was last opened at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_opened: Option<Timestamped<String>>,
+    /// Number of pages in the original PDF or EPUB, -1 for notebooks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original: Option<Timestamped<i32>>,
+    /// Every page, including deleted ones, in no particular order
+    #[serde(default)]
+    pub pages: Vec<CPage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuids: Option<Vec<AuthorId>>,
+
+    /// Keys read as `null`; see `null_keys`
+    #[serde(skip)]
+    pub nulls: Vec<String>,
+
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CPage {
+    /// The page's id, and the name of its `.rm` file
+    pub id: String,
+    /// Sort key: pages are shown in the order of these strings
+    pub idx: Timestamped<String>,
+    /// Template name, e.g. `P Dots S`. Blank or missing for no template.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<Timestamped<String>>,
+    /// For PDFs, the page of the original this page shows. Missing on inserted pages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redir: Option<Timestamped<i32>>,
+    /// Nonzero for deleted pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<Timestamped<i32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll_time: Option<Timestamped<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vertical_scroll: Option<Timestamped<Number>>,
+
+    /// Keys read as `null`; see `null_keys`
+    #[serde(skip)]
+    pub nulls: Vec<String>,
+
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// Maps the author numbers in CRDT ids to the devices that made them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorId {
+    pub first: String,
+    pub second: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub name: String,
+    /// Milliseconds since the epoch
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageTag {
+    pub name: String,
+    pub page_id: String,
+    /// Milliseconds since the epoch
+    pub timestamp: u64,
+}
+
+impl CPage {
+    pub fn is_deleted(&self) -> bool {
+        self.deleted.as_ref().is_some_and(|d| d.value != 0)
+    }
+}
+
+impl CPages {
+    /// Pages that haven't been deleted, in the order they're shown.
+    pub fn ordered(&self) -> Vec<&CPage> {
+        let mut pages: Vec<&CPage> = self.pages.iter().filter(|p| !p.is_deleted()).collect();
+        pages.sort_by(|a, b| a.idx.value.cmp(&b.idx.value).then_with(|| a.id.cmp(&b.id)));
+        pages
+    }
+}
+
+impl Content {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(RMError::IoError)?;
+        Content::from_value(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Read a parsed `.content`, remembering which keys were `null`.
+    pub fn from_value(value: Value) -> Result<Self> {
+        let mut content: Content = serde_json::from_value(value.clone())?;
+        content.nulls = null_keys(&value);
+        if let Some(c_pages) = &mut content.c_pages {
+            let raw = &value["cPages"];
+            c_pages.nulls = null_keys(raw);
+            let raw_pages = raw["pages"].as_array().map(Vec::as_slice).unwrap_or_default();
+            for page in &mut c_pages.pages {
+                if let Some(raw_page) = raw_pages.iter().find(|p| p["id"] == page.id.as_str()) {
+                    page.nulls = null_keys(raw_page);
+                };
+            }
+        };
+        Ok(content)
+    }
+
+    /// The JSON to save, with the `null`s the file was read with.
+    pub fn to_value(&self) -> Result<Value> {
+        let mut value = serde_json::to_value(self)?;
+        restore_nulls(&mut value, &self.nulls);
+        if let Some(c_pages) = &self.c_pages {
+            let raw = &mut value["cPages"];
+            restore_nulls(raw, &c_pages.nulls);
+            if let Some(raw_pages) = raw["pages"].as_array_mut() {
+                for (page, raw_page) in c_pages.pages.iter().zip(raw_pages) {
+                    restore_nulls(raw_page, &page.nulls);
+                }
+            };
+        };
+        Ok(value)
+    }
+
+    /// Save in the same layout as the tablet: keys sorted, indented by four spaces.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut bytes = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+        let mut serializer = serde_json::Serializer::with_formatter(&mut bytes, formatter);
+        self.to_value()?.serialize(&mut serializer)?;
+        bytes.push(b'\n');
+        std::fs::write(path, bytes).map_err(RMError::IoError)
+    }
+
+    /// Ids of the document's pages, in order, from whichever page list it has.
+    pub fn page_ids(&self) -> Vec<&str> {
+        match (&self.c_pages, &self.pages) {
+            (Some(c_pages), _) => c_pages.ordered().into_iter().map(|p| p.id.as_str()).collect(),
+            (None, Some(pages)) => pages.iter().map(String::as_str).collect(),
+            (None, None) => Vec::new(),
+        }
+    }
+
+    /// The id of the page the document was last opened at. Old `.content` files
+    /// don't say; `.metadata`'s `lastOpenedPage` index is the fallback.
+    pub fn last_opened(&self, last_opened_page: Option<usize>) -> Option<&str> {
+        if let Some(last) = self.c_pages.as_ref().and_then(|c| c.last_opened.as_ref()) {
+            return Some(&last.value);
+        };
+        self.page_ids().get(last_opened_page?).copied()
+    }
+}
+
+/// The keys of a JSON object whose values are `null`.
+///
+/// Typed fields read an explicit `null` as `None`, and `None` isn't written when
+/// saving, so the key would be lost. `Content::from_value` keeps these keys in each
+/// struct's `nulls`, and `Content::to_value` puts them back with `restore_nulls`.
+fn null_keys(value: &Value) -> Vec<String> {
+    value.as_object().map_or_else(Vec::new, |object| {
+        object.iter().filter(|(_, v)| v.is_null()).map(|(k, _)| k.clone()).collect()
+    })
+}
+
+/// Put back `null`s for the keys that have no value now.
+fn restore_nulls(value: &mut Value, nulls: &[String]) {
+    if let Some(object) = value.as_object_mut() {
+        for key in nulls {
+            object.entry(key.as_str()).or_insert(Value::Null);
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONTENT: &str = "assets/test_notebooks/213001cb-42c0-4628-8ed0-8320c15da2a8.content";
+
+    #[test]
+    fn c_pages() {
+        let content = Content::from_file(CONTENT).unwrap();
+        assert_eq!(content.file_type, Some(FileType::Notebook));
+        assert_eq!(content.format_version, Some(2));
+        assert_eq!(content.margins, Some(125));
+        assert_eq!(content.zoom_mode.as_deref(), Some("bestFit"));
+
+        let c_pages = content.c_pages.as_ref().unwrap();
+        let last = c_pages.last_opened.as_ref().unwrap();
+        assert_eq!(last.timestamp, CrdtId::new(1, 25));
+        assert_eq!(content.last_opened(None), Some("9e0bdc4b-14cd-4d25-abb9-3ffd58d5a66e"));
+
+        let ids = content.page_ids();
+        assert_eq!(ids.len(), 21);
+        assert_eq!(ids[0], "24f01482-b319-4c5b-b1d8-804ce5a25d0c");
+        assert_eq!(ids[20], "9e0bdc4b-14cd-4d25-abb9-3ffd58d5a66e");
+        assert_eq!(c_pages.pages[3].vertical_scroll.as_ref().map(|v| v.value.as_f64()), Some(Some(1872.0)));
+        assert_eq!(c_pages.pages[0].template.as_ref().unwrap().value, "P Dots S");
+    }
+
+    #[test]
+    fn page_order() {
+        let mut content = Content::from_file(CONTENT).unwrap();
+        let c_pages = content.c_pages.as_mut().unwrap();
+        c_pages.pages.swap(0, 5);
+        c_pages.pages[2].idx = Timestamped::new(CrdtId::new(1, 30), "b".to_string());
+        c_pages.pages[3].deleted = Some(Timestamped::new(CrdtId::new(1, 31), 1));
+
+        let ids = content.page_ids();
+        assert_eq!(ids.len(), 20);
+        assert_eq!(ids[0], "8b2c35bb-b4a0-4141-83a6-01f69b3ff850");
+        assert_eq!(ids[1], "24f01482-b319-4c5b-b1d8-804ce5a25d0c");
+        assert!(!ids.contains(&"ded854a1-beab-4db5-8720-511a93836570"));
+    }
+
+    #[test]
+    fn old_pages() {
+        let json = r#"{
+            "extraMetadata": {"LastPen": "Finelinerv2"},
+            "fileType": "pdf",
+            "fontName": "",
+            "lastOpenedPage": 1,
+            "lineHeight": -1,
+            "margins": 100,
+            "orientation": "portrait",
+            "pageCount": 2,
+            "pages": ["first", "second"],
+            "redirectionPageMap": [0, -1],
+            "textScale": 1.2,
+            "customZoomScale": 1,
+            "customZoomCenterX": null,
+            "transform": {"m11": 1}
+        }"#;
+        let content = Content::from_value(serde_json::from_str(json).unwrap()).unwrap();
+        assert_eq!(content.file_type, Some(FileType::Pdf));
+        assert_eq!(content.page_ids(), vec!["first", "second"]);
+        assert_eq!(content.last_opened(Some(1)), Some("second"));
+        assert_eq!(content.text_scale.as_ref().and_then(Number::as_f64), Some(1.2));
+        assert_eq!(content.custom_zoom_center_x, None);
+        assert!(content.other.contains_key("redirectionPageMap"));
+
+        let saved = content.to_value().unwrap();
+        assert_eq!(saved, serde_json::from_str::<Value>(json).unwrap());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let content = Content::from_file(CONTENT).unwrap();
+        let mut path = std::env::temp_dir();
+        path.push(format!("rmconvert-content-{}.content", std::process::id()));
+        content.to_file(&path).unwrap();
+
+        let saved: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        let original: Value = serde_json::from_slice(&std::fs::read(CONTENT).unwrap()).unwrap();
+        assert_eq!(saved, original);
+        assert_eq!(saved["cPages"]["pages"][3]["verticalScroll"]["value"].to_string(), "1872");
+        assert_eq!(Content::from_file(&path).unwrap(), content);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+
+//! A whole document: its `.metadata`, `.content`, `.pagedata` and `.local` files,
+//! and its pages.
+//!
+//! A document with id `<id>` is spread over these, all in the storage directory:
+//!
+//! ```text
+//! <id>.metadata     name, folder and times (see `metadata`)
+//! <id>.content      settings and the page list (see `content`)
+//! <id>.pagedata     one template name per line, for documents without `cPages`
+//! <id>.local        device-local state, usually just `{}`
+//! <id>.pdf/.epub    the original file, for PDFs and EPUBs
+//! <id>/             one `<page id>.rm` for every page that has been written on
+//! <id>.thumbnails/  one `<page id>.png` (or `.jpg`) per page
+//! ```
+//!
+//! Only `.metadata` and `.content` have to be there.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use super::types::*;
+use super::parse::parse_full;
+use super::write::write_blocks_to_rm_file;
+use super::content::Content;
+use super::metadata::DocumentMetadata;
+
+type Result<T> = std::result::Result<T, RMError>;
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub id: String,
+    /// The storage directory the document is in
+    pub root: PathBuf,
+    pub metadata: DocumentMetadata,
+    pub content: Content,
+    /// Templates from `.pagedata`, by page index. Empty if there's no such file.
+    pub pagedata: Vec<String>,
+    /// `.local`, if there is one
+    pub local: Option<Map<String, Value>>,
+    /// Pages in the order they're shown, without deleted pages
+    pub pages: Vec<Page>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub id: String,
+    /// Where the page is in the document, counting from 0
+    pub index: usize,
+    /// Template name, e.g. `P Dots S`. `None` for no template.
+    pub template: Option<String>,
+    /// For PDFs and EPUBs, the page of the original file this page shows, counting
+    /// from 0. `None` for pages inserted on the tablet, and for notebooks.
+    pub original_page: Option<usize>,
+    /// The page's `.rm` file. Pages that have never been written on don't have one yet.
+    pub file: PathBuf,
+    pub thumbnail: Option<PathBuf>,
+}
+
+impl Page {
+    /// Read the page's `.rm` file. Pages that have never been written on are empty.
+    pub fn read(&self) -> anyhow::Result<Notebook> {
+        match self.file.is_file() {
+            true => parse_full(&std::fs::read(&self.file)?),
+            false => Ok(Notebook { frontmatter: Frontmatter::new(6), blocks: Vec::new() }),
+        }
+    }
+}
+
+impl Document {
+    /// Open the document `id` in the storage directory `root`.
+    pub fn open<P: AsRef<Path>>(root: P, id: &str) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let file = |extension: &str| root.join(format!("{}.{}", id, extension));
+
+        let metadata = DocumentMetadata::from_file(file("metadata"))?;
+        let content = Content::from_file(file("content"))?;
+        let pagedata = match std::fs::read_to_string(file("pagedata")) {
+            Ok(text) => text.lines().map(str::to_string).collect(),
+            Err(_) => Vec::new(),
+        };
+        let local = match std::fs::read(file("local")) {
+            Ok(bytes) => Some(serde_json::from_slice(&bytes)?),
+            Err(_) => None,
+        };
+
+        let mut document = Document { id: id.to_string(), root, metadata, content, pagedata, local, pages: Vec::new() };
+        document.pages = document.find_pages();
+        Ok(document)
+    }
+
+    fn find_pages(&self) -> Vec<Page> {
+        let page_dir = self.root.join(&self.id);
+        let thumbnail_dir = self.root.join(format!("{}.thumbnails", self.id));
+        let existing = |path: PathBuf| path.is_file().then_some(path);
+
+        let page = |index: usize, id: &str, template: Option<&str>, original_page: Option<usize>| Page {
+            id: id.to_string(),
+            index,
+            template: template.or(self.pagedata.get(index).map(String::as_str))
+                              .filter(|t| !t.is_empty())
+                              .map(str::to_string),
+            original_page,
+            file: page_dir.join(format!("{}.rm", id)),
+            thumbnail: existing(thumbnail_dir.join(format!("{}.png", id)))
+                .or_else(|| existing(thumbnail_dir.join(format!("{}.jpg", id)))),
+        };
+
+        match (&self.content.c_pages, &self.content.pages) {
+            (Some(c_pages), _) => {
+                // pages past the end of the original can't show it
+                let original_count = c_pages.original.as_ref().and_then(|o| usize::try_from(o.value).ok());
+                c_pages.ordered().into_iter().enumerate().map(|(index, p)| {
+                    let template = p.template.as_ref().map(|t| t.value.as_str());
+                    let original_page = p.redir.as_ref()
+                                         .and_then(|r| usize::try_from(r.value).ok())
+                                         .filter(|r| original_count.is_none_or(|count| *r < count));
+                    page(index, &p.id, template, original_page)
+                }).collect()
+            },
+            (None, Some(ids)) => {
+                // without cPages, pages of a PDF are the original's pages, in order
+                let redirection = self.content.other.get("redirectionPageMap").and_then(Value::as_array);
+                ids.iter().enumerate().map(|(index, id)| {
+                    let original_page = match redirection {
+                        Some(map) => map.get(index).and_then(Value::as_i64).and_then(|r| usize::try_from(r).ok()),
+                        None => self.is_annotated_file().then_some(index),
+                    };
+                    page(index, id, None, original_page)
+                }).collect()
+            },
+            (None, None) => Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.metadata.visible_name
+    }
+
+    /// Whether this is a PDF or EPUB, rather than a notebook.
+    pub fn is_annotated_file(&self) -> bool {
+        use super::content::FileType;
+        matches!(self.content.file_type, Some(FileType::Pdf) | Some(FileType::Epub))
+    }
+
+    /// The original PDF, for PDFs and EPUBs. The tablet keeps a PDF of EPUBs too.
+    pub fn pdf_file(&self) -> Option<PathBuf> {
+        let path = self.root.join(format!("{}.pdf", self.id));
+        path.is_file().then_some(path)
+    }
+
+    /// The devices in `.content`'s `uuids`, as a page's `AuthorIds` block lists them.
+    pub fn authors(&self) -> AuthorIds {
+        let uuids = self.content.c_pages.as_ref().and_then(|c| c.uuids.as_ref());
+        let authors = uuids.into_iter().flatten().filter_map(|a| {
+            let hex: Vec<char> = a.first.chars().filter(|c| *c != '-').collect();
+            let uuid = hex.chunks(2)
+                          .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok())
+                          .collect::<Option<Vec<u8>>>()?;
+            Some(AuthorId { uuid, author: u16::try_from(a.second).ok()? })
+        }).collect();
+        AuthorIds { authors }
+    }
+
+    /// Give a page that has never been written on a `.rm` file of its own, blank,
+    /// so it can be drawn into. Pages that already have one are left alone.
+    pub fn start_page(&self, page: &Page) -> anyhow::Result<()> {
+        if page.file.is_file() {
+            return Ok(());
+        };
+        if let Some(page_dir) = page.file.parent() {
+            std::fs::create_dir_all(page_dir)?;
+        };
+        write_blocks_to_rm_file(Notebook::blank(self.authors()), &page.file)?;
+        Ok(())
+    }
+
+    /// The page with this number, counting from 1, as shown on the tablet.
+    pub fn page(&self, number: usize) -> Result<&Page> {
+        number.checked_sub(1)
+              .and_then(|index| self.pages.get(index))
+              .ok_or(RMError::ArgsError(format!("no page {} in {:?}, which has {} pages",
+                                                number, self.name(), self.pages.len())))
+    }
+
+    /// The page the document was last opened at, or the first page.
+    pub fn last_opened_page(&self) -> Option<&Page> {
+        self.content.last_opened(self.metadata.last_opened_page)
+                    .and_then(|id| self.pages.iter().find(|p| p.id == id))
+                    .or(self.pages.first())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RM_DIR: &str = "assets/test_notebooks";
+
+    #[test]
+    fn open_document() {
+        let document = Document::open(RM_DIR, "e9453e30-cd4a-4a9b-aef4-41e04c047ff5").unwrap();
+        assert_eq!(document.name(), "tester3");
+        assert_eq!(document.local, Some(Map::new()));
+        assert!(!document.is_annotated_file());
+
+        let ids: Vec<&str> = document.pages.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["645327b2-7ac1-4a8a-b68d-573480dbf41f",
+                         "9f93cfc0-1a7e-400d-9194-b816c0b27a6a",
+                         "5b6148bf-2906-4281-adb5-a428d83de792"]);
+        let page = document.page(3).unwrap();
+        assert_eq!(page.index, 2);
+        assert_eq!(page.template.as_deref(), Some("P Dots S"));
+        assert_eq!(page.original_page, None);
+        assert!(page.file.is_file() && page.thumbnail.is_some());
+        assert_eq!(document.last_opened_page(), Some(page));
+        assert!(document.page(0).is_err() && document.page(4).is_err());
+
+        let notebook = page.read().unwrap();
+        assert_eq!(notebook.frontmatter.version, 6);
+    }
+
+    #[test]
+    fn original_pages() {
+        let root = std::env::temp_dir().join(format!("rmconvert-document-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("annotated.metadata"),
+                       r#"{"visibleName": "paper", "parent": "", "type": "DocumentType"}"#).unwrap();
+        // moved, inserted, deleted, and pointing past the original's three pages
+        let page = |id: &str, idx: &str, redir: Option<i32>, deleted: bool| serde_json::json!({
+            "id": id,
+            "idx": {"timestamp": "1:2", "value": idx},
+            "redir": redir.map(|r| serde_json::json!({"timestamp": "1:3", "value": r})),
+            "deleted": deleted.then(|| serde_json::json!({"timestamp": "1:4", "value": 1})),
+        });
+        let content = serde_json::json!({
+            "fileType": "pdf",
+            "cPages": {
+                "original": {"timestamp": "1:1", "value": 3},
+                "pages": [
+                    page("a", "bd", Some(0), false),
+                    page("b", "ba", Some(1), false),
+                    page("c", "bb", None, false),
+                    page("d", "bc", Some(2), true),
+                    page("e", "be", Some(3), false),
+                ],
+            },
+        });
+        std::fs::write(root.join("annotated.content"), content.to_string()).unwrap();
+
+        let document = Document::open(&root, "annotated").unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(document.is_annotated_file());
+        let pages: Vec<(&str, Option<usize>)> = document.pages.iter()
+                                                        .map(|p| (p.id.as_str(), p.original_page))
+                                                        .collect();
+        assert_eq!(pages, [("b", Some(1)), ("c", None), ("a", Some(0)), ("e", None)]);
+        assert_eq!(document.pdf_file(), None);
+    }
+
+    #[test]
+    fn blank_pages() {
+        let page = Page {
+            id: "blank".to_string(),
+            index: 0,
+            template: None,
+            original_page: None,
+            file: PathBuf::from("no/such/page.rm"),
+            thumbnail: None,
+        };
+        assert!(page.read().unwrap().blocks.is_empty());
+    }
+}
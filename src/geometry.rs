@@ -0,0 +1,628 @@
+
+//! Geometry for SVG import: turning path data into polylines.
+//!
+//! Everything here works in f64 and in the SVG's own user units; it's only turned
+//! into page coordinates once the points are made into `SimplePoint`s.
+
+use std::ops::Mul;
+use std::str::FromStr;
+
+use svg::node::element::path::{Command, Data, Position};
+
+use super::types::*;
+
+type Result<T> = std::result::Result<T, RMError>;
+
+pub type Pt = (f64, f64);
+
+/// How far, in user units, a flattened curve may stray from the real one.
+pub const DEFAULT_TOLERANCE: f64 = 0.25;
+
+/// Deepest a curve is split when flattening, whatever the tolerance.
+const MAX_DEPTH: u32 = 16;
+
+/// Flatten path data into one polyline per subpath.
+///
+/// Every command is supported, absolute and relative. A new subpath starts at each
+/// moveto, and after a closepath. Subpaths with fewer than two distinct points
+/// are dropped.
+pub fn flatten_path(data: &Data, tolerance: f64) -> Result<Vec<Vec<Pt>>> {
+    let mut flat = Flattener {
+        tolerance: tolerance.max(1e-6),
+        ..Flattener::default()
+    };
+
+    for command in data.iter() {
+        flat.command(command)?;
+    };
+    flat.finish_subpath();
+    Ok(flat.subpaths)
+}
+
+#[derive(Default)]
+struct Flattener {
+    tolerance: f64,
+    subpaths: Vec<Vec<Pt>>,
+    current: Vec<Pt>,
+    pos: Pt,
+    start: Pt,
+    /// The second control point of the last cubic, for S
+    last_cubic: Option<Pt>,
+    /// The control point of the last quadratic, for T
+    last_quad: Option<Pt>,
+}
+
+impl Flattener {
+    fn command(&mut self, command: &Command) -> Result<()> {
+        let (pos, params, arity) = match command {
+            Command::Move(p, a) | Command::Line(p, a) | Command::SmoothQuadraticCurve(p, a) => (p, a, 2),
+            Command::HorizontalLine(p, a) | Command::VerticalLine(p, a) => (p, a, 1),
+            Command::QuadraticCurve(p, a) | Command::SmoothCubicCurve(p, a) => (p, a, 4),
+            Command::CubicCurve(p, a) => (p, a, 6),
+            Command::EllipticalArc(p, a) => (p, a, 7),
+            Command::Close => {
+                self.close();
+                return Ok(());
+            },
+        };
+        let relative = matches!(pos, Position::Relative);
+
+        if params.is_empty() || params.len() % arity != 0 {
+            return Err(RMError::ArgsError(format!("bad path data: {:?}", command)));
+        };
+
+        for (i, args) in params.chunks(arity).enumerate() {
+            let args: Vec<f64> = args.iter().map(|a| *a as f64).collect();
+            let origin = if relative { self.pos } else { (0.0, 0.0) };
+            let abs = |x: f64, y: f64| (origin.0 + x, origin.1 + y);
+
+            let mut cubic = None;
+            let mut quad = None;
+            match command {
+                // pairs after the first in a moveto are linetos
+                Command::Move(..) if i == 0 => {
+                    self.finish_subpath();
+                    self.pos = abs(args[0], args[1]);
+                    self.start = self.pos;
+                    self.current.push(self.pos);
+                },
+                Command::Move(..) | Command::Line(..) => {
+                    self.line_to(abs(args[0], args[1]));
+                },
+                Command::HorizontalLine(..) => {
+                    let x = if relative { self.pos.0 + args[0] } else { args[0] };
+                    self.line_to((x, self.pos.1));
+                },
+                Command::VerticalLine(..) => {
+                    let y = if relative { self.pos.1 + args[0] } else { args[0] };
+                    self.line_to((self.pos.0, y));
+                },
+                Command::CubicCurve(..) => {
+                    let (c1, c2, end) = (abs(args[0], args[1]), abs(args[2], args[3]), abs(args[4], args[5]));
+                    self.cubic_to(c1, c2, end);
+                    cubic = Some(c2);
+                },
+                Command::SmoothCubicCurve(..) => {
+                    let c1 = reflect(self.last_cubic, self.pos);
+                    let (c2, end) = (abs(args[0], args[1]), abs(args[2], args[3]));
+                    self.cubic_to(c1, c2, end);
+                    cubic = Some(c2);
+                },
+                Command::QuadraticCurve(..) => {
+                    let (c, end) = (abs(args[0], args[1]), abs(args[2], args[3]));
+                    self.quad_to(c, end);
+                    quad = Some(c);
+                },
+                Command::SmoothQuadraticCurve(..) => {
+                    let c = reflect(self.last_quad, self.pos);
+                    self.quad_to(c, abs(args[0], args[1]));
+                    quad = Some(c);
+                },
+                Command::EllipticalArc(..) => {
+                    let end = abs(args[5], args[6]);
+                    self.arc_to((args[0], args[1]), args[2], args[3] != 0.0, args[4] != 0.0, end);
+                },
+                Command::Close => unreachable!(),
+            };
+            self.last_cubic = cubic;
+            self.last_quad = quad;
+        };
+        Ok(())
+    }
+
+    fn push(&mut self, pt: Pt) {
+        if self.current.is_empty() {
+            self.current.push(self.pos);
+        };
+        if self.current.last() != Some(&pt) {
+            self.current.push(pt);
+        };
+    }
+
+    fn line_to(&mut self, end: Pt) {
+        self.push(end);
+        self.pos = end;
+    }
+
+    fn cubic_to(&mut self, c1: Pt, c2: Pt, end: Pt) {
+        let mut out = Vec::new();
+        flatten_cubic(self.pos, c1, c2, end, self.tolerance, 0, &mut out);
+        for pt in out {
+            self.push(pt);
+        };
+        self.pos = end;
+    }
+
+    fn quad_to(&mut self, c: Pt, end: Pt) {
+        // a quadratic is a cubic with its control points 2/3 of the way to c
+        let c1 = lerp(self.pos, c, 2.0 / 3.0);
+        let c2 = lerp(end, c, 2.0 / 3.0);
+        self.cubic_to(c1, c2, end);
+    }
+
+    fn arc_to(&mut self, radii: Pt, rotation: f64, large_arc: bool, sweep: bool, end: Pt) {
+        for pt in flatten_arc(self.pos, radii, rotation, large_arc, sweep, end, self.tolerance) {
+            self.push(pt);
+        };
+        self.pos = end;
+    }
+
+    fn close(&mut self) {
+        if !self.current.is_empty() {
+            self.push(self.start);
+        };
+        self.finish_subpath();
+        self.pos = self.start;
+        self.last_cubic = None;
+        self.last_quad = None;
+    }
+
+    fn finish_subpath(&mut self) {
+        let subpath = std::mem::take(&mut self.current);
+        if subpath.len() > 1 {
+            self.subpaths.push(subpath);
+        };
+    }
+}
+
+fn lerp(a: Pt, b: Pt, t: f64) -> Pt {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+/// The reflection of `control` about `pos`, or `pos` itself if there's no control.
+fn reflect(control: Option<Pt>, pos: Pt) -> Pt {
+    match control {
+        Some(c) => (2.0 * pos.0 - c.0, 2.0 * pos.1 - c.1),
+        None => pos,
+    }
+}
+
+/// Distance from `p` to the line through `a` and `b`.
+fn distance_to_line(p: Pt, a: Pt, b: Pt) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len = dx.hypot(dy);
+    if len == 0.0 {
+        return (p.0 - a.0).hypot(p.1 - a.1);
+    };
+    ((p.0 - a.0) * dy - (p.1 - a.1) * dx).abs() / len
+}
+
+/// The value of the cubic Bézier `p0 c1 c2 p3` at `t`.
+pub fn cubic_point(p0: Pt, c1: Pt, c2: Pt, p3: Pt, t: f64) -> Pt {
+    let u = 1.0 - t;
+    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+    (
+        a * p0.0 + b * c1.0 + c * c2.0 + d * p3.0,
+        a * p0.1 + b * c1.1 + c * c2.1 + d * p3.1,
+    )
+}
+
+/// Add points along a cubic to `out`, not including `p0`. The curve is split in
+/// half until both control points are within `tolerance` of the chord.
+fn flatten_cubic(p0: Pt, c1: Pt, c2: Pt, p3: Pt, tolerance: f64, depth: u32, out: &mut Vec<Pt>) {
+    let flat = distance_to_line(c1, p0, p3).max(distance_to_line(c2, p0, p3)) <= tolerance;
+    if flat || depth >= MAX_DEPTH {
+        out.push(p3);
+        return;
+    };
+
+    // de Casteljau
+    let (a, b, c) = (lerp(p0, c1, 0.5), lerp(c1, c2, 0.5), lerp(c2, p3, 0.5));
+    let (d, e) = (lerp(a, b, 0.5), lerp(b, c, 0.5));
+    let mid = lerp(d, e, 0.5);
+    flatten_cubic(p0, a, d, mid, tolerance, depth + 1, out);
+    flatten_cubic(mid, e, c, p3, tolerance, depth + 1, out);
+}
+
+/// Points along an elliptical arc, not including `start`, following the SVG
+/// rules for out-of-range parameters.
+fn flatten_arc(start: Pt, radii: Pt, rotation: f64, large_arc: bool, sweep: bool, end: Pt, tolerance: f64) -> Vec<Pt> {
+    let (mut rx, mut ry) = (radii.0.abs(), radii.1.abs());
+    if start == end {
+        return Vec::new();
+    };
+    if rx == 0.0 || ry == 0.0 {
+        return vec!(end);
+    };
+
+    // https://www.w3.org/TR/SVG/implnote.html#ArcConversionEndpointToCenter
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let (hx, hy) = ((start.0 - end.0) / 2.0, (start.1 - end.1) / 2.0);
+    let (x1, y1) = (cos * hx + sin * hy, -sin * hx + cos * hy);
+
+    let scale = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if scale > 1.0 {
+        rx *= scale.sqrt();
+        ry *= scale.sqrt();
+    };
+
+    let num = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let den = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let mut coef = (num / den).max(0.0).sqrt();
+    if large_arc == sweep {
+        coef = -coef;
+    };
+    let (cx1, cy1) = (coef * rx * y1 / ry, -coef * ry * x1 / rx);
+    let (cx, cy) = (
+        cos * cx1 - sin * cy1 + (start.0 + end.0) / 2.0,
+        sin * cx1 + cos * cy1 + (start.1 + end.1) / 2.0,
+    );
+
+    let angle = |ux: f64, uy: f64| uy.atan2(ux);
+    let theta1 = angle((x1 - cx1) / rx, (y1 - cy1) / ry);
+    let mut delta = angle((-x1 - cx1) / rx, (-y1 - cy1) / ry) - theta1;
+    if sweep && delta < 0.0 {
+        delta += 2.0 * std::f64::consts::PI;
+    } else if !sweep && delta > 0.0 {
+        delta -= 2.0 * std::f64::consts::PI;
+    };
+
+    // the angle a chord can cover while staying within tolerance of the arc
+    let r = rx.max(ry);
+    let step = 2.0 * (1.0 - (tolerance / r).min(1.0)).acos();
+    let n = ((delta.abs() / step.max(1e-3)).ceil() as usize).clamp(1, 1 << MAX_DEPTH);
+
+    let mut points: Vec<Pt> = (1..n).map(|i| {
+        let t = theta1 + delta * i as f64 / n as f64;
+        let (x, y) = (rx * t.cos(), ry * t.sin());
+        (cos * x - sin * y + cx, sin * x + cos * y + cy)
+    }).collect();
+    points.push(end);
+    points
+}
+
+/// An affine transform, as in SVG's `matrix(a b c d e f)`:
+///
+/// ```text
+/// | a c e |
+/// | b d f |
+/// | 0 0 1 |
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 };
+
+    pub fn matrix(a: f64, b: f64, c: f64, d: f64, e: f64, f: f64) -> Self {
+        Transform { a, b, c, d, e, f }
+    }
+
+    pub fn translate(tx: f64, ty: f64) -> Self {
+        Transform::matrix(1.0, 0.0, 0.0, 1.0, tx, ty)
+    }
+
+    pub fn scale(sx: f64, sy: f64) -> Self {
+        Transform::matrix(sx, 0.0, 0.0, sy, 0.0, 0.0)
+    }
+
+    /// Rotate by `angle` degrees, clockwise on screen, about the origin.
+    pub fn rotate(angle: f64) -> Self {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Transform::matrix(cos, sin, -sin, cos, 0.0, 0.0)
+    }
+
+    pub fn skew_x(angle: f64) -> Self {
+        Transform::matrix(1.0, 0.0, angle.to_radians().tan(), 1.0, 0.0, 0.0)
+    }
+
+    pub fn skew_y(angle: f64) -> Self {
+        Transform::matrix(1.0, angle.to_radians().tan(), 0.0, 1.0, 0.0, 0.0)
+    }
+
+    pub fn apply(&self, (x, y): Pt) -> Pt {
+        (self.a * x + self.c * y + self.e, self.b * x + self.d * y + self.f)
+    }
+
+    /// Roughly how much the transform enlarges things: the square root of the area
+    /// it scales by.
+    pub fn scale_factor(&self) -> f64 {
+        (self.a * self.d - self.b * self.c).abs().sqrt()
+    }
+
+    /// The transform from an element's viewBox to the viewport it's drawn into,
+    /// following `preserveAspectRatio`. `view_box` is `(min_x, min_y, width, height)`
+    /// and `viewport` is `(x, y, width, height)`.
+    pub fn view_box(view_box: (f64, f64, f64, f64), viewport: (f64, f64, f64, f64), preserve_aspect_ratio: Option<&str>) -> Result<Self> {
+        let (vx, vy, vw, vh) = view_box;
+        let (x, y, w, h) = viewport;
+        if vw <= 0.0 || vh <= 0.0 {
+            return Err(RMError::ArgsError(format!("bad viewBox: {:?}", view_box)));
+        };
+
+        let mut words = preserve_aspect_ratio.unwrap_or("").split_whitespace()
+                                             .skip_while(|w| *w == "defer");
+        let align = words.next().unwrap_or("xMidYMid");
+        let slice = match words.next() {
+            None | Some("meet") => false,
+            Some("slice") => true,
+            Some(other) => return Err(RMError::ArgsError(format!("bad preserveAspectRatio: {:?}", other))),
+        };
+
+        let (mut sx, mut sy) = (w / vw, h / vh);
+        if align == "none" {
+            return Ok(Transform::translate(x, y) * Transform::scale(sx, sy) * Transform::translate(-vx, -vy));
+        };
+        if align.len() != 8 {
+            return Err(RMError::ArgsError(format!("bad preserveAspectRatio: {:?}", align)));
+        };
+        let uniform = if slice { sx.max(sy) } else { sx.min(sy) };
+        sx = uniform;
+        sy = uniform;
+
+        let fraction = |part: &str| match part {
+            "Min" => Ok(0.0),
+            "Mid" => Ok(0.5),
+            "Max" => Ok(1.0),
+            _ => Err(RMError::ArgsError(format!("bad preserveAspectRatio: {:?}", align))),
+        };
+        let tx = x + (w - vw * sx) * fraction(&align[1..4])?;
+        let ty = y + (h - vh * sy) * fraction(&align[5..8])?;
+        Ok(Transform::translate(tx, ty) * Transform::scale(sx, sy) * Transform::translate(-vx, -vy))
+    }
+}
+
+/// `a * b` applies `b` first, then `a`, as for a parent and child element.
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, o: Transform) -> Transform {
+        Transform {
+            a: self.a * o.a + self.c * o.b,
+            b: self.b * o.a + self.d * o.b,
+            c: self.a * o.c + self.c * o.d,
+            d: self.b * o.c + self.d * o.d,
+            e: self.a * o.e + self.c * o.f + self.e,
+            f: self.b * o.e + self.d * o.f + self.f,
+        }
+    }
+}
+
+/// Parse an SVG `transform` attribute: a list of transform functions, applied
+/// right to left.
+impl FromStr for Transform {
+    type Err = RMError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad = || RMError::ArgsError(format!("bad transform: {:?}", s));
+        let mut transform = Transform::IDENTITY;
+        let mut rest = s.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+
+        while !rest.is_empty() {
+            let open = rest.find('(').ok_or_else(bad)?;
+            let close = rest.find(')').ok_or_else(bad)?;
+            if close < open {
+                return Err(bad());
+            };
+            let name = rest[..open].trim();
+            let args = parse_numbers(&rest[open + 1..close]).ok_or_else(bad)?;
+
+            let next = match (name, args.as_slice()) {
+                ("matrix", [a, b, c, d, e, f]) => Transform::matrix(*a, *b, *c, *d, *e, *f),
+                ("translate", [tx]) => Transform::translate(*tx, 0.0),
+                ("translate", [tx, ty]) => Transform::translate(*tx, *ty),
+                ("scale", [s]) => Transform::scale(*s, *s),
+                ("scale", [sx, sy]) => Transform::scale(*sx, *sy),
+                ("rotate", [angle]) => Transform::rotate(*angle),
+                ("rotate", [angle, cx, cy]) => {
+                    Transform::translate(*cx, *cy) * Transform::rotate(*angle) * Transform::translate(-cx, -cy)
+                },
+                ("skewX", [angle]) => Transform::skew_x(*angle),
+                ("skewY", [angle]) => Transform::skew_y(*angle),
+                _ => return Err(bad()),
+            };
+            transform = transform * next;
+            rest = rest[close + 1..].trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        };
+        Ok(transform)
+    }
+}
+
+/// Parse a list of numbers separated by whitespace and/or commas, as found in
+/// transforms and viewBoxes. A sign or second decimal point also starts a new
+/// number, so `1-2.5.5` is `1 -2.5 .5`.
+pub fn parse_numbers(s: &str) -> Option<Vec<f64>> {
+    let mut numbers = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() || c == ',' {
+            chars.next();
+            continue;
+        };
+        let mut end = start;
+        let mut seen_dot = false;
+        let mut seen_exp = false;
+        let mut prev = None;
+        while let Some(&(i, c)) = chars.peek() {
+            let ok = match c {
+                '0'..='9' => true,
+                '+' | '-' => i == start || matches!(prev, Some('e' | 'E')),
+                '.' => !seen_dot && !seen_exp,
+                'e' | 'E' => !seen_exp && i != start,
+                _ => false,
+            };
+            if !ok {
+                break;
+            };
+            seen_dot |= c == '.';
+            seen_exp |= c == 'e' || c == 'E';
+            prev = Some(c);
+            end = i + c.len_utf8();
+            chars.next();
+        };
+        numbers.push(s[start..end].parse().ok()?);
+    };
+    Some(numbers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn flatten(d: &str) -> Vec<Vec<Pt>> {
+        flatten_path(&Data::parse(d).unwrap(), DEFAULT_TOLERANCE).unwrap()
+    }
+
+    fn close_to(a: Pt, b: Pt) -> bool {
+        (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6
+    }
+
+    #[test]
+    fn lines_and_subpaths() {
+        let paths = flatten("M 10 10 h 10 v 10 H 0 Z m 100 0 l 5 5 5 -5");
+        assert_eq!(paths, vec!(
+            vec!((10.0, 10.0), (20.0, 10.0), (20.0, 20.0), (0.0, 20.0), (10.0, 10.0)),
+            vec!((110.0, 10.0), (115.0, 15.0), (120.0, 10.0)),
+        ));
+
+        // a moveto with more pairs draws lines; a lone moveto draws nothing
+        assert_eq!(flatten("M 0 0 1 1 2 0 M 5 5"), vec!(vec!((0.0, 0.0), (1.0, 1.0), (2.0, 0.0))));
+    }
+
+    #[test]
+    fn curves_stay_within_tolerance() {
+        let (p0, c1, c2, p3) = ((0.0, 0.0), (0.0, 100.0), (100.0, 100.0), (100.0, 0.0));
+        let paths = flatten("M 0 0 C 0 100 100 100 100 0");
+        let line = &paths[0];
+        assert!(close_to(*line.last().unwrap(), p3));
+        // the middle of the curve is on it, as a cubic (not quadratic) should be
+        assert!(line.iter().any(|p| close_to(*p, cubic_point(p0, c1, c2, p3, 0.5))));
+        for t in 0..=20 {
+            let on_curve = cubic_point(p0, c1, c2, p3, t as f64 / 20.0);
+            let nearest = line.windows(2)
+                              .map(|w| segment_distance(on_curve, w[0], w[1]))
+                              .fold(f64::MAX, f64::min);
+            assert!(nearest <= DEFAULT_TOLERANCE * 1.5, "{} away at t={}", nearest, t);
+        };
+
+        // a tighter tolerance means more points
+        let fine = flatten_path(&Data::parse("M 0 0 C 0 100 100 100 100 0").unwrap(), 0.01).unwrap();
+        assert!(fine[0].len() > line.len());
+    }
+
+    #[test]
+    fn smooth_and_quadratic_curves() {
+        // S reflects the last control point, so this is symmetrical about x = 100
+        let paths = flatten("M 0 0 C 0 50 50 50 50 0 S 100 -50 100 0");
+        assert!(close_to(*paths[0].last().unwrap(), (100.0, 0.0)));
+        assert!(paths[0].iter().any(|p| p.1 < -30.0));
+
+        let paths = flatten("M 0 0 q 50 50 100 0 t 100 0");
+        assert!(close_to(*paths[0].last().unwrap(), (200.0, 0.0)));
+        assert!(paths[0].iter().any(|p| (p.1 - 25.0).abs() < 0.5));
+        assert!(paths[0].iter().any(|p| (p.1 + 25.0).abs() < 0.5));
+    }
+
+    #[test]
+    fn arcs() {
+        // half a circle of radius 50 around (50, 0), going through (50, 50)
+        let paths = flatten("M 0 0 A 50 50 0 0 0 100 0");
+        for p in &paths[0] {
+            assert!(((p.0 - 50.0).hypot(p.1) - 50.0).abs() < 1e-6);
+        };
+        assert!(paths[0].iter().any(|p| p.1 > 49.0));
+        assert!(close_to(*paths[0].last().unwrap(), (100.0, 0.0)));
+
+        // radii too small get scaled up; relative arcs end in the right place
+        let paths = flatten("M 10 10 a 1 1 0 1 1 20 0");
+        assert!(close_to(*paths[0].last().unwrap(), (30.0, 10.0)));
+    }
+
+    fn close_transform(a: Transform, b: Transform) -> bool {
+        [a.a - b.a, a.b - b.b, a.c - b.c, a.d - b.d, a.e - b.e, a.f - b.f].iter().all(|d| d.abs() < 1e-9)
+    }
+
+    #[test]
+    fn parse_transforms() {
+        let t: Transform = "translate(10, 20) scale(2)".parse().unwrap();
+        assert_eq!(t.apply((1.0, 1.0)), (12.0, 22.0));
+
+        let t: Transform = "matrix(1 0 0 1 5 -5)".parse().unwrap();
+        assert_eq!(t, Transform::translate(5.0, -5.0));
+
+        let t: Transform = "rotate(90 10 10)".parse().unwrap();
+        assert!(close_to(t.apply((20.0, 10.0)), (10.0, 20.0)));
+
+        let t: Transform = "skewX(45),translate(0 1)".parse().unwrap();
+        assert!(close_to(t.apply((0.0, 0.0)), (1.0, 1.0)));
+        let t: Transform = "skewY(45)".parse().unwrap();
+        assert!(close_to(t.apply((1.0, 0.0)), (1.0, 1.0)));
+
+        assert_eq!("".parse::<Transform>().unwrap(), Transform::IDENTITY);
+        assert!("rotate(1, 2)".parse::<Transform>().is_err());
+        assert!("wobble(3)".parse::<Transform>().is_err());
+        assert_eq!(parse_numbers("1-2.5.5e1,3"), Some(vec!(1.0, -2.5, 5.0, 3.0)));
+    }
+
+    #[test]
+    fn compose_transforms() {
+        let parent = Transform::translate(100.0, 0.0);
+        let child = Transform::scale(2.0, 2.0);
+        assert_eq!((parent * child).apply((1.0, 1.0)), (102.0, 2.0));
+        assert_eq!((child * parent).apply((1.0, 1.0)), (202.0, 2.0));
+        assert!(close_transform(Transform::rotate(30.0) * Transform::rotate(60.0), Transform::rotate(90.0)));
+    }
+
+    #[test]
+    fn view_boxes() {
+        let view_box = (0.0, 0.0, 100.0, 50.0);
+        let viewport = (0.0, 0.0, 400.0, 400.0);
+
+        // meet: scale 4, centred vertically
+        let t = Transform::view_box(view_box, viewport, None).unwrap();
+        assert_eq!(t.apply((0.0, 0.0)), (0.0, 100.0));
+        assert_eq!(t.apply((100.0, 50.0)), (400.0, 300.0));
+
+        let t = Transform::view_box(view_box, viewport, Some("xMinYMax meet")).unwrap();
+        assert_eq!(t.apply((0.0, 0.0)), (0.0, 200.0));
+
+        // slice: scale 8, centred horizontally
+        let t = Transform::view_box(view_box, viewport, Some("xMidYMid slice")).unwrap();
+        assert_eq!(t.apply((0.0, 0.0)), (-200.0, 0.0));
+
+        let t = Transform::view_box((10.0, 10.0, 100.0, 50.0), viewport, Some("none")).unwrap();
+        assert_eq!(t.apply((110.0, 60.0)), (400.0, 400.0));
+
+        assert!(Transform::view_box(view_box, viewport, Some("xMidYMid squash")).is_err());
+    }
+
+    fn segment_distance(p: Pt, a: Pt, b: Pt) -> f64 {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let t = (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+        (p.0 - a.0 - t * dx).hypot(p.1 - a.1 - t * dy)
+    }
+}
@@ -0,0 +1,364 @@
+
+//! The JSON export: a stable, documented view of a page for other tools.
+//!
+//! Unlike the `Debug` output and the types in `rmconvert::types`, this doesn't
+//! follow the file's own layout. It's a page of layers of strokes, plus the typed
+//! text, in page coordinates (origin at the top left, 1404 by 1872). The schema is
+//! in `assets/page.schema.json`; anything that changes it bumps `SCHEMA_VERSION`.
+
+use std::f32::consts::PI;
+
+use serde::{Serialize, Deserialize};
+
+use super::types::*;
+use super::scene::Layer;
+use super::document::Document;
+use super::svg::{pen_color, HEIGHT, WIDTH};
+use super::text::{Paragraph, RichText};
+
+type Result<T> = std::result::Result<T, RMError>;
+
+pub const SCHEMA_NAME: &str = "rmconvert-page";
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A whole document is a list of pages, each in the page schema. Its own schema
+/// is in `assets/document.schema.json`.
+pub const DOCUMENT_SCHEMA_NAME: &str = "rmconvert-document";
+pub const DOCUMENT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentJson {
+    /// Always `rmconvert-document`
+    pub schema: String,
+    pub schema_version: u32,
+    pub id: String,
+    /// The name shown on the tablet
+    pub name: String,
+    /// In the order they're shown
+    pub pages: Vec<DocumentPageJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentPageJson {
+    pub id: String,
+    pub template: Option<String>,
+    pub page: PageJson,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageJson {
+    /// Always `rmconvert-page`
+    pub schema: String,
+    pub schema_version: u32,
+    /// The version of the `.rm` file the page was read from
+    pub format_version: u8,
+    pub width: f32,
+    pub height: f32,
+    pub layers: Vec<LayerJson>,
+    /// Typed text boxes; pages have at most one in practice.
+    pub text: Vec<TextJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerJson {
+    /// The layer's CRDT id, e.g. `0:11`
+    pub id: String,
+    pub name: String,
+    pub visible: bool,
+    pub strokes: Vec<StrokeJson>,
+    pub highlights: Vec<HighlightJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StrokeJson {
+    pub id: String,
+    /// Pen name, as taken by `draw --pen`. Missing on some erased strokes.
+    pub pen: Option<String>,
+    /// Color name, as taken by `draw --color`
+    pub color: Option<String>,
+    /// The color as drawn, `#rrggbb`
+    pub rgb: Option<String>,
+    /// The pen's thickness setting
+    pub thickness: Option<f64>,
+    /// Erased, but still in the file. Only there with `--include-deleted`.
+    pub deleted: bool,
+    pub points: Vec<PointJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PointJson {
+    pub x: f32,
+    pub y: f32,
+    /// Drawn width at this point, in page units
+    pub width: f32,
+    /// 0 to 1
+    pub pressure: f32,
+    /// Pen direction, in radians
+    pub direction: f32,
+    /// Pen speed, in the tablet's own units
+    pub speed: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HighlightJson {
+    pub id: String,
+    pub text: String,
+    pub color: String,
+    pub rectangles: Vec<RectJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RectJson {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextJson {
+    /// Left edge of the text box
+    pub x: f64,
+    /// Top edge of the text box
+    pub y: f64,
+    pub width: f32,
+    pub paragraphs: Vec<ParagraphJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParagraphJson {
+    /// One of `plain`, `heading`, `bold`, `bullet`, `bullet-2`, `checkbox`,
+    /// `checkbox-checked`, `basic`, or a number for styles we don't know
+    pub style: String,
+    /// The paragraph's text without formatting
+    pub text: String,
+    pub spans: Vec<SpanJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpanJson {
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl PageJson {
+    /// The page's layers, or just the one picked out by `only`, and its text.
+    pub fn from_notebook(notebook: &Notebook, only: Option<&str>, include_deleted: bool) -> Result<Self> {
+        let layers = notebook.select_layers(only, include_deleted)?;
+        let text = notebook.blocks.iter().filter_map(|b| match b {
+            Block::TextDef(text) => Some(TextJson::from(text)),
+            _ => None,
+        }).collect();
+
+        Ok(PageJson {
+            schema: SCHEMA_NAME.to_string(),
+            schema_version: SCHEMA_VERSION,
+            format_version: notebook.frontmatter.version,
+            width: WIDTH,
+            height: HEIGHT,
+            layers: layers.iter().map(LayerJson::from).collect(),
+            text,
+        })
+    }
+}
+
+impl DocumentJson {
+    /// `pages` are the document's pages, already converted.
+    pub fn new(document: &Document, pages: Vec<PageJson>) -> Self {
+        DocumentJson {
+            schema: DOCUMENT_SCHEMA_NAME.to_string(),
+            schema_version: DOCUMENT_SCHEMA_VERSION,
+            id: document.id.clone(),
+            name: document.name().to_string(),
+            pages: document.pages.iter().zip(pages).map(|(p, page)| DocumentPageJson {
+                id: p.id.clone(),
+                template: p.template.clone(),
+                page,
+            }).collect(),
+        }
+    }
+}
+
+impl From<&Layer> for LayerJson {
+    fn from(layer: &Layer) -> Self {
+        LayerJson {
+            id: layer.id.to_string(),
+            name: layer.name.clone(),
+            visible: layer.visible,
+            strokes: layer.lines.iter().map(StrokeJson::from).collect(),
+            highlights: layer.highlights.iter().filter_map(HighlightJson::from_glyph).collect(),
+        }
+    }
+}
+
+impl From<&Line> for StrokeJson {
+    fn from(line: &Line) -> Self {
+        StrokeJson {
+            id: line.line_id.to_string(),
+            pen: line.pen_type.map(|p| p.to_string()),
+            color: line.color.map(|c| c.to_string()),
+            rgb: line.color.map(|c| pen_color(c).to_string()),
+            thickness: line.brush_size,
+            deleted: line.is_deleted(),
+            points: line.points.iter().map(PointJson::from).collect(),
+        }
+    }
+}
+
+impl From<&Point> for PointJson {
+    fn from(point: &Point) -> Self {
+        PointJson {
+            x: point.x + HALF_WIDTH,
+            y: point.y,
+            width: point.width as f32 / 4.0,
+            pressure: point.pressure as f32 / u8::MAX as f32,
+            direction: point.direction as f32 * 2.0 * PI / u8::MAX as f32,
+            speed: point.speed,
+        }
+    }
+}
+
+impl HighlightJson {
+    /// Highlights that have been erased have no value, and are left out.
+    fn from_glyph(glyph: &Glyph) -> Option<Self> {
+        let value = glyph.value.as_ref()?;
+        Some(HighlightJson {
+            id: glyph.header.item_id.to_string(),
+            text: value.text.clone(),
+            color: value.color.to_string(),
+            rectangles: value.rectangles.iter().map(|r| RectJson {
+                x: r.x + HALF_WIDTH as f64,
+                y: r.y,
+                width: r.w,
+                height: r.h,
+            }).collect(),
+        })
+    }
+}
+
+impl From<&TextDef> for TextJson {
+    fn from(text: &TextDef) -> Self {
+        TextJson {
+            x: text.pos_x + HALF_WIDTH as f64,
+            y: text.pos_y,
+            width: text.width,
+            paragraphs: RichText::from(text).paragraphs.iter().map(ParagraphJson::from).collect(),
+        }
+    }
+}
+
+impl From<&Paragraph> for ParagraphJson {
+    fn from(paragraph: &Paragraph) -> Self {
+        ParagraphJson {
+            style: paragraph.style.to_string(),
+            text: paragraph.text(),
+            spans: paragraph.spans.iter().map(|s| SpanJson {
+                text: s.text.clone(),
+                bold: s.bold,
+                italic: s.italic,
+            }).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::*;
+
+    const FORMATTED: &str = "assets/test_notebooks/e9453e30-cd4a-4a9b-aef4-41e04c047ff5/5b6148bf-2906-4281-adb5-a428d83de792.rm";
+    const SCHEMA: &str = "assets/page.schema.json";
+    const DOCUMENT_SCHEMA: &str = "assets/document.schema.json";
+
+    fn read_schema(path: &str) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// Every object's keys are exactly its definition's properties, all the way down.
+    fn check(value: &serde_json::Value, definition: &serde_json::Value, schema: &serde_json::Value) {
+        let definition = match definition["$ref"].as_str() {
+            Some("page.schema.json") => return check(value, &read_schema(SCHEMA), &read_schema(SCHEMA)),
+            Some(reference) => &schema["$defs"][reference.trim_start_matches("#/$defs/")],
+            None => definition,
+        };
+        match value {
+            serde_json::Value::Object(map) => {
+                let properties = definition["properties"].as_object().unwrap();
+                let mut keys: Vec<&String> = map.keys().collect();
+                let mut expected: Vec<&String> = properties.keys().collect();
+                keys.sort();
+                expected.sort();
+                assert_eq!(keys, expected);
+                for required in definition["required"].as_array().unwrap() {
+                    assert!(map.contains_key(required.as_str().unwrap()));
+                };
+                for (key, v) in map {
+                    check(v, &properties[key], schema);
+                };
+            },
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    check(item, &definition["items"], schema);
+                };
+            },
+            _ => {},
+        };
+    }
+
+    #[test]
+    fn page_json() {
+        let notebook = parse_full(&std::fs::read(TEST_FILE_01).unwrap()).unwrap();
+        let page = PageJson::from_notebook(&notebook, None, false).unwrap();
+
+        assert_eq!(page.schema_version, SCHEMA_VERSION);
+        assert_eq!(page.layers[0].id, "0:11");
+        let stroke = &page.layers[0].strokes[0];
+        assert_eq!(stroke.pen.as_deref(), Some("ballpoint"));
+        assert_eq!(stroke.rgb.as_deref(), Some("#000000"));
+        assert_eq!(stroke.points[0].width, 3.0);
+        assert!(stroke.points.iter().all(|p| (0.0..=1.0).contains(&p.pressure)));
+
+        let json = serde_json::to_string(&page).unwrap();
+        assert_eq!(serde_json::from_str::<PageJson>(&json).unwrap(), page);
+    }
+
+    #[test]
+    fn text_json() {
+        let notebook = parse_full(&std::fs::read(FORMATTED).unwrap()).unwrap();
+        let page = PageJson::from_notebook(&notebook, None, false).unwrap();
+
+        let paragraphs = &page.text[0].paragraphs;
+        assert_eq!(paragraphs[0].style, "heading");
+        assert_eq!(paragraphs[0].text, "i am big text");
+        assert!(paragraphs.iter().flat_map(|p| &p.spans).any(|s| s.bold && s.italic && s.text == "both"));
+    }
+
+    /// The schema file should describe what we actually write.
+    #[test]
+    fn schema_matches() {
+        let schema = read_schema(SCHEMA);
+        assert_eq!(schema["properties"]["schema_version"]["const"], SCHEMA_VERSION);
+        assert_eq!(schema["properties"]["schema"]["const"], SCHEMA_NAME);
+
+        let notebook = parse_full(&std::fs::read(FORMATTED).unwrap()).unwrap();
+        let page = serde_json::to_value(PageJson::from_notebook(&notebook, None, false).unwrap()).unwrap();
+        check(&page, &schema, &schema);
+    }
+
+    #[test]
+    fn document_schema_matches() {
+        let schema = read_schema(DOCUMENT_SCHEMA);
+        assert_eq!(schema["properties"]["schema_version"]["const"], DOCUMENT_SCHEMA_VERSION);
+        assert_eq!(schema["properties"]["schema"]["const"], DOCUMENT_SCHEMA_NAME);
+
+        let document = Document::open("assets/test_notebooks", "e9453e30-cd4a-4a9b-aef4-41e04c047ff5").unwrap();
+        let pages = document.pages.iter()
+                                  .map(|p| PageJson::from_notebook(&p.read().unwrap(), None, false).unwrap())
+                                  .collect();
+        let json = DocumentJson::new(&document, pages);
+        assert_eq!(json.pages.len(), 3);
+        assert_eq!(json.pages[2].page.text[0].paragraphs[0].text, "i am big text");
+        check(&serde_json::to_value(json).unwrap(), &schema, &schema);
+    }
+}
@@ -0,0 +1,204 @@
+
+//! Parsers for the older fixed-layout lines formats (v3 and v5, firmware 2.x).
+//!
+//! These files have no tagged blocks, just a nested list of
+//! layers -> strokes -> segments, all little-endian:
+//!
+//! ```text
+//! u32 num_layers
+//!   u32 num_strokes
+//!     u32 pen, u32 color, u32 unknown, f32 width, [v5: u32 unknown], u32 num_segments
+//!       f32 x, f32 y, f32 speed, f32 direction, f32 width, f32 pressure
+//! ```
+//!
+//! Everything is converted into the same blocks the v6 parser produces, layers
+//! included, so the exporters don't need to care which version they're looking at.
+
+use std::f32::consts::PI;
+
+use nom::IResult;
+use nom::number::complete as num;
+use nom::multi::length_count;
+
+use super::types::*;
+
+/// The id given to the first legacy layer. v6 files use this id for the default
+/// "Layer 1"; later legacy layers count up from it.
+pub const FIRST_LAYER_ID: u64 = 11;
+
+pub fn parse_legacy(input: &[u8], version: u8) -> IResult<&[u8], Vec<Block>> {
+    let (input, layers) = length_count(num::le_u32, |i| parse_legacy_layer(i, version))(input)?;
+    Ok((input, legacy_blocks(layers)))
+}
+
+/// The blocks a v6 file would have for these layers: each layer declared, named
+/// and placed on the root in order, and its lines chained one after another, so
+/// the scene tree sees them just as it would a v6 page.
+fn legacy_blocks(layers: Vec<Vec<Line>>) -> Vec<Block> {
+    const END: CrdtId = CrdtId::new(0, 0);
+    // the layers' places on the root need ids of their own, after the layers'
+    let first_info = FIRST_LAYER_ID + layers.len() as u64;
+    let mut next_line = 1;
+    let mut blocks = Vec::new();
+
+    for (idx, lines) in layers.into_iter().enumerate() {
+        let layer_id = CrdtId::new(0, FIRST_LAYER_ID + idx as u64);
+        let info_id = CrdtId::new(0, first_info + idx as u64);
+        blocks.push(Block::LayerDef(LayerDef {
+            layer_id,
+            node_id: END,
+            is_update: true,
+            parent_id: ROOT_ID,
+        }));
+        blocks.push(Block::LayerName(LayerName {
+            layer_id,
+            name: LwwValue { timestamp: END, value: format!("Layer {}", idx + 1) },
+            visible: LwwValue { timestamp: END, value: true },
+            unknown_unsized: Vec::new(),
+        }));
+        blocks.push(Block::LayerInfo(LayerInfo {
+            id_field_0: ROOT_ID,
+            id_field_1: info_id,
+            id_field_2: if idx == 0 { END } else { CrdtId::new(0, info_id.counter - 1) },
+            id_field_3: END,
+            deleted_length: 0,
+            layer_id: Some(layer_id),
+        }));
+
+        let mut left = END;
+        for mut line in lines {
+            line.layer_id = layer_id;
+            line.line_id = CrdtId::new(LOCAL_AUTHOR, next_line);
+            line.last_line_id = left;
+            line.id_field_0 = END;
+            left = line.line_id;
+            next_line += 1;
+            blocks.push(Block::Line(line));
+        };
+    };
+    blocks
+}
+
+pub fn parse_legacy_layer(input: &[u8], version: u8) -> IResult<&[u8], Vec<Line>> {
+    length_count(num::le_u32, |i| parse_legacy_stroke(i, version))(input)
+}
+
+pub fn parse_legacy_stroke(input: &[u8], version: u8) -> IResult<&[u8], Line> {
+    let (input, pen_type) = num::le_u32(input)?;
+    let (input, color) = num::le_u32(input)?;
+    let (input, _unknown) = num::le_u32(input)?;
+    let (input, brush_size) = num::le_f32(input)?;
+    let (input, _) = if version >= 5 {
+        num::le_u32(input)?
+    } else {
+        (input, 0)
+    };
+    let (input, points) = length_count(num::le_u32, parse_legacy_point)(input)?;
+
+    Ok((input, Line {
+        pen_type: Some(pen_type.into()),
+        color: Some(color.into()),
+        brush_size: Some(brush_size as f64),
+        points,
+        ..Line::default()
+    }))
+}
+
+/// Legacy points are all floats, and x is measured from the left edge of the page
+/// rather than the middle. Scale everything into the v6 ranges.
+pub fn parse_legacy_point(input: &[u8]) -> IResult<&[u8], Point> {
+    let (input, x) = num::le_f32(input)?;
+    let (input, y) = num::le_f32(input)?;
+    let (input, speed) = num::le_f32(input)?;
+    let (input, direction) = num::le_f32(input)?;
+    let (input, width) = num::le_f32(input)?;
+    let (input, pressure) = num::le_f32(input)?;
+
+    Ok((input, Point {
+        x: x - HALF_WIDTH,
+        y,
+        speed: to_u16(speed * 4.0),
+        width: to_u16(width * 4.0),
+        direction: to_u8(255.0 * direction / (2.0 * PI)),
+        pressure: to_u8(pressure * 255.0),
+    }))
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, u8::MAX as f32) as u8
+}
+
+fn to_u16(value: f32) -> u16 {
+    value.round().clamp(0.0, u16::MAX as f32) as u16
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::parse_full;
+
+    fn stroke(version: u8, pen: u32, points: &[(f32, f32)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(pen.to_le_bytes());
+        out.extend(1u32.to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend(2.0f32.to_le_bytes());
+        if version >= 5 {
+            out.extend(0u32.to_le_bytes());
+        };
+        out.extend((points.len() as u32).to_le_bytes());
+        for (x, y) in points {
+            for v in [*x, *y, 0.5, PI, 2.0, 1.0] {
+                out.extend(v.to_le_bytes());
+            };
+        };
+        out
+    }
+
+    fn legacy_file(version: u8) -> Vec<u8> {
+        let mut out = RawBytes::from(Frontmatter::new(version));
+        out.extend(2u32.to_le_bytes());
+
+        out.extend(1u32.to_le_bytes());
+        out.extend(stroke(version, 2, &[(702.0, 10.0), (712.0, 20.0)]));
+
+        out.extend(1u32.to_le_bytes());
+        out.extend(stroke(version, 17, &[(0.0, 0.0)]));
+        out
+    }
+
+    #[test]
+    fn parse_legacy_versions() {
+        for version in [3, 5] {
+            let notebook = parse_full(&legacy_file(version)).unwrap();
+            assert_eq!(notebook.frontmatter.version, version);
+
+            let lines: Vec<&Line> = notebook.blocks.iter().filter_map(|b| match b {
+                Block::Line(l) => Some(l),
+                _ => None,
+            }).collect();
+            assert_eq!(lines.len(), 2);
+
+            assert_eq!(lines[0].pen_type, Some(PenType::Ballpoint1));
+            assert_eq!(lines[0].color, Some(PenColor::Gray));
+            assert_eq!(lines[0].points.len(), 2);
+            assert_eq!(lines[0].points[0].x, 0.0);
+            assert_eq!(lines[0].points[1].y, 20.0);
+            assert_eq!(lines[0].points[0].pressure, 255);
+            assert_eq!(lines[0].points[0].direction, 128);
+            assert_eq!(lines[0].layer_id, CrdtId::new(0, FIRST_LAYER_ID));
+
+            assert_eq!(lines[1].pen_type, Some(PenType::Fineliner2));
+            assert_eq!(lines[1].points[0].x, -HALF_WIDTH);
+            assert_eq!(lines[1].layer_id, CrdtId::new(0, FIRST_LAYER_ID + 1));
+            assert_ne!(lines[0].line_id, lines[1].line_id);
+
+            // the exporters see the layers as they would a v6 page's
+            let layers = notebook.layers();
+            let names: Vec<&str> = layers.iter().map(|l| l.name.as_str()).collect();
+            assert_eq!(names, ["Layer 1", "Layer 2"]);
+            assert_eq!(layers[0].lines.len(), 1);
+            assert_eq!(layers[1].lines.len(), 1);
+        };
+    }
+}
@@ -1,6 +1,17 @@
 pub mod types;
 pub mod parse;
+pub mod legacy;
+pub mod scene;
+pub mod write;
 pub mod svg;
+pub mod geometry;
+pub mod text;
+pub mod json;
+pub mod pdf;
+pub mod content;
+pub mod metadata;
+pub mod library;
+pub mod document;
 pub mod util;
 pub mod cli;
 pub mod config;
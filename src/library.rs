@@ -0,0 +1,236 @@
+
+//! The tablet's storage directory (`RM_DIR`) as a tree of folders and documents.
+//!
+//! On disk everything is flat: each document or folder is a `.metadata` file named
+//! after its id, and the tree only exists through each item's `parent`. A `Library`
+//! reads all of them, so items can be found by the path shown on the tablet, like
+//! `Work/Meetings/2024-03`, rather than by id.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use super::types::*;
+use super::document::Document;
+use super::metadata::*;
+
+type Result<T> = std::result::Result<T, RMError>;
+
+#[derive(Debug, Clone)]
+pub struct Library {
+    pub root: PathBuf,
+    /// Every document and folder, by id, including those in the trash
+    pub items: BTreeMap<String, DocumentMetadata>,
+    /// Ids of the items in each folder, by the folder's id, sorted by name. The top
+    /// level is `ROOT_PARENT`.
+    children: HashMap<String, Vec<String>>,
+}
+
+impl Library {
+    /// Read every `.metadata` file in `root`. Files that can't be read are skipped
+    /// with a warning, rather than making the whole library unusable.
+    pub fn scan<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let mut items = BTreeMap::new();
+
+        for entry in std::fs::read_dir(&root).map_err(RMError::IoError)? {
+            let path = entry.map_err(RMError::IoError)?.path();
+            if path.extension().is_none_or(|ext| ext != "metadata") {
+                continue;
+            };
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            match DocumentMetadata::from_file(&path) {
+                Ok(metadata) => {
+                    items.insert(id.to_string(), metadata);
+                },
+                Err(error) => warn!("Skipping {}: {}", path.display(), error),
+            };
+        };
+
+        Ok(Library::from_items(root, items))
+    }
+
+    pub fn from_items(root: PathBuf, items: BTreeMap<String, DocumentMetadata>) -> Self {
+        let mut children: HashMap<String, Vec<String>> = HashMap::new();
+        for (id, metadata) in &items {
+            if !metadata.is_trashed() {
+                children.entry(metadata.parent.clone()).or_default().push(id.clone());
+            };
+        };
+        for ids in children.values_mut() {
+            ids.sort_by(|a, b| items[a].visible_name.cmp(&items[b].visible_name).then_with(|| a.cmp(b)));
+        };
+        Library { root, items, children }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&DocumentMetadata> {
+        self.items.get(id)
+    }
+
+    /// Ids of the items in a folder, sorted by name. Use `ROOT_PARENT` for the top level.
+    pub fn children(&self, folder: &str) -> &[String] {
+        self.children.get(folder).map_or(&[], |ids| ids.as_slice())
+    }
+
+    /// The path shown on the tablet, e.g. `Work/Meetings/2024-03`. `None` for items
+    /// in the trash, or whose folders are missing.
+    pub fn path_of(&self, id: &str) -> Option<String> {
+        let mut names = Vec::new();
+        let mut current = id;
+        while current != ROOT_PARENT {
+            let metadata = self.items.get(current)?;
+            // a loop of parents would never reach the top
+            if metadata.is_trashed() || names.len() > self.items.len() {
+                return None;
+            };
+            names.push(metadata.visible_name.as_str());
+            current = &metadata.parent;
+        };
+        names.reverse();
+        Some(names.join("/"))
+    }
+
+    /// Find an item by its id, or by its path. Leading and trailing slashes are ignored.
+    pub fn find(&self, path_or_id: &str) -> Result<&str> {
+        if let Some((id, _)) = self.items.get_key_value(path_or_id) {
+            return Ok(id);
+        };
+
+        let mut folder = ROOT_PARENT;
+        for name in path_or_id.split('/').filter(|n| !n.is_empty()) {
+            let matches: Vec<&String> = self.children(folder).iter()
+                                            .filter(|id| self.items[*id].visible_name == name)
+                                            .collect();
+            folder = match matches[..] {
+                [id] => id.as_str(),
+                [] => return Err(RMError::ArgsError(format!("nothing named {:?} in {:?}", name, path_or_id))),
+                _ => return Err(RMError::ArgsError(format!(
+                    "{:?} is ambiguous; use one of the ids {:?}", path_or_id, matches))),
+            };
+        };
+        match folder {
+            ROOT_PARENT => Err(RMError::ArgsError(format!("{:?} isn't a document", path_or_id))),
+            id => Ok(id),
+        }
+    }
+
+    /// Find a document, as with `find`, but not a folder.
+    pub fn find_document(&self, path_or_id: &str) -> Result<&str> {
+        let id = self.find(path_or_id)?;
+        match self.items[id].is_folder() {
+            true => Err(RMError::ArgsError(format!("{:?} is a folder, not a document", path_or_id))),
+            false => Ok(id),
+        }
+    }
+
+    /// The document changed most recently, leaving out the trash and the folders in it.
+    pub fn last_modified(&self) -> Option<&str> {
+        self.items.iter()
+                  .filter(|(id, m)| !m.is_folder() && self.path_of(id).is_some())
+                  .max_by_key(|(_, m)| m.last_modified.as_ref().and_then(Millis::time))
+                  .map(|(id, _)| id.as_str())
+    }
+
+    /// Path of one of an item's files, e.g. `file(id, "content")`.
+    pub fn file(&self, id: &str, extension: &str) -> PathBuf {
+        self.root.join(format!("{}.{}", id, extension))
+    }
+
+    pub fn open(&self, id: &str) -> Result<Document> {
+        Document::open(&self.root, id)
+    }
+
+    /// The `.rm` file of one of a document's pages, counting from 0. Without a page,
+    /// the page the document was last opened at.
+    pub fn page_file(&self, id: &str, page: Option<usize>) -> Result<PathBuf> {
+        let document = self.open(id)?;
+        let page = match page {
+            Some(n) => document.page(n + 1)?,
+            None => document.last_opened_page()
+                            .ok_or(RMError::ArgsError(format!("{:?} has no pages", document.name())))?,
+        };
+        Ok(page.file.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RM_DIR: &str = "assets/test_notebooks";
+
+    fn metadata(name: &str, parent: &str, item_type: ItemType) -> DocumentMetadata {
+        serde_json::from_value(serde_json::json!({
+            "visibleName": name,
+            "parent": parent,
+            "type": item_type,
+        })).unwrap()
+    }
+
+    fn library() -> Library {
+        let items = [
+            ("work", metadata("Work", "", ItemType::Collection)),
+            ("meetings", metadata("Meetings", "work", ItemType::Collection)),
+            ("march", metadata("2024-03", "meetings", ItemType::Document)),
+            ("april", metadata("2024-04", "meetings", ItemType::Document)),
+            ("old", metadata("2024-03", "trash", ItemType::Document)),
+            ("dup1", metadata("Notes", "", ItemType::Document)),
+            ("dup2", metadata("Notes", "", ItemType::Document)),
+        ];
+        Library::from_items(PathBuf::new(), items.into_iter().map(|(id, m)| (id.to_string(), m)).collect())
+    }
+
+    #[test]
+    fn folder_tree() {
+        let library = library();
+        assert_eq!(library.children(ROOT_PARENT), ["dup1", "dup2", "work"]);
+        assert_eq!(library.children("meetings"), ["march", "april"]);
+        assert_eq!(library.path_of("march").as_deref(), Some("Work/Meetings/2024-03"));
+        assert_eq!(library.path_of("old"), None);
+
+        assert_eq!(library.find("Work/Meetings/2024-03").unwrap(), "march");
+        assert_eq!(library.find("/Work/Meetings/").unwrap(), "meetings");
+        assert_eq!(library.find("april").unwrap(), "april");
+        assert!(library.find("Work/2024-03").is_err());
+        assert!(library.find("Notes").is_err());
+        assert!(library.find("/").is_err());
+        assert!(library.find_document("Work").is_err());
+    }
+
+    #[test]
+    fn last_modified() {
+        let mut library = library();
+        let mut touch = |id: &str, millis: &str| {
+            let mut metadata = library.items[id].clone();
+            metadata.last_modified = serde_json::from_value(millis.into()).unwrap();
+            library.items.insert(id.to_string(), metadata);
+        };
+        touch("march", "1000");
+        touch("april", "2000");
+        touch("old", "3000");
+        touch("dup1", "500");
+        assert_eq!(library.last_modified(), Some("april"));
+
+        // a folder in the trash takes everything in it along
+        let mut meetings = library.items["meetings"].clone();
+        meetings.parent = TRASH_PARENT.to_string();
+        library.items.insert("meetings".to_string(), meetings);
+        assert_eq!(library.last_modified(), Some("dup1"));
+    }
+
+    #[test]
+    fn scan_dir() {
+        let library = Library::scan(RM_DIR).unwrap();
+        assert_eq!(library.find_document("tester2").unwrap(), "213001cb-42c0-4628-8ed0-8320c15da2a8");
+        assert_eq!(library.last_modified(), Some("e9453e30-cd4a-4a9b-aef4-41e04c047ff5"));
+
+        let last = library.page_file("e9453e30-cd4a-4a9b-aef4-41e04c047ff5", None).unwrap();
+        assert!(last.ends_with("e9453e30-cd4a-4a9b-aef4-41e04c047ff5/5b6148bf-2906-4281-adb5-a428d83de792.rm"));
+        let first = library.page_file("213001cb-42c0-4628-8ed0-8320c15da2a8", Some(0)).unwrap();
+        assert!(first.ends_with("24f01482-b319-4c5b-b1d8-804ce5a25d0c.rm"));
+        assert!(library.page_file("213001cb-42c0-4628-8ed0-8320c15da2a8", Some(21)).is_err());
+    }
+}
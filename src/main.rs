@@ -9,7 +9,14 @@ use anyhow::Result;
 
 use rmconvert::types::*;
 use rmconvert::parse::*;
+use rmconvert::write::*;
 use rmconvert::svg::*;
+use rmconvert::geometry::Transform;
+use rmconvert::text::RichText;
+use rmconvert::json::{PageJson, DocumentJson};
+use rmconvert::document::{Document, Page};
+use rmconvert::pdf::{pdf_from_pages, annotate_pdf, AnnotatedPage, PageFit, PdfPage};
+use rmconvert::library::Library;
 use rmconvert::util::*;
 use rmconvert::cli::*;
 use rmconvert::config::*;
@@ -21,170 +28,275 @@ use clio::Input;
 
 use log::warn;
 
-fn extract_from_blocks(ExtractArgs {skip_text, skip_lines, .. }: ExtractArgs, notebook: Notebook) -> (Option<Vec<Path>>, Option<Vec<String>>) {
-
-    (None, None)
-}
-
-/// Convert Blocks into SVG paths, and return the Vec<Path>.
-///
-/// Path creation is very simplistic, and not all Block types are supported.
-fn blocks_to_svg_paths(notebook: Notebook) -> Vec<Path> {
-    let mut paths = Vec::new();
-
-    let blocks = notebook.blocks;
-
-    for block in &blocks {
-        if let Block::Line(line) = block {
-            let path = path_from_line(&line);
-            match path {
-                None => {},
-                Some(p) => {
-                    paths.push(p);
-                },
-            };
-        };
-    };
-
-    warn!("SVG path extraction ignores text");
-    paths
-}
-
 fn file_to_blocks<R: Read>(mut rmpath: R) -> Result<Notebook> {
     let mut bytes: Vec<u8> = Vec::new();
     rmpath.read_to_end(&mut bytes)?;
     parse_full(&bytes)
 }
 
-fn write_blocks_to_rm_file(notebook: Notebook, file: PathBuf) -> Result<PathBuf> {
-    todo!()
+/// The parts of `extract`'s arguments that change what gets rendered.
+#[derive(Debug, Clone, Default)]
+struct RenderOptions {
+    layer: Option<String>,
+    include_deleted: bool,
+    skip_text: bool,
+    skip_lines: bool,
+    border: bool,
+    pretty: bool,
+    annotate: bool,
+    /// From the document's text settings, when there's a document
+    text_layout: TextLayout,
 }
 
-fn write_blocks<W: Write>(notebook: Notebook, writer: W) -> Result<()> {
-    todo!()
+impl From<&ExtractArgs> for RenderOptions {
+    fn from(args: &ExtractArgs) -> Self {
+        RenderOptions {
+            layer: args.layer.clone(),
+            include_deleted: args.include_deleted,
+            skip_text: args.skip_text,
+            skip_lines: args.skip_lines,
+            border: args.border,
+            pretty: args.pretty,
+            annotate: args.annotate,
+            text_layout: TextLayout::default(),
+        }
+    }
 }
 
-/// Render the Notebook as a String, based on the required output format. 
+/// Render the Notebook in the required output format, ready to be written out.
 ///
-/// All output formats are returned as Strings, until this abstraction proves problematic.
-fn render(notebook: Notebook, format: OutputFormat, settings: Settings) -> Result<String> {
+/// Everything is bytes rather than a String, since `Bytes` output is a binary `.rm` file.
+fn render(notebook: &Notebook, format: OutputFormat, options: &RenderOptions, settings: &Settings) -> Result<Vec<u8>> {
+    if options.annotate {
+        Err(RMError::ArgsError("--annotate draws a whole document; leave out --page".to_string()))?
+    };
     match format {
         OutputFormat::Markdown => {
-            render_markdown(notebook, settings.output.markdown)
+            render_markdown(notebook, options, &settings.output.markdown)
         },
         OutputFormat::JSON => {
-            render_json(notebook, settings.output.json)
+            render_json(notebook, options, &settings.output.json)
         },
         OutputFormat::SVG => {
-            render_svg(notebook, settings.output.svg)
+            render_svg(notebook, options, &settings.output.svg)
+        },
+        OutputFormat::PDF => {
+            render_pdf("", vec![pdf_page(notebook, options)?], &settings.output.pdf)
         },
         OutputFormat::Debug => {
-            render_debug(notebook, settings.output.debug)
+            render_debug(notebook, options, &settings.output.debug)
         },
         OutputFormat::Bytes => {
-            render_bytes(notebook, settings.output.bytes)
+            render_bytes(notebook, options, &settings.output.bytes)
         },
     }
 }
 
-fn render_markdown(notebook: Notebook, cfg: MarkdownCfg) -> Result<String> {
-    let mut strings = Vec::new();
-
-    let blocks = notebook.blocks;
+fn render_markdown(notebook: &Notebook, options: &RenderOptions, cfg: &MarkdownCfg) -> Result<Vec<u8>> {
+    if options.skip_text {
+        return Ok(Vec::new());
+    };
 
-    for block in blocks {
+    let mut strings = Vec::new();
+    for block in &notebook.blocks {
         if let Block::TextDef(tdef) = block {
-            for chunk in &tdef.texts {
-                strings.push(chunk.text.clone());
-            };
+            strings.push(RichText::from(tdef).to_markdown());
         };
     };
 
-    warn!("Text rendering ignores text formatting");
-    Ok(strings.join("\n"))
+    Ok(strings.join("\n\n").into_bytes())
+}
+
+/// The page in the schema from `assets/page.schema.json`.
+fn render_json(notebook: &Notebook, options: &RenderOptions, cfg: &JsonCfg) -> Result<Vec<u8>> {
+    to_json(&page_json(notebook, options)?, options)
 }
 
-fn render_json(notebook: Notebook, cfg: JsonCfg) -> Result<String> {
-    let json = serde_json::to_string(&notebook.blocks)?;
-    Ok(json)
+fn page_json(notebook: &Notebook, options: &RenderOptions) -> Result<PageJson> {
+    let mut page = PageJson::from_notebook(notebook, options.layer.as_deref(), options.include_deleted)?;
+    if options.skip_lines {
+        page.layers.iter_mut().for_each(|l| l.strokes.clear());
+    };
+    if options.skip_text || options.layer.is_some() {
+        page.text.clear();
+    };
+    Ok(page)
 }
 
-fn render_svg(notebook: Notebook, cfg: SvgCfg) -> Result<String> {
-    todo!()
+fn to_json<T: serde::Serialize>(value: &T, options: &RenderOptions) -> Result<Vec<u8>> {
+    let json = if options.pretty {
+        serde_json::to_string_pretty(value)?
+    } else {
+        serde_json::to_string(value)?
+    };
+    Ok(json.into_bytes())
 }
 
-fn render_debug(notebook: Notebook, cfg: DebugCfg) -> Result<String> {
-    Ok(format!("{:?}", notebook.blocks))
+fn render_svg(notebook: &Notebook, options: &RenderOptions, cfg: &SvgCfg) -> Result<Vec<u8>> {
+    Ok(svg_to_string(svg_groups(notebook, options)?).into_bytes())
 }
 
-fn render_bytes(notebook: Notebook, cfg: BytesCfg) -> Result<String> {
-    todo!()
+fn svg_groups(notebook: &Notebook, options: &RenderOptions) -> Result<Vec<svg::node::element::Group>> {
+    let mut groups = groups_from_notebook(notebook, options.layer.as_deref(), options.include_deleted)?;
+    if options.skip_lines {
+        groups.clear();
+    };
+    // typed text belongs to the page rather than a layer
+    if !options.skip_text && options.layer.is_none() {
+        groups.extend(text_groups_from_notebook(notebook, &options.text_layout));
+    };
+    if options.border {
+        groups.push(svg::node::element::Group::new().set("class", "border").add(create_border_path()));
+    };
+    Ok(groups)
 }
 
+fn render_pdf(title: &str, pages: Vec<PdfPage>, cfg: &PdfCfg) -> Result<Vec<u8>> {
+    Ok(pdf_from_pages(title, &pages)?)
+}
 
-// TODO: use stdin? Does it even make sense here?
-// TODO: make the panics reprint the --help text
-fn do_extract(eargs: ExtractArgs, rmdir: Option<PathBuf>) -> Result<Notebook> {
+fn pdf_page(notebook: &Notebook, options: &RenderOptions) -> Result<PdfPage> {
+    let mut page = PdfPage { border: options.border, text_layout: options.text_layout.clone(), ..PdfPage::default() };
+    if !options.skip_lines {
+        page.layers = notebook.select_layers(options.layer.as_deref(), options.include_deleted)?;
+    };
+    // typed text belongs to the page rather than a layer
+    if !options.skip_text && options.layer.is_none() {
+        page.text = notebook.blocks.iter().filter_map(|b| match b {
+            Block::TextDef(text) => Some(text.clone()),
+            _ => None,
+        }).collect();
+    };
+    Ok(page)
+}
 
-    let ExtractArgs {input, output, last, skip_lines, skip_text, border} = eargs;
+fn render_debug(notebook: &Notebook, options: &RenderOptions, cfg: &DebugCfg) -> Result<Vec<u8>> {
+    Ok(format!("{:?}", notebook.blocks).into_bytes())
+}
 
-    let notebook = match (input, last) {
+/// The page as a `.rm` file again, as it would be written by `draw`.
+fn render_bytes(notebook: &Notebook, options: &RenderOptions, cfg: &BytesCfg) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    write_blocks(notebook.clone(), &mut bytes)?;
+    Ok(bytes)
+}
 
-        // no input or last-modified flag; panic
-        (None, false) => {
-            panic!("no input file, dunno what to do");
+/// Render every page of a document into one output: Markdown pages separated by
+/// rules, a JSON document of pages, an SVG with the pages one below the other, or
+/// a PDF with a page for each.
+fn render_document(document: &Document, format: OutputFormat, options: &RenderOptions, settings: &Settings) -> Result<Vec<u8>> {
+    if options.annotate && format != OutputFormat::PDF {
+        Err(RMError::ArgsError("--annotate draws onto a PDF; use --format pdf".to_string()))?
+    };
+    let mut pages = document.pages.iter().map(Page::read).collect::<Result<Vec<Notebook>>>()?;
+
+    // with --layer, pages without that layer (blank ones, say) come out empty;
+    // it's only wrong if no page has it
+    let empty = RenderOptions { layer: None, ..options.clone() };
+    let page_options: Vec<&RenderOptions> = match &options.layer {
+        Some(name) => {
+            if !pages.iter().any(|p| p.find_layer(name).is_some()) {
+                Err(RMError::ArgsError(format!("no layer named {:?} in {:?}", name, document.name())))?
+            };
+            pages.iter_mut().map(|page| match page.find_layer(name) {
+                Some(_) => options,
+                None => {
+                    page.blocks.clear();
+                    &empty
+                },
+            }).collect()
         },
-        // use last modified rM file
-        (None, true) => {
-            if let Some(dir) = rmdir {
-                let lastf = last_modified_page(&dir).unwrap();
-                let cliopath = Input::new(&lastf).unwrap();
-                file_to_blocks(cliopath)?
-            } else {
-                panic!("no rmdir to use!");
+        None => vec![options; pages.len()],
+    };
+    let pages = pages.iter().zip(page_options);
+
+    match format {
+        OutputFormat::Markdown => {
+            let mut texts = Vec::new();
+            for (page, page_options) in pages {
+                let text = render_markdown(page, page_options, &settings.output.markdown)?;
+                if !text.is_empty() {
+                    texts.push(text);
+                };
+            };
+            Ok(texts.join(&b"\n\n---\n\n"[..]))
+        },
+        OutputFormat::JSON => {
+            let json = pages.map(|(p, page_options)| page_json(p, page_options)).collect::<Result<Vec<PageJson>>>()?;
+            to_json(&DocumentJson::new(document, json), options)
+        },
+        OutputFormat::SVG => {
+            let pages = pages.map(|(p, page_options)| svg_groups(p, page_options)).collect::<Result<Vec<_>>>()?;
+            Ok(pages_svg_to_string(pages).into_bytes())
+        },
+        OutputFormat::PDF => {
+            let pages = pages.map(|(p, page_options)| pdf_page(p, page_options)).collect::<Result<Vec<_>>>()?;
+            match options.annotate {
+                true => annotate_document(document, pages),
+                false => render_pdf(document.name(), pages, &settings.output.pdf),
             }
         },
-        // use input, ignore last flag with msg
-        (Some(inp), true) => {
-            eprintln!("Both --input and --last were given; ignoring --last...");
-            file_to_blocks(inp)?
+        OutputFormat::Debug => {
+            let pages = pages.map(|(p, page_options)| render_debug(p, page_options, &settings.output.debug)).collect::<Result<Vec<_>>>()?;
+            Ok(pages.join(&b'\n'))
         },
-        // use input
-        (Some(inp), false) => {
-            file_to_blocks(inp)?
+        OutputFormat::Bytes => {
+            Err(RMError::ArgsError("bytes output is a single page; pick one with --page".to_string()))?
         },
-    };
-
-    let Notebook{frontmatter, blocks} = notebook.clone();
-
-
-    if !skip_lines {
-        let svg_paths = blocks_to_svg_paths(notebook.clone());
-
-        if let Some(out) = output.clone() {
-            //svg_paths.push(create_border_path());
-            write_svg(svg_paths, out.to_string()).unwrap();
-        } else {
-            write_svg_to_stdout(svg_paths).unwrap();
-        };
-    };
+    }
+}
 
-    if !skip_text {
-        let text = render_markdown(notebook.clone(), MarkdownCfg{});
+/// The document's original PDF with `pages` drawn over the pages they annotate.
+fn annotate_document(document: &Document, pages: Vec<PdfPage>) -> Result<Vec<u8>> {
+    let original = document.pdf_file().ok_or_else(|| {
+        RMError::ArgsError(format!("{:?} has no original PDF to annotate", document.name()))
+    })?;
+    let pages: Vec<AnnotatedPage> = document.pages.iter().zip(pages).map(|(p, page)| AnnotatedPage {
+        original_page: p.original_page,
+        page,
+    }).collect();
+    Ok(annotate_pdf(&std::fs::read(original)?, &pages, PageFit::from_content(&document.content))?)
+}
 
-        if let Some(_out) = output.clone() {
-            todo!("Can't write text to files yet");
-        } else {
-            warn!("assuming ASCII...");
+/// Open a document in `rmdir`, found by its path on the tablet or its id.
+fn open_document(rmdir: Option<&PathBuf>, document: &str) -> Result<Document> {
+    let dir = rmdir.ok_or(RMError::ArgsError("--document needs an RM_DIR".to_string()))?;
+    let library = Library::scan(dir)?;
+    let id = library.find_document(document)?;
+    Ok(library.open(id)?)
+}
 
-            for line in &text {
-                let bytes = line.clone().into_bytes().into_boxed_slice();
-                std::io::stdout().write_all(&bytes).unwrap();
-            };
-        };
+/// The `.rm` file of a page of `document` to draw into, counting from 1, or of the
+/// page it was last opened at. A page that has never been written on gets a blank one.
+fn draw_page_file(document: &Document, page: Option<u32>) -> Result<PathBuf> {
+    let page = match page {
+        Some(n) => document.page(n as usize)?,
+        None => document.last_opened_page()
+                        .ok_or(RMError::ArgsError(format!("{:?} has no pages", document.name())))?,
     };
+    document.start_page(page)?;
+    Ok(page.file.clone())
+}
 
-    Ok(Notebook{frontmatter, blocks})
+/// Read the page to extract from: the `--input` file, or the last one modified in
+/// `rmdir` with `--last`.
+fn read_extract_input(eargs: ExtractArgs, rmdir: Option<PathBuf>) -> Result<Notebook> {
+    match (eargs.input, eargs.last) {
+        (None, false) => {
+            Err(RMError::ArgsError("no file to extract from".to_string()))?
+        },
+        (None, true) => {
+            let dir = rmdir.ok_or(RMError::ArgsError("--last needs an RM_DIR".to_string()))?;
+            file_to_blocks(std::fs::File::open(last_modified_page(&dir)?)?)
+        },
+        (Some(inp), true) => {
+            eprintln!("Both --input and --last were given; ignoring --last...");
+            file_to_blocks(inp)
+        },
+        (Some(inp), false) => {
+            file_to_blocks(inp)
+        },
+    }
 }
 
 #[allow(unused_variables, unused_mut)]
@@ -192,41 +304,45 @@ fn file_to_stats<R: Read>(mut rmpath: R) -> Result<()> {
     
     let Notebook{frontmatter: fm, blocks} = file_to_blocks(rmpath)?;
 
-    let mut num_blocks = blocks.len();
+    let mut num_blocks = 0;
     let mut num_lines = 0;
     let mut num_points = 0;
     let mut num_layer_defs = 0;
     let mut num_layer_names = 0;
     let mut num_layer_infos = 0;
+    let mut num_glyphs = 0;
     let mut num_text = 0;
     let mut num_text_chunks = 0;
     let mut num_text_backs = 0;
     let mut all_text: Vec<TextDef> = Vec::new();
 
     for block in &blocks {
-        num_blocks = num_blocks + 1;
+        num_blocks += 1;
 
         match block {
             Block::Line(line) => {
-                num_lines = num_lines + 1;
-                num_points = num_points + line.points.len();
+                num_lines += 1;
+                num_points += line.points.len();
             },
             Block::LayerDef(layer) => {
-                num_layer_defs = num_layer_defs + 1;
+                num_layer_defs += 1;
             },
             Block::TextDef(text) => {
-                num_text = num_text + 1;
-                num_text_chunks = num_text_chunks + text.texts.len();
-                num_text_backs = num_text_backs + text.backmatter.len();
+                num_text += 1;
+                num_text_chunks += text.texts.len();
+                num_text_backs += text.backmatter.len();
                 all_text.push(text.clone());
             },
             Block::LayerName(name) => {
-                num_layer_names = num_layer_names + 1;
+                num_layer_names += 1;
             },
             Block::LayerInfo(info) => {
-                num_layer_infos = num_layer_infos + 1;
+                num_layer_infos += 1;
             },
-            Block::Unknown(flag, raw) => {
+            Block::Glyph(glyph) => {
+                num_glyphs += 1;
+            },
+            _ => {
             },
         };
     };
@@ -239,9 +355,10 @@ fn file_to_stats<R: Read>(mut rmpath: R) -> Result<()> {
     println!("Total Layer Defs: {}", num_layer_defs);
     println!("Total Layer Names: {}", num_layer_names);
     println!("Total Layer Infos: {}", num_layer_infos);
+    println!("Total Highlights: {}", num_glyphs);
     println!("Total Text Objects: {}", num_text);
     println!("Total Text Chunks: {}", num_text_chunks);
-    println!("Total Text Backmatter: {}", num_text_chunks);
+    println!("Total Text Backmatter: {}", num_text_backs);
     println!("The Texts: {:?}", all_text);
 
     Ok(())
@@ -249,7 +366,8 @@ fn file_to_stats<R: Read>(mut rmpath: R) -> Result<()> {
 
 fn main() -> Result<()> {
 
-    env_logger::init();
+    // warnings say when output isn't quite what the page holds, so show them by default
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
 
     let cli = Cli::parse();
 
@@ -257,44 +375,85 @@ fn main() -> Result<()> {
 
     match cli.command {
         Commands::Extract(e_args) => {
-            let notebook = do_extract(e_args.clone(), cli.rm_path)?;
-            //let out_str = render(notebook, e_args.format, settings);
+            let format = e_args.format.unwrap_or(settings.output.default);
+            let mut options = RenderOptions::from(&e_args);
+            let output = e_args.output.clone();
+
+            // a document without a page is extracted whole
+            let rendered = match (&e_args.document, e_args.page) {
+                (Some(path), page) => {
+                    let document = open_document(cli.rm_path.as_ref(), path)?;
+                    options.text_layout = TextLayout::from_content(&document.content);
+                    match page {
+                        Some(page) => render(&document.page(page as usize)?.read()?, format, &options, &settings)?,
+                        None => render_document(&document, format, &options, &settings)?,
+                    }
+                },
+                _ => {
+                    let notebook = read_extract_input(e_args, cli.rm_path)?;
+                    render(&notebook, format, &options, &settings)?
+                },
+            };
+
+            let mut out = output.unwrap_or_else(clio::Output::std);
+            out.write_all(&rendered)?;
+            out.finish()?;
         },
         Commands::Draw(d_args) => {
 
-            let e_args = ExtractArgs {
-                input: match d_args.target.output {
-                    None => None,
-                    Some(p) => Some(Input::try_from(p.path().clone())?),
+            let target = match (d_args.target.output, d_args.target.document, d_args.target.last) {
+                (Some(path), _, _) => path,
+                (None, Some(document), _) => {
+                    draw_page_file(&open_document(cli.rm_path.as_ref(), &document)?, d_args.page)?
+                },
+                (None, None, true) => {
+                    let dir = cli.rm_path.ok_or(RMError::ArgsError("--last needs an RM_DIR".to_string()))?;
+                    let library = Library::scan(&dir)?;
+                    let id = library.last_modified()
+                                    .ok_or(RMError::ArgsError(format!("no documents in {}", dir.display())))?;
+                    draw_page_file(&library.open(id)?, None)?
                 },
-                output: None,
-                last: d_args.target.last,
-                skip_text: false,
-                skip_lines: false,
-                border: false,
+                (None, None, false) => {
+                    Err(RMError::ArgsError("no file to draw into".to_string()))?
+                },
+            };
+            let mut notebook = file_to_blocks(std::fs::File::open(&target)?)?;
+
+            let layer_id = match &d_args.layer {
+                Some(name) => notebook.find_layer(name)
+                                      .ok_or(RMError::ArgsError(format!("no layer named {:?}", name)))?,
+                None => notebook.layers().first().map_or(Line::default().layer_id, |l| l.id),
+            };
+
+            let place = |lines: &mut Vec<Line>| {
+                for line in lines.iter_mut() {
+                    line.layer_id = layer_id;
+                    line.restyle(d_args.pen, d_args.color, d_args.width);
+                };
             };
-            let mut notebook = do_extract(e_args, cli.rm_path)?;
 
             // file/stdin, then path. text is separate but comes last
             if let Some(mut svg) = d_args.input.svg {
                 // load svg file into Blocks and add to notebook
                 let mut raw = String::new();
                 svg.lock().read_to_string(&mut raw)?;
-                let lines = read_svg_buffer(&raw[..])?;
-                let mut blocks = lines.into_iter().map(|l| Block::Line(l)).collect();
-                notebook.blocks.append(&mut blocks);
+                let options = ImportOptions { style_from_svg: d_args.style_from_svg };
+                let mut lines = read_svg_buffer_with(&raw[..], &options)?;
+                place(&mut lines);
+                notebook.push_lines(lines);
             };
             if let Some(path) = d_args.input.path {
-                // stick in a Data and do a ::from, then add to notebook
-                Err(RMError::NotImplementedError)?
+                let data = svg::node::element::path::Data::parse(&path)?;
+                let mut lines: Vec<Line> = lines_from_data(&data, &Transform::IDENTITY)?.into_iter().map(Line::from).collect();
+                place(&mut lines);
+                notebook.push_lines(lines);
             };
             if let Some(text) = d_args.input.text {
-                // ???
-                Err(RMError::NotImplementedError)?
+                notebook.push_text(&text);
             };
 
             //write notebook back to file
-            write_blocks_to_rm_file(notebook, PathBuf::from_str("/home/barry/whatever.rm")?)?;
+            write_blocks_to_rm_file(notebook, &target)?;
 
 
         },
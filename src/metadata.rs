@@ -0,0 +1,147 @@
+
+//! The `.metadata` file: a document's or folder's name, place in the folder tree,
+//! and when it was last changed.
+//!
+//! Every document and folder in the tablet's storage directory has one, named
+//! after its id. Times are written as strings of milliseconds since the epoch.
+//! Fields this model doesn't know are kept in `other`.
+
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+use super::types::*;
+
+type Result<T> = std::result::Result<T, RMError>;
+
+/// The `parent` of everything at the top level.
+pub const ROOT_PARENT: &str = "";
+/// The `parent` of everything in the trash.
+pub const TRASH_PARENT: &str = "trash";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemType {
+    /// A notebook, PDF or EPUB
+    #[serde(rename = "DocumentType")]
+    Document,
+    /// A folder
+    #[serde(rename = "CollectionType")]
+    Collection,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentMetadata {
+    pub visible_name: String,
+    /// Id of the folder this is in: `""` at the top level, `"trash"` in the trash
+    #[serde(default)]
+    pub parent: String,
+    #[serde(rename = "type")]
+    pub item_type: ItemType,
+    #[serde(default)]
+    pub pinned: bool,
+    /// Only written by older firmware; newer firmware moves things to the trash
+    /// and then removes the files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_time: Option<Millis>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<Millis>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_opened: Option<Millis>,
+    /// Index of the page the document was last opened at, counting from 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_opened_page: Option<usize>,
+
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// A time as a string of milliseconds since the epoch. It's kept as written, as
+/// `"0"` and empty strings both mean no time at all.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Millis(String);
+
+impl Millis {
+    /// `None` for `"0"` and empty strings.
+    pub fn time(&self) -> Option<SystemTime> {
+        match self.0.parse::<u64>() {
+            Ok(millis) if millis > 0 => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+            _ => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Millis {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if !s.is_empty() {
+            s.parse::<u64>().map_err(serde::de::Error::custom)?;
+        };
+        Ok(Millis(s))
+    }
+}
+
+impl DocumentMetadata {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(RMError::IoError)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn is_folder(&self) -> bool {
+        self.item_type == ItemType::Collection
+    }
+
+    /// In the trash, or deleted by older firmware.
+    pub fn is_trashed(&self) -> bool {
+        self.parent == TRASH_PARENT || self.deleted == Some(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const METADATA: &str = "assets/test_notebooks/e9453e30-cd4a-4a9b-aef4-41e04c047ff5.metadata";
+
+    #[test]
+    fn read_metadata() {
+        let metadata = DocumentMetadata::from_file(METADATA).unwrap();
+        assert_eq!(metadata.visible_name, "tester3");
+        assert_eq!(metadata.parent, ROOT_PARENT);
+        assert_eq!(metadata.item_type, ItemType::Document);
+        assert!(!metadata.is_folder() && !metadata.is_trashed());
+        assert_eq!(metadata.last_opened_page, Some(2));
+        let last_modified = metadata.last_modified.as_ref().and_then(Millis::time);
+        assert_eq!(last_modified, Some(UNIX_EPOCH + Duration::from_millis(1703287974573)));
+        assert!(metadata.created_time.as_ref().and_then(Millis::time) < last_modified);
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let json = r#"{
+            "deleted": false,
+            "lastModified": "1690568688624",
+            "lastOpened": "0",
+            "metadatamodified": false,
+            "parent": "trash",
+            "pinned": true,
+            "synced": true,
+            "type": "CollectionType",
+            "version": 3,
+            "visibleName": "Work"
+        }"#;
+        let metadata: DocumentMetadata = serde_json::from_str(json).unwrap();
+        assert!(metadata.is_folder() && metadata.is_trashed());
+        assert_eq!(metadata.last_opened.as_ref().and_then(Millis::time), None);
+        assert_eq!(metadata.other["version"], 3);
+
+        let expected: Value = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), expected);
+    }
+}
@@ -1,17 +1,19 @@
 
 use std::str::from_utf8;
 
-use nom::{IResult, HexDisplay};
-use nom::bytes::complete::{self as bytes, tag, take};
+use nom::IResult;
+use nom::bytes::complete::{self as bytes, tag};
 use nom::character::complete as cchar;
 use nom::number::complete as num;
-use nom::sequence::{preceded, delimited};
-use nom::multi::{length_value, length_data, length_count, many0, count};
-use nom::combinator::map as pmap;
+use nom::sequence::preceded;
+use nom::multi::{length_data, length_count, many0};
+use nom::combinator::{all_consuming, opt, map_res, map as pmap};
 
 use anyhow::{Result, Context};
+use log::warn;
 
 use super::types::*;
+use super::legacy::parse_legacy;
 
 pub const TEST_FILE_01: &str = "assets/test_notebooks/213001cb-42c0-4628-8ed0-8320c15da2a8/110b4d92-e42e-4b78-a0cb-ebd40862f2f0.rm";
 pub const TEST_FILE_02: &str = "assets/test_notebooks/213001cb-42c0-4628-8ed0-8320c15da2a8/9e0bdc4b-14cd-4d25-abb9-3ffd58d5a66e.rm";
@@ -19,342 +21,480 @@ pub const TEST_FILE_02: &str = "assets/test_notebooks/213001cb-42c0-4628-8ed0-83
 
 pub fn parse_full(input: &[u8]) -> Result<Notebook> {
     let msg = "Failed to parse full file".to_string();
-    let (input, fm) = parse_frontmatter(input).map_err(|e| e.to_owned()).context(msg)?;
-    //let (_rest, blocks) = many0(parse_block)(input)?;
-    let (_rest, blocks) = many0(parse_block)(input).map_err(|e| e.to_owned()).context("Failed to parse full file")?;
-    Ok(Notebook{frontmatter:fm, blocks: blocks})
+    let (input, fm) = parse_frontmatter(input).context(msg.clone())?;
+    let (_rest, blocks) = if fm.is_legacy() {
+        all_consuming(|i| parse_legacy(i, fm.version))(input).map_err(|e| e.to_owned()).context(msg)?
+    } else {
+        all_consuming(many0(parse_block))(input).map_err(|e| e.to_owned()).context(msg)?
+    };
+    Ok(Notebook{frontmatter:fm, blocks})
 }
 
-pub fn parse_frontmatter(input: &[u8]) -> IResult<&[u8], Frontmatter> {
+/// Parse the header, and check that it's a version we can read.
+///
+/// The header is always `HEADER_LEN` bytes: `VERSION_HEADER`, the version number,
+/// and then spaces.
+pub fn parse_frontmatter(input: &[u8]) -> std::result::Result<(&[u8], Frontmatter), RMError> {
+    let header_err = || {
+        let shown = &input[..input.len().min(HEADER_LEN)];
+        RMError::HeaderError(String::from_utf8_lossy(shown).into_owned())
+    };
+
+    if input.len() < HEADER_LEN {
+        return Err(header_err());
+    };
+    let (header, rest) = input.split_at(HEADER_LEN);
+
+    let parsed: IResult<&[u8], (&[u8], &[u8])> = all_consuming(nom::sequence::tuple((
+        preceded(tag(VERSION_HEADER), cchar::digit1),
+        bytes::take_while(|b| b == b' '),
+    )))(header);
 
-    // TODO magic 163 is the frontmatter for now...
-    let (rest, input) = take(163 as usize)(input)?;
+    let (ver_u8, spaces) = match parsed {
+        Ok((_, out)) => out,
+        Err(_) => return Err(header_err()),
+    };
+
+    let version = from_utf8(ver_u8).unwrap() //shouldn't fail if nom doesn't
+                      .parse::<u8>()
+                      .map_err(|_| header_err())?;
+
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(RMError::UnsupportedVersion(version));
+    };
 
-    let (input, ver_u8) = delimited(
-                             tag("reMarkable .lines file, version="),
-                             cchar::digit1,
-                             tag("          "),
-                          )(input)?;
-    let ver_str = from_utf8(ver_u8).unwrap(); //shouldn't fail if nom doesn't
-    let ver = ver_str.parse::<u8>().unwrap(); //same
-    Ok((rest, (Frontmatter{version: ver, unknown: input.to_owned()})))
+    Ok((rest, Frontmatter{version, padding: spaces.len()}))
 }
 
+/// Read one block. A block that doesn't parse as its type, or has bytes left over
+/// that its type doesn't account for, is kept as `Block::Unknown` so it can still be
+/// written back unchanged. Lines, text and highlights instead keep what's left over
+/// themselves, so they can still be drawn.
 pub fn parse_block(input: &[u8]) -> IResult<&[u8], Block> {
     let (input, len) = num::le_u32(input)?;
     let (input, flag) = bytes::take(4usize)(input)?;
 
     let (input, body) = bytes::take(len)(input)?;
 
-    match flag {
-        &[0,1,1,1] => {
-            parse_layer_def(&body).map(|(_,l)| (input,Block::LayerDef(l)))
+    let typed = match flag {
+        f if f == LAYER_DEF_FLAG => {
+            parse_layer_def(body).map(|(r,l)| (r,Block::LayerDef(l)))
         },
-        &[0,1,2,2] => {
-            parse_layer_name(&body).map(|(_,l)| (input,Block::LayerName(l)))
+        f if f == LAYER_NAME_FLAG => {
+            parse_layer_name(body).map(|(r,l)| (r,Block::LayerName(l)))
         },
-        &[0,1,1,7] => {
-            parse_text_def(&body).map(|(_,l)| (input,Block::TextDef(l)))
+        f if f == TEXT_DEF_FLAG => {
+            parse_text_def(body).map(|(r,l)| (r,Block::TextDef(l)))
         },
-        &[0,1,1,4] => {
-            parse_layer_info(&body).map(|(_,l)| (input,Block::LayerInfo(l)))
+        f if f == LAYER_INFO_FLAG => {
+            parse_layer_info(body).map(|(r,l)| (r,Block::LayerInfo(l)))
         },
-        &[0,2,2,5] => {
-            parse_line(&body).map(|(_,l)| (input,Block::Line(l)))
+        f if f == LINE_FLAG => {
+            parse_line(body).map(|(r,l)| (r,Block::Line(l)))
+        },
+        f if f == GLYPH_FLAG => {
+            parse_glyph(body).map(|(r,g)| (r,Block::Glyph(g)))
+        },
+        f if f == TEXT_ITEM_FLAG => {
+            parse_text_item(body).map(|(r,t)| (r,Block::TextItem(t)))
+        },
+        f if f == TOMBSTONE_FLAG => {
+            parse_item_header(body).map(|(r,h)| (r,Block::Tombstone(h)))
+        },
+        f if f == MIGRATION_INFO_FLAG => {
+            parse_migration_info(body).map(|(r,m)| (r,Block::MigrationInfo(m)))
+        },
+        f if f == AUTHOR_IDS_FLAG => {
+            parse_author_ids(body).map(|(r,a)| (r,Block::AuthorIds(a)))
+        },
+        f if f == PAGE_INFO_FLAG => {
+            parse_page_info(body).map(|(r,p)| (r,Block::PageInfo(p)))
+        },
+        f if f == SCENE_INFO_FLAG => {
+            parse_scene_info(body).map(|(r,i)| (r,Block::SceneInfo(i)))
         },
         _          => {
+            return Ok((input, Block::Unknown(flag.to_vec(), body.to_vec())));
+        },
+    };
+
+    match typed {
+        Ok((&[], block)) => Ok((input, block)),
+        _ => {
+            warn!("Keeping a block with flag {:?} as it is: it has fields we don't know", flag);
             Ok((input, Block::Unknown(flag.to_vec(), body.to_vec())))
         },
     }
 }
 
 pub fn parse_layer_def(input: &[u8]) -> IResult<&[u8], LayerDef> {
+    let (input, layer_id) = tagged_id(1)(input)?;
+    let (input, node_id) = tagged_id(2)(input)?;
+    let (input, is_update) = tagged_bool(3)(input)?;
+    let (input, parent) = tagged_subblock(4)(input)?;
+    let (_, parent_id) = all_consuming(tagged_id(1))(parent)?;
 
-    let mut parsers = nom::sequence::tuple((
-        |i| parse_id_field(&[0x1f], i, 0x2f),
-        preceded(bytes::tag(&[0x2f]), bytes::take_till(|b| b==0x4c)),
-        length_value(
-            preceded(bytes::tag(&[0x4c]), num::le_u32),
-            preceded(bytes::tag(&[0x1f]), many0(num::u8)),
-            )),
-        );
-    let (input,output) = parsers(input)?;
-    
     Ok((input, LayerDef {
-        layer_id: output.0,
-        unknown_1: output.1.into(),
-        unknown_2: output.2,
+        layer_id,
+        node_id,
+        is_update,
+        parent_id,
     }))
 }
 
 pub fn parse_layer_name(input: &[u8]) -> IResult<&[u8], LayerName> {
-    let (input, layer_id) = parse_id_field(&[0x1f], input, 0x2c)?;
-
-    let (input, subblock) = preceded(bytes::tag(&[0x2c]), length_data(num::le_u32))(input)?;
-    let (subblock, id_field_0) = parse_id_field(&[0x1f], subblock, 0x2c)?;
-    let (_, subblock) = preceded(bytes::tag(&[0x2c]), length_data(num::le_u32))(subblock)?;
-    let (subblock, len_name) = num::u8(subblock)?;
-    let (_, name) = preceded(
-                            bytes::tag(&[0x01]), 
-                            count(num::u8, len_name as usize),
-                        )(subblock)?;
-
-    let (input, unknown_rest) = length_value(
-                                    preceded(bytes::tag(&[0x3c]), num::le_u32),
-                                    many0(num::u8),
-                                )(input)?;
-
-    let name_str = match String::from_utf8(name) {
-        Ok(s) => s,
-        Err(_) => panic!("Got bytes, but was unable to convert to valid UTF-8."),
-    };
-    
+    let (input, layer_id) = tagged_id(1)(input)?;
+    let (input, name) = lww(2, tagged_string(2))(input)?;
+    let (input, visible) = lww(3, tagged_bool(2))(input)?;
+    let (input, unknown_unsized) = many0(num::u8)(input)?;
+
     Ok((input, LayerName {
         layer_id,
-        id_field_0,
-        name: name_str,
-        unknown_rest,
+        name,
+        visible,
+        unknown_unsized,
     }))
 }
 
 pub fn parse_text_def(input: &[u8]) -> IResult<&[u8], TextDef> {
-    let mut parsers = nom::sequence::tuple((
-        |i| parse_id_field(&[0x1f], i, 0x2c),
-        preceded(bytes::tag(&[0x2c]), num::le_u32),
-        preceded(bytes::tag(&[0x1c]), num::le_u32),
-        preceded(bytes::tag(&[0x1c]), num::le_u32),
-        length_count(num::u8, parse_text_chunk),
-        preceded(
-            bytes::tag(&[0x2c]),
-            length_value(num::le_u32, many0(parse_text_backmatter)),
-        ),
-        preceded(
-            bytes::tag(&[0x3c]),
-            length_value(num::le_u32, many0(num::u8)),
-        ),
-        many0(num::u8),
-    ));
-
-    let (input, output) = parsers(input)?;
-    let (id_field_0,_,_,_,texts,backmatter,unknown_sized,unknown_unsized) = output;
+    let (input, id_field_0) = tagged_id(1)(input)?;
+
+    let (input, text) = tagged_subblock(2)(input)?;
+    let (text, chunks) = tagged_subblock(1)(text)?;
+    let (_, chunks) = all_consuming(tagged_subblock(1))(chunks)?;
+    let (_, texts) = all_consuming(length_count(varuint, parse_text_chunk))(chunks)?;
+    let (_, formats) = all_consuming(tagged_subblock(2))(text)?;
+    let (_, formats) = all_consuming(tagged_subblock(1))(formats)?;
+    let (_, backmatter) = all_consuming(length_count(varuint, parse_text_backmatter))(formats)?;
+
+    let (input, position) = tagged_subblock(3)(input)?;
+    let (_, (pos_x, pos_y)) = all_consuming(nom::sequence::pair(num::le_f64, num::le_f64))(position)?;
+    let (input, width) = tagged_f32(4)(input)?;
+    let (input, unknown_unsized) = many0(num::u8)(input)?;
 
     Ok((input, TextDef {
-        id_field_0: id_field_0.try_into().unwrap(),
+        id_field_0,
         texts,
         backmatter,
-        unknown_sized: unknown_sized.to_hex(unknown_sized.len()),
-        unknown_unsized: unknown_unsized.to_hex(unknown_unsized.len()),
+        pos_x,
+        pos_y,
+        width,
+        unknown_unsized,
     }))
 }
 
-pub fn parse_layer_info(input: &[u8]) -> IResult<&[u8], LayerInfo> {
-    let mut front_parsers = nom::sequence::tuple((
-        |i| parse_id_field(&[0x1f], i, 0x2f),
-        |i| parse_id_field(&[0x2f], i, 0x3f),
-        |i| parse_id_field(&[0x3f], i, 0x4f),
-        |i| parse_id_field(&[0x4f], i, 0x54),
-        preceded(bytes::tag(&[0x54]), num::le_u32),
-    ));
-
-    let (input, front) = front_parsers(input)?;
-
-    let mut info = LayerInfo {
-        id_field_0: front.0,
-        id_field_1: front.1,
-        id_field_2: front.2,
-        id_field_3: front.3,
-        layer_id: None,
-    };
-
-    //get layer id, at end of block with no terminator
-    if front.4 == 0 {
-        let (input, count) = preceded(bytes::tag(&[0x6c]), num::le_u32)(input)?;
-        let (input, layer_id) = preceded(bytes::tag(&[0x02, 0x2f]), bytes::take(count-2))(input)?;
+/// The header shared by every scene item (lines, groups, text): the parent, the
+/// item's own id, its left and right neighbours, and how much of it is deleted.
+pub fn parse_item_header(input: &[u8]) -> IResult<&[u8], ItemHeader> {
+    let (input, (parent_id, item_id, left_id, right_id, deleted_length)) = nom::sequence::tuple((
+        tagged_id(1),
+        tagged_id(2),
+        tagged_id(3),
+        tagged_id(4),
+        tagged_u32(5),
+    ))(input)?;
+
+    Ok((input, ItemHeader { parent_id, item_id, left_id, right_id, deleted_length }))
+}
 
-        info.layer_id = Some(pad_id_field(layer_id));
-        Ok((input,info))
-    }
-    else {
-       Ok((input,info))
+/// The optional value subblock of a scene item, which starts with the item type.
+fn item_value(item_type: u8) -> impl Fn(&[u8]) -> IResult<&[u8], Option<&[u8]>> {
+    move |input| {
+        opt(pmap(
+            nom::combinator::verify(tagged_subblock(6), |b: &[u8]| b.first() == Some(&item_type)),
+            |b| &b[1..],
+        ))(input)
     }
 }
 
-pub fn parse_line(input: &[u8]) -> IResult<&[u8], Line> {
+pub fn parse_layer_info(input: &[u8]) -> IResult<&[u8], LayerInfo> {
+    let (input, front) = parse_item_header(input)?;
+    let (input, value) = item_value(0x02)(input)?;
 
-    let mut id_field_parsers = nom::sequence::tuple((
-        |i| parse_id_field(&[0x1f], i, 0x2f),
-        |i| parse_id_field(&[0x2f], i, 0x3f),
-        |i| parse_id_field(&[0x3f], i, 0x4f),
-        |i| parse_id_field(&[0x4f], i, 0x54),
-    ));
+    let layer_id = match value {
+        Some(value) => Some(all_consuming(tagged_id(2))(value)?.1),
+        None => None,
+    };
 
-    let (input, id_fields) = id_field_parsers(input)?;
+    Ok((input, LayerInfo {
+        id_field_0: front.parent_id,
+        id_field_1: front.item_id,
+        id_field_2: front.left_id,
+        id_field_3: front.right_id,
+        deleted_length: front.deleted_length,
+        layer_id,
+    }))
+}
 
-    let (input, done_flag) = preceded(bytes::tag(&[0x54]), num::le_u32)(input)?;
+pub fn parse_line(input: &[u8]) -> IResult<&[u8], Line> {
+    let (input, front) = parse_item_header(input)?;
 
     let mut line = Line {
-        layer_id: id_fields.0,
-        line_id: id_fields.1,
-        last_line_id: id_fields.2,
-        id_field_0: id_fields.3,
+        layer_id: front.parent_id,
+        line_id: front.item_id,
+        last_line_id: front.left_id,
+        id_field_0: front.right_id,
+        deleted_length: front.deleted_length,
         pen_type: None,
         color: None,
         brush_size: None,
+        starting_length: 0.0,
         points: Vec::new(),
+        timestamp: CrdtId::default(),
+        move_id: None,
+        unknown_unsized: Vec::new(),
     };
 
-    if done_flag==0 {
-        
-        let mut parsers = 
-            preceded( 
-                bytes::tag(&[0x6c]),
-                length_value(num::le_u32,
-                    nom::sequence::tuple((
-                        preceded(bytes::tag(&[0x03, 0x14]),   num::le_u32),
-                        preceded(bytes::tag(&[0x24]),         num::le_u32),
-                        preceded(bytes::tag(&[0x38,0,0,0,0]), num::le_f32),
-                        preceded(
-                            bytes::tag(&[0x44,0,0,0,0,0x5c]),
-                            length_count(
-                                pmap(num::le_u32, |l| l/14), 
-                                parse_point),
-                            ),
-                        bytes::tag(&[111,0,1]),
-                    ))
-                ),
-            );
-    
-        let (input, details) = parsers(input)?;
-
-        line.pen_type = Some(details.0);
-        line.color = Some(details.1);
+    let (input, value) = item_value(0x03)(input)?;
+    if let Some(value) = value {
+        let (_, details) = all_consuming(nom::sequence::tuple((
+            tagged_u32(1),
+            tagged_u32(2),
+            tagged_f64(3),
+            tagged_f32(4),
+            tagged_subblock(5),
+            tagged_id(6),
+            opt(tagged_id(7)),
+        )))(value)?;
+        let (_, points) = all_consuming(many0(parse_point))(details.4)?;
+
+        line.pen_type = Some(details.0.into());
+        line.color = Some(details.1.into());
         line.brush_size = Some(details.2);
-        line.points = details.3;
-        Ok((input,line))
-    }
-    else {
-        Ok((input,line))
-    }
+        line.starting_length = details.3;
+        line.points = points;
+        line.timestamp = details.5;
+        line.move_id = details.6;
+    };
+    let (input, unknown_unsized) = many0(num::u8)(input)?;
+    line.unknown_unsized = unknown_unsized;
+
+    Ok((input, line))
 }
 
-//---------------------------------------------
+pub fn parse_glyph(input: &[u8]) -> IResult<&[u8], Glyph> {
+    let (input, header) = parse_item_header(input)?;
+    let (input, value) = item_value(0x01)(input)?;
+
+    let value = match value {
+        Some(value) => {
+            let (_, (start, length, color, text, rects)) = all_consuming(nom::sequence::tuple((
+                opt(tagged_u32(2)),
+                tagged_u32(3),
+                tagged_u32(4),
+                tagged_string(5),
+                tagged_subblock(6),
+            )))(value)?;
+            let (_, rectangles) = all_consuming(length_count(varuint, parse_rect))(rects)?;
+            Some(GlyphValue { start, length, color: color.into(), text, rectangles })
+        },
+        None => None,
+    };
+    let (input, unknown_unsized) = many0(num::u8)(input)?;
 
-pub fn parse_text_chunk(input: &[u8]) -> IResult<&[u8], TextChunk> {
-    let (input, len) = preceded(bytes::tag(&[0x0c]), num::le_u32)(input)?;
-    let (final_input, rest) = bytes::take(len)(input)?;
+    Ok((input, Glyph { header, value, unknown_unsized }))
+}
 
-    let (rest, chunk_id) = preceded(
-                               bytes::tag(&[0x2f]), 
-                               bytes::take_till(|b| b==0x3f),
-                           )(rest)?;
+pub fn parse_rect(input: &[u8]) -> IResult<&[u8], Rect> {
+    let (input, (x, y, w, h)) = nom::sequence::tuple((
+        num::le_f64, num::le_f64, num::le_f64, num::le_f64,
+    ))(input)?;
+    Ok((input, Rect { x, y, w, h }))
+}
 
-    let (rest, other_chunk_id_0) = preceded(
-                                       bytes::tag(&[0x3f]), 
-                                       bytes::take_till(|b| b==0x4f),
-                                   )(rest)?;
+pub fn parse_text_item(input: &[u8]) -> IResult<&[u8], TextItem> {
+    let (input, header) = parse_item_header(input)?;
+    let (input, value) = opt(tagged_subblock(6))(input)?;
 
-    let (rest, other_chunk_id_1) = preceded(
-                                       bytes::tag(&[0x4f]), 
-                                       bytes::take_till(|b| b==0x54),
-                                   )(rest)?;
+    Ok((input, TextItem { header, value: value.map(|v| v.to_vec()) }))
+}
 
-    let (rest, done_flag) = preceded(bytes::tag(&[0x54]), num::le_u32)(rest)?;
+pub fn parse_migration_info(input: &[u8]) -> IResult<&[u8], MigrationInfo> {
+    let (input, migration_id) = tagged_id(1)(input)?;
+    let (input, is_device) = tagged_bool(2)(input)?;
+    let (input, unknown) = opt(tagged_bool(3))(input)?;
 
-    let mut chunk = TextChunk{
-        chunk_id: {
-            pad_id_field(chunk_id)
-        },
-        other_chunk_id_0: {
-            pad_id_field(other_chunk_id_0)
-        },
-        other_chunk_id_1: {
-            pad_id_field(other_chunk_id_1)
-        },
-        done_flag,
-        text: String::new(),
-        magic_dollar: None,
-    };
+    Ok((input, MigrationInfo { migration_id, is_device, unknown }))
+}
 
-    if done_flag == 0 {
-        let (rest, len_chunk) = preceded(bytes::tag(&[0x6c]), num::le_u32)(rest)?;
-        let (rest, len_string) = num::u8(rest)?;
-        let (rest, text) = preceded(
-                                bytes::tag(&[0x01]), 
-                                count(num::u8, len_string as usize),
-                            )(rest)?;
-        let (_rest, magic_dollar) = 
-            if len_chunk - len_string as u32 - 2 == 5 {
-                preceded(bytes::tag(&[0x24]), pmap(num::le_u32, |p| Some(p)))(rest)?
-            }
-            else {
-                (rest, None)
-            };
-        chunk.text = match String::from_utf8(text) {
-        Ok(s) => s,
-        Err(_) => panic!("Got bytes, but was unable to convert to valid UTF-8."),
+pub fn parse_author_ids(input: &[u8]) -> IResult<&[u8], AuthorIds> {
+    let (input, authors) = length_count(varuint, |i| {
+        let (i, body) = tagged_subblock(0)(i)?;
+        let (_, (uuid, author)) = all_consuming(nom::sequence::pair(
+            pmap(length_data(varuint), |u: &[u8]| u.to_vec()),
+            num::le_u16,
+        ))(body)?;
+        Ok((i, AuthorId { uuid, author }))
+    })(input)?;
+
+    Ok((input, AuthorIds { authors }))
+}
+
+pub fn parse_page_info(input: &[u8]) -> IResult<&[u8], PageInfo> {
+    let (input, (loads_count, merges_count, text_chars_count, text_lines_count)) = nom::sequence::tuple((
+        tagged_u32(1),
+        tagged_u32(2),
+        tagged_u32(3),
+        tagged_u32(4),
+    ))(input)?;
+    let (input, type_folio_use_count) = opt(tagged_u32(5))(input)?;
+
+    Ok((input, PageInfo {
+        loads_count,
+        merges_count,
+        text_chars_count,
+        text_lines_count,
+        type_folio_use_count,
+    }))
+}
+
+pub fn parse_scene_info(input: &[u8]) -> IResult<&[u8], SceneInfo> {
+    let (input, current_layer) = lww(1, tagged_id(2))(input)?;
+    let (input, background_visible) = opt(lww(2, tagged_bool(2)))(input)?;
+    let (input, root_document_visible) = opt(lww(3, tagged_bool(2)))(input)?;
+    let (input, paper_size) = opt(|i| {
+        let (i, body) = tagged_subblock(5)(i)?;
+        let (_, size) = all_consuming(nom::sequence::pair(num::le_u32, num::le_u32))(body)?;
+        Ok((i, size))
+    })(input)?;
+
+    Ok((input, SceneInfo {
+        current_layer,
+        background_visible,
+        root_document_visible,
+        paper_size,
+    }))
+}
+
+//---------------------------------------------
+
+pub fn parse_text_chunk(input: &[u8]) -> IResult<&[u8], TextChunk> {
+    let (input, body) = tagged_subblock(0)(input)?;
+
+    let (body, chunk_id) = tagged_id(2)(body)?;
+    let (body, other_chunk_id_0) = tagged_id(3)(body)?;
+    let (body, other_chunk_id_1) = tagged_id(4)(body)?;
+    let (body, deleted_length) = tagged_u32(5)(body)?;
+
+    let (_, value) = all_consuming(opt(tagged_subblock(6)))(body)?;
+    let (text, inline_format) = match value {
+        Some(value) => all_consuming(nom::sequence::pair(parse_string, opt(tagged_u32(2))))(value)?.1,
+        None => (String::new(), None),
     };
-        chunk.magic_dollar = magic_dollar;
-        Ok((final_input, chunk))
-    }
-    else {
-        Ok((final_input, chunk))
-    }
+
+    Ok((input, TextChunk {
+        chunk_id,
+        other_chunk_id_0,
+        other_chunk_id_1,
+        deleted_length,
+        text,
+        inline_format,
+    }))
 }
 
 pub fn parse_text_backmatter(input: &[u8]) -> IResult<&[u8], TextBackmatter> {
-    let (input, backmatter) =
-        preceded(
-            bytes::tag(&[0x1c]),
-            length_value(
-                num::le_u32,
-                preceded(
-                    bytes::take(1 as usize),
-                    nom::sequence::tuple(( bytes::take(2 as usize),
-                            preceded(bytes::tag(&[0x1f]), bytes::take(2 as usize)),
-                            preceded(
-                                bytes::tag(&[0x2c]), 
-                                length_data(num::le_u32),
-                            ),
-                    )),
-                ),
-            ),
-        )(input)?;
+    let (input, char_id) = parse_crdt_id(input)?;
+    let (input, timestamp) = tagged_id(1)(input)?;
+    let (input, format) = tagged_subblock(2)(input)?;
+    let (_, format) = all_consuming(preceded(bytes::tag(&[TEXT_FORMAT_MARKER]), num::u8))(format)?;
+
     Ok((input, TextBackmatter {
-        id_field_0: pad_id_field(backmatter.0).try_into().unwrap(),
-        id_field_1: pad_id_field(backmatter.1).try_into().unwrap(),
-        id_field_2: pad_id_field(backmatter.2).try_into().unwrap(),
+        char_id,
+        timestamp,
+        format,
     }))
 }
 
-pub fn pad_id_field(field: &[u8]) -> IdField {
-    let vfield = {
-        if field.len() == 1 {
-            let v = [field.to_owned(), vec!(0), vec!(0)].concat();
-            v
-        } else if field.len() == 2 {
-            let v = [field.to_owned(), vec!(0)].concat();
-            v
-        } else {
-            field.to_owned()
-            
-        }
+//---------------------------------------------
+// Tagged values. Every field in a v6 block is preceded by a varint tag holding the
+// field's index and a `TagType`; these check the tag and read the value after it.
+
+/// Read a LEB128 varint.
+pub fn varuint(input: &[u8]) -> IResult<&[u8], u64> {
+    let mut value = 0u64;
+    for (i, byte) in input.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((&input[i+1..], value));
+        };
     };
-    vfield.try_into().unwrap()
+    Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::TooLarge)))
+}
+
+/// A CRDT id with no tag: the author byte, then the counter as a varint.
+pub fn parse_crdt_id(input: &[u8]) -> IResult<&[u8], CrdtId> {
+    let (input, author) = num::u8(input)?;
+    let (input, counter) = varuint(input)?;
+    Ok((input, CrdtId::new(author, counter)))
+}
+
+/// Match the tag for field `index` of type `tag_type`.
+pub fn field_tag(index: u8, tag_type: TagType) -> impl Fn(&[u8]) -> IResult<&[u8], ()> {
+    let expected = ((index as u64) << 4) | tag_type as u64;
+    move |input| {
+        let (rest, _) = nom::combinator::verify(varuint, |t| *t == expected)(input)?;
+        Ok((rest, ()))
+    }
+}
+
+pub fn tagged_id(index: u8) -> impl Fn(&[u8]) -> IResult<&[u8], CrdtId> {
+    move |input| preceded(field_tag(index, TagType::Id), parse_crdt_id)(input)
 }
 
-// this_start is the flag/delimiter of THIS field, and is discarded.
-// next start is the flag/delimiter of the next field, which is not consumed.
-pub fn parse_id_field<'a, 'b>(this_start: &'b [u8], input: &'a [u8], next_start: u8) -> IResult<&'a [u8], IdField> {
-    let (input, field) = preceded( bytes::tag(this_start), 
-                                   bytes::take_while(|b| b!=next_start)
-                                   )(input)?;
-            
-    Ok((input, pad_id_field(field)))
+pub fn tagged_u8(index: u8) -> impl Fn(&[u8]) -> IResult<&[u8], u8> {
+    move |input| preceded(field_tag(index, TagType::Byte1), num::u8)(input)
+}
+
+pub fn tagged_bool(index: u8) -> impl Fn(&[u8]) -> IResult<&[u8], bool> {
+    move |input| pmap(tagged_u8(index), |b| b != 0)(input)
+}
+
+pub fn tagged_u32(index: u8) -> impl Fn(&[u8]) -> IResult<&[u8], u32> {
+    move |input| preceded(field_tag(index, TagType::Byte4), num::le_u32)(input)
+}
+
+pub fn tagged_f32(index: u8) -> impl Fn(&[u8]) -> IResult<&[u8], f32> {
+    move |input| preceded(field_tag(index, TagType::Byte4), num::le_f32)(input)
+}
+
+pub fn tagged_f64(index: u8) -> impl Fn(&[u8]) -> IResult<&[u8], f64> {
+    move |input| preceded(field_tag(index, TagType::Byte8), num::le_f64)(input)
+}
+
+/// A length-prefixed subblock; gives back its body.
+pub fn tagged_subblock(index: u8) -> impl Fn(&[u8]) -> IResult<&[u8], &[u8]> {
+    move |input| preceded(field_tag(index, TagType::Length4), length_data(num::le_u32))(input)
+}
+
+/// A last-write-wins value: a subblock holding the timestamp as field 1 and the
+/// value, read by `value`, as field 2.
+pub fn lww<'a, T, F>(index: u8, mut value: F) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], LwwValue<T>>
+where
+    F: FnMut(&'a [u8]) -> IResult<&'a [u8], T>,
+{
+    move |input| {
+        let (input, body) = tagged_subblock(index)(input)?;
+        let (body, timestamp) = tagged_id(1)(body)?;
+        let (_, value) = all_consuming(&mut value)(body)?;
+        Ok((input, LwwValue { timestamp, value }))
+    }
+}
+
+/// A subblock holding nothing but a string.
+pub fn tagged_string(index: u8) -> impl Fn(&[u8]) -> IResult<&[u8], String> {
+    move |input| {
+        let (input, body) = tagged_subblock(index)(input)?;
+        let (_, text) = all_consuming(parse_string)(body)?;
+        Ok((input, text))
+    }
 }
 
-pub fn parse_id_field_old(input: &[u8]) -> IResult<&[u8], IdField> {
-    let (input, field) = bytes::take(2 as usize)(input)?;
-    Ok((input, field.try_into().unwrap()))
+/// A string: varint length, a byte that's always 1, then that many bytes of UTF-8.
+pub fn parse_string(input: &[u8]) -> IResult<&[u8], String> {
+    let (input, len) = varuint(input)?;
+    let (input, raw) = preceded(bytes::tag(&[0x01]), bytes::take(len))(input)?;
+    let (_, text) = map_res(|i| Ok((i, i)), |b: &[u8]| String::from_utf8(b.to_vec()))(raw)?;
+    Ok((input, text))
 }
 
 pub fn parse_version_header(input: &[u8]) -> IResult<&[u8], u8> {
@@ -366,10 +506,8 @@ pub fn parse_version_header(input: &[u8]) -> IResult<&[u8], u8> {
 pub fn parse_point(input: &[u8]) -> IResult<&[u8], Point> {
     let (input, x) = num::le_f32(input)?;
     let (input, y) = num::le_f32(input)?;
-    let (input, speed) = num::u8(input)?;
-    let (input, _) = bytes::tag(&[0x00])(input)?;
-    let (input, width) = num::u8(input)?;
-    let (input, _) = bytes::tag(&[0x00])(input)?;
+    let (input, speed) = num::le_u16(input)?;
+    let (input, width) = num::le_u16(input)?;
     let (input, direction) = num::u8(input)?;
     let (input, pressure) = num::u8(input)?;
 
@@ -402,11 +540,29 @@ mod test {
         init();
         let mut assets = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         assets.push("assets/test_notebooks/213001cb-42c0-4628-8ed0-8320c15da2a8");
-        for entry in assets.read_dir().expect("can't read assets!") {
-            if let Ok(file) = entry {
-                if file.path().extension().unwrap() == clap::builder::OsStr::from("rm") {
+        for file in assets.read_dir().expect("can't read assets!").flatten() {
+            if file.path().extension().unwrap() == "rm" {
+                let bytes: Vec<u8> = read(file.path()).unwrap();
+                let _notebook = parse_full(&bytes).unwrap();
+            };
+        };
+    }
+
+    #[test]
+    fn round_trip_all() {
+        init();
+        let mut assets = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        assets.push("assets/test_notebooks");
+        for dir in assets.read_dir().expect("can't read assets!").flatten() {
+            if !dir.path().is_dir() {
+                continue;
+            };
+            for file in dir.path().read_dir().expect("can't read notebook dir!").flatten() {
+                if file.path().extension().is_some_and(|e| e == "rm") {
                     let bytes: Vec<u8> = read(file.path()).unwrap();
-                    let _notebook = parse_full(&bytes.clone()).unwrap();
+                    let notebook = parse_full(&bytes).unwrap();
+                    let written = RawBytes::from(notebook);
+                    assert!(written == bytes, "round trip failed for {:?}", file.path());
                 };
             };
         };
@@ -420,6 +576,50 @@ mod test {
         assert_eq!(version, 6);
     }
 
+    #[test]
+    fn read_frontmatter() {
+        let v6 = b"reMarkable .lines file, version=6          ";
+        let (rest, fm) = parse_frontmatter(v6).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(fm.version, 6);
+        assert_eq!(fm.padding, 10);
+        assert_eq!(RawBytes::from(fm), v6.to_vec());
+
+        let v3 = b"reMarkable .lines file, version=3          ";
+        let (_, fm) = parse_frontmatter(v3).unwrap();
+        assert!(fm.is_legacy());
+    }
+
+    #[test]
+    fn reject_bad_frontmatter() {
+        let v4 = b"reMarkable .lines file, version=4          ";
+        assert!(matches!(parse_frontmatter(v4), Err(RMError::UnsupportedVersion(4))));
+
+        let v2 = b"reMarkable lines with selections and layers";
+        assert!(matches!(parse_frontmatter(v2), Err(RMError::HeaderError(_))));
+
+        let short = b"reMarkable .lines";
+        assert!(matches!(parse_frontmatter(short), Err(RMError::HeaderError(_))));
+    }
+
+    #[test]
+    fn read_tagged_values() {
+        // ids whose bytes look like tags, and a counter too big for one byte
+        let ids = [CrdtId::new(0, 0x4c), CrdtId::new(0x2f, 0x1f), CrdtId::new(1, 300)];
+        for id in ids {
+            let bytes = [crate::types::field_tag(2, TagType::Id), RawBytes::from(id)].concat();
+            assert_eq!(tagged_id(2)(&bytes).unwrap(), (&[][..], id));
+            assert!(tagged_id(1)(&bytes).is_err());
+        };
+
+        assert_eq!(varuint(&[0xac, 0x02, 0xff]).unwrap(), (&[0xff][..], 300));
+        assert_eq!(crate::types::varuint(300), vec!(0xac, 0x02));
+
+        // indexes above 7 need a two byte tag
+        let bytes = [0x8c, 0x01, 0x01, 0x00, 0x00, 0x00, 0x2a];
+        assert_eq!(tagged_subblock(8)(&bytes).unwrap().1, &[0x2a]);
+    }
+
     #[test]
     fn get_line() {
         let bytes: &[u8] = &read(TEST_FILE_01).unwrap();
@@ -427,7 +627,7 @@ mod test {
 
         // this points in this line, in this file, has the following widths.
         if let Block::Line(line) = block {
-            let widths: Vec<u8> = line.points.iter().map(|p| p.width).collect();
+            let widths: Vec<u16> = line.points.iter().map(|p| p.width).collect();
             assert_eq!(widths, &[37,37,38,38,39,40,41,43,43,43,44,42,41]);
         }
         else {
@@ -435,12 +635,62 @@ mod test {
         };
     }
 
+    /// `block` written out again, with `extra` added to the end of its body.
+    fn with_extra(block: Block, extra: &[u8]) -> Vec<u8> {
+        let raw = RawBytes::from(block);
+        let body = [&raw[8..], extra].concat();
+        [&(body.len() as u32).to_le_bytes()[..], &raw[4..8], &body].concat()
+    }
+
+    #[test]
+    fn keep_unknown_fields() {
+        let bytes: &[u8] = &read(TEST_FILE_01).unwrap();
+        let (_, block) = parse_block(&bytes[2281..]).unwrap();
+        let Block::Line(line) = block.clone() else { panic!() };
+
+        // a field from newer firmware after the ones we know
+        let extra = [crate::types::field_tag(8, TagType::Byte4), 7u32.to_le_bytes().to_vec()].concat();
+        let raw = with_extra(block, &extra);
+        let (rest, parsed) = parse_block(&raw).unwrap();
+        assert!(rest.is_empty());
+        let Block::Line(parsed) = parsed else { panic!() };
+        assert_eq!(parsed.unknown_unsized, extra);
+        assert_eq!(parsed.points.len(), line.points.len());
+        assert_eq!(RawBytes::from(Block::Line(parsed)), raw);
+
+        // blocks that can't keep extra fields themselves are kept whole
+        let notebook = parse_full(bytes).unwrap();
+        let layer_def = notebook.blocks.iter().find(|b| matches!(b, Block::LayerDef(_))).unwrap();
+        let raw = with_extra(layer_def.clone(), &extra);
+        let (_, parsed) = parse_block(&raw).unwrap();
+        assert!(matches!(parsed, Block::Unknown(_, _)));
+        assert_eq!(RawBytes::from(parsed), raw);
+    }
+
     #[test]
     fn get_all_blocks() {
         let bytes: &[u8] = &read(TEST_FILE_01).unwrap();
-        let notebook = parse_full(&bytes).unwrap();
+        let notebook = parse_full(bytes).unwrap();
 
-        assert_eq!(12, notebook.blocks.clone().len());
+        assert_eq!(16, notebook.blocks.clone().len());
+    }
+
+    #[test]
+    fn get_preamble_blocks() {
+        let bytes: &[u8] = &read(TEST_FILE_01).unwrap();
+        let notebook = parse_full(bytes).unwrap();
+
+        let authors = notebook.blocks.iter().find_map(|b| match b {
+            Block::AuthorIds(a) => Some(a),
+            _ => None,
+        }).unwrap();
+        assert_eq!(authors.authors.len(), 1);
+        assert_eq!(authors.authors[0].uuid.len(), 16);
+        assert_eq!(authors.authors[0].author, 1);
+
+        assert!(notebook.blocks.iter().any(|b| matches!(b, Block::MigrationInfo(_))));
+        assert!(notebook.blocks.iter().any(|b| matches!(b, Block::PageInfo(_))));
+        assert!(notebook.blocks.iter().any(|b| matches!(b, Block::SceneInfo(_))));
     }
 
 }
@@ -0,0 +1,906 @@
+
+//! PDF output: one PDF page per reMarkable page, drawn as vectors.
+//!
+//! Pages are the tablet's size, 1404 by 1872 units at 226 DPI, and everything is
+//! drawn in page units (origin top left, y down) under a transform to PDF points.
+//! Strokes are styled as in the SVG output (see `svg::pen_style`). Typed text uses
+//! the standard Helvetica fonts, so it can be selected and searched; those only
+//! cover Latin-1, and other characters come out as `?`, with a warning naming them.
+//!
+//! `annotate_pdf` draws the same pages over the original PDF of an annotated
+//! document instead, placed where the tablet shows them.
+
+use std::collections::{BTreeSet, HashSet};
+
+use lopdf::{dictionary, Dictionary, Object, ObjectId, Stream, StringFormat};
+use lopdf::content::{Content, Operation};
+use log::warn;
+
+use super::types::*;
+use super::scene::Layer;
+use super::svg::{bullet, pen_style, point_width, parse_color, TextLayout, DPI, WIDTH, HEIGHT};
+use super::text::{ParagraphStyle, RichText};
+use super::geometry::Transform;
+
+type Result<T> = std::result::Result<T, RMError>;
+
+/// PDF points per page unit.
+pub const POINTS_PER_UNIT: f32 = 72.0 / DPI as f32;
+pub const PAGE_WIDTH_PT: f32 = WIDTH * POINTS_PER_UNIT;
+pub const PAGE_HEIGHT_PT: f32 = HEIGHT * POINTS_PER_UNIT;
+
+/// What goes on one page of the PDF.
+#[derive(Debug, Clone, Default)]
+pub struct PdfPage {
+    /// Bottom layer first. Hidden layers aren't drawn.
+    pub layers: Vec<Layer>,
+    pub text: Vec<TextDef>,
+    /// Outline the edges of the tablet's screen
+    pub border: bool,
+    pub text_layout: TextLayout,
+}
+
+impl PdfPage {
+    /// The page's title in the PDF's outline: its number, and its first heading if
+    /// it has one.
+    pub fn title(&self, number: usize) -> String {
+        let heading = self.text.iter()
+                               .flat_map(|t| RichText::from(t).paragraphs)
+                               .find(|p| p.style == ParagraphStyle::Heading && !p.text().trim().is_empty());
+        match heading {
+            Some(heading) => format!("Page {}: {}", number, heading.text().trim()),
+            None => format!("Page {}", number),
+        }
+    }
+}
+
+/// The standard fonts used for typed text, shared by every page.
+#[derive(Debug, Clone, Copy)]
+pub struct Fonts {
+    regular: ObjectId,
+    bold: ObjectId,
+    italic: ObjectId,
+    bold_italic: ObjectId,
+}
+
+impl Fonts {
+    pub fn add_to(doc: &mut lopdf::Document) -> Self {
+        let mut font = |name: &str| doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => name,
+            "Encoding" => "WinAnsiEncoding",
+        });
+        Fonts {
+            regular: font("Helvetica"),
+            bold: font("Helvetica-Bold"),
+            italic: font("Helvetica-Oblique"),
+            bold_italic: font("Helvetica-BoldOblique"),
+        }
+    }
+
+    fn resource_name(bold: bool, italic: bool) -> &'static str {
+        match (bold, italic) {
+            (false, false) => "RM-F1",
+            (true, false) => "RM-F2",
+            (false, true) => "RM-F3",
+            (true, true) => "RM-F4",
+        }
+    }
+
+    fn dictionary(&self) -> Dictionary {
+        dictionary! {
+            Fonts::resource_name(false, false) => self.regular,
+            Fonts::resource_name(true, false) => self.bold,
+            Fonts::resource_name(false, true) => self.italic,
+            Fonts::resource_name(true, true) => self.bold_italic,
+        }
+    }
+}
+
+/// The operations that draw a page, in page units, and the resources they use.
+#[derive(Debug, Clone, Default)]
+pub struct PageDrawing {
+    pub operations: Vec<Operation>,
+    /// Stroke opacities used, in percent. Each needs its own graphics state.
+    opacities: BTreeSet<u32>,
+    has_text: bool,
+    /// Characters of typed text the fonts can't show, drawn as `?`
+    replaced: BTreeSet<char>,
+}
+
+impl PageDrawing {
+    pub fn new(page: &PdfPage) -> Self {
+        let mut drawing = PageDrawing::default();
+        for layer in page.layers.iter().filter(|l| l.visible) {
+            // under the layer's strokes, as on the tablet
+            for glyph in &layer.highlights {
+                drawing.highlight(glyph);
+            };
+            for line in &layer.lines {
+                drawing.line(line);
+            };
+        };
+        for text in &page.text {
+            drawing.text(text, &page.text_layout);
+        };
+        if page.border {
+            drawing.border();
+        };
+        if !drawing.replaced.is_empty() {
+            let replaced: String = drawing.replaced.iter().collect();
+            warn!("the PDF's fonts can't show {:?}, so typed text has `?` in their place", replaced);
+        };
+        drawing
+    }
+
+    fn op(&mut self, operator: &str, operands: Vec<Object>) {
+        self.operations.push(Operation::new(operator, operands));
+    }
+
+    fn set_opacity(&mut self, opacity: f64) {
+        let percent = (opacity * 100.0).round().clamp(0.0, 100.0) as u32;
+        self.opacities.insert(percent);
+        self.op("gs", vec![Object::Name(opacity_name(percent).into_bytes())]);
+    }
+
+    /// A line, styled as `svg::paths_from_line` styles it.
+    fn line(&mut self, line: &Line) {
+        let pen_type = line.pen_type.unwrap_or_default();
+        let Some(style) = pen_style(pen_type, line.color.unwrap_or_default()) else {
+            return;
+        };
+        if line.points.is_empty() {
+            return;
+        };
+        let (r, g, b) = parse_color(&style.color).unwrap_or_default();
+        let x = |p: &Point| (p.x + HALF_WIDTH).into();
+
+        self.op("q", vec![]);
+        self.op("RG", vec![channel(r), channel(g), channel(b)]);
+        self.op("J", vec![if style.cap == "square" { 2 } else { 1 }.into()]);
+        self.op("j", vec![1.into()]);
+        if line.is_deleted() {
+            self.op("d", vec![vec![Object::Integer(4), Object::Integer(4)].into(), 0.into()]);
+            self.set_opacity(style.opacity * 0.5);
+        } else {
+            self.set_opacity(style.opacity);
+        };
+
+        if style.variable_width && line.points.len() > 1 {
+            for pair in line.points.windows(2) {
+                self.op("w", vec![point_width(pen_type, &pair[0]).into()]);
+                self.op("m", vec![x(&pair[0]), pair[0].y.into()]);
+                self.op("l", vec![x(&pair[1]), pair[1].y.into()]);
+                self.op("S", vec![]);
+            };
+        } else {
+            let widths: Vec<f64> = line.points.iter().map(|p| point_width(pen_type, p)).collect();
+            self.op("w", vec![(widths.iter().sum::<f64>() / widths.len() as f64).into()]);
+            let (first, rest) = line.points.split_first().unwrap_or((&line.points[0], &[]));
+            self.op("m", vec![x(first), first.y.into()]);
+            // a single point still makes a dot, with round caps
+            for p in if rest.is_empty() { std::slice::from_ref(first) } else { rest } {
+                self.op("l", vec![x(p), p.y.into()]);
+            };
+            self.op("S", vec![]);
+        };
+        self.op("Q", vec![]);
+    }
+
+    /// A highlight over text of the original file, as filled rectangles in the
+    /// highlighter's color.
+    fn highlight(&mut self, glyph: &Glyph) {
+        let Some(value) = &glyph.value else {
+            return;
+        };
+        let Some(style) = pen_style(PenType::Highlighter1, value.color) else {
+            return;
+        };
+        if value.rectangles.is_empty() {
+            return;
+        };
+        let (r, g, b) = parse_color(&style.color).unwrap_or_default();
+        self.op("q", vec![]);
+        self.op("rg", vec![channel(r), channel(g), channel(b)]);
+        self.set_opacity(style.opacity);
+        for rect in &value.rectangles {
+            self.op("re", vec![(rect.x + HALF_WIDTH as f64).into(), rect.y.into(), rect.w.into(), rect.h.into()]);
+        };
+        self.op("f", vec![]);
+        self.op("Q", vec![]);
+    }
+
+    /// Typed text, laid out as `svg::group_from_text` lays it out.
+    fn text(&mut self, text: &TextDef, layout: &TextLayout) {
+        let x = text.pos_x + HALF_WIDTH as f64;
+        let mut top = text.pos_y;
+        for paragraph in RichText::from(text).paragraphs {
+            let scale = TextLayout::scale(paragraph.style);
+            let size = layout.font_size * scale;
+            if paragraph.text().trim().is_empty() {
+                top += layout.line_height * scale;
+                continue;
+            };
+            self.has_text = true;
+
+            let all_bold = matches!(paragraph.style, ParagraphStyle::Heading | ParagraphStyle::Bold);
+            for (bullet, indent, line) in layout.wrap(&paragraph, text.width, pdf_bullet(paragraph.style)) {
+                let y = top + size;
+                top += layout.line_height * scale;
+
+                self.op("BT", vec![]);
+                // flipped back over, as the page is drawn with y down
+                self.op("Tm", vec![1.into(), 0.into(), 0.into(), (-1).into(), (x + indent).into(), y.into()]);
+                if !bullet.is_empty() {
+                    self.op("Tf", vec![Object::Name(Fonts::resource_name(all_bold, false).into()), size.into()]);
+                    let shown = win_ansi(bullet, &mut self.replaced);
+                    self.op("Tj", vec![Object::String(shown, StringFormat::Literal)]);
+                };
+                for span in &line.spans {
+                    let font = Fonts::resource_name(all_bold || span.bold, span.italic);
+                    self.op("Tf", vec![Object::Name(font.into()), size.into()]);
+                    let shown = win_ansi(&span.text, &mut self.replaced);
+                    self.op("Tj", vec![Object::String(shown, StringFormat::Literal)]);
+                };
+                self.op("ET", vec![]);
+            };
+        };
+    }
+
+    fn border(&mut self) {
+        self.op("q", vec![]);
+        self.op("RG", vec![channel(0x80), channel(0x80), channel(0x80)]);
+        self.op("w", vec![1.into()]);
+        self.op("re", vec![0.into(), 0.into(), WIDTH.into(), HEIGHT.into()]);
+        self.op("S", vec![]);
+        self.op("Q", vec![]);
+    }
+
+    /// The drawing as a content stream, placed on the PDF page by `transform`
+    /// (`a b c d e f`, from page units to the PDF page's space).
+    pub fn content(&self, transform: [f32; 6]) -> Result<Vec<u8>> {
+        let mut operations = vec![
+            Operation::new("q", vec![]),
+            Operation::new("cm", transform.iter().map(|v| (*v).into()).collect()),
+        ];
+        operations.extend(self.operations.iter().cloned());
+        operations.push(Operation::new("Q", vec![]));
+        Ok(Content { operations }.encode()?)
+    }
+
+    /// The `ExtGState` and `Font` resources the drawing needs.
+    pub fn resources(&self, fonts: &Fonts) -> Dictionary {
+        let mut resources = Dictionary::new();
+        if !self.opacities.is_empty() {
+            let states: Dictionary = self.opacities.iter().map(|percent| {
+                let alpha = *percent as f32 / 100.0;
+                (opacity_name(*percent), Object::Dictionary(dictionary! {
+                    "Type" => "ExtGState",
+                    "CA" => alpha,
+                    "ca" => alpha,
+                }))
+            }).collect();
+            resources.set("ExtGState", states);
+        };
+        if self.has_text {
+            resources.set("Font", fonts.dictionary());
+        };
+        resources
+    }
+}
+
+/// What starts a paragraph in `style`. The standard fonts have no ballot boxes, so
+/// checkboxes are drawn as in Markdown.
+fn pdf_bullet(style: ParagraphStyle) -> &'static str {
+    match style {
+        ParagraphStyle::Checkbox => "[ ] ",
+        ParagraphStyle::CheckboxChecked => "[x] ",
+        _ => bullet(style),
+    }
+}
+
+/// Prefixed so they don't clash with resources of pages being drawn over.
+fn opacity_name(percent: u32) -> String {
+    format!("RM-GS{}", percent)
+}
+
+fn channel(value: u8) -> Object {
+    (value as f32 / 255.0).into()
+}
+
+/// From page units to PDF points, with the origin moved to the bottom left.
+pub fn page_transform() -> [f32; 6] {
+    [POINTS_PER_UNIT, 0.0, 0.0, -POINTS_PER_UNIT, 0.0, PAGE_HEIGHT_PT]
+}
+
+/// Text in the standard fonts' WinAnsi encoding. Latin-1 maps straight across;
+/// a few other common characters have their own codes, and the rest become `?`
+/// and are added to `replaced`.
+fn win_ansi(text: &str, replaced: &mut BTreeSet<char>) -> Vec<u8> {
+    text.chars().map(|c| match c {
+        '\u{20}'..='\u{7e}' | '\u{a0}'..='\u{ff}' => c as u8,
+        '\u{20ac}' => 0x80,
+        '\u{2026}' => 0x85,
+        '\u{2018}' => 0x91,
+        '\u{2019}' => 0x92,
+        '\u{201c}' => 0x93,
+        '\u{201d}' => 0x94,
+        '\u{2022}' => 0x95,
+        '\u{2013}' => 0x96,
+        '\u{2014}' => 0x97,
+        _ => {
+            replaced.insert(c);
+            b'?'
+        },
+    }).collect()
+}
+
+/// A string for the PDF's outline and info: plain if it's ASCII, otherwise UTF-16
+/// with a byte order mark.
+pub fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    };
+    let mut bytes = vec![0xfe, 0xff];
+    bytes.extend(text.encode_utf16().flat_map(|u| u.to_be_bytes()));
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// An outline with an entry for each page, each going to the whole page.
+pub fn add_outline(doc: &mut lopdf::Document, entries: &[(String, ObjectId)]) -> Option<ObjectId> {
+    if entries.is_empty() {
+        return None;
+    };
+    let outline_id = doc.new_object_id();
+    let ids: Vec<ObjectId> = entries.iter().map(|_| doc.new_object_id()).collect();
+    for (i, (title, page)) in entries.iter().enumerate() {
+        let mut entry = dictionary! {
+            "Title" => text_string(title),
+            "Parent" => outline_id,
+            "Dest" => vec![(*page).into(), "Fit".into()],
+        };
+        if i > 0 {
+            entry.set("Prev", ids[i - 1]);
+        };
+        if let Some(next) = ids.get(i + 1) {
+            entry.set("Next", *next);
+        };
+        doc.objects.insert(ids[i], Object::Dictionary(entry));
+    };
+    doc.objects.insert(outline_id, Object::Dictionary(dictionary! {
+        "Type" => "Outlines",
+        "First" => ids[0],
+        "Last" => ids[ids.len() - 1],
+        "Count" => ids.len() as i64,
+    }));
+    Some(outline_id)
+}
+
+/// A PDF of `pages`, titled `title` unless it's empty, with an outline entry for
+/// every page.
+pub fn pdf_from_pages(title: &str, pages: &[PdfPage]) -> Result<Vec<u8>> {
+    let mut doc = lopdf::Document::with_version("1.7");
+    let pages_id = doc.new_object_id();
+    let fonts = Fonts::add_to(&mut doc);
+
+    let mut kids = Vec::new();
+    let mut outline = Vec::new();
+    for (index, page) in pages.iter().enumerate() {
+        let drawing = PageDrawing::new(page);
+        let mut stream = Stream::new(Dictionary::new(), drawing.content(page_transform())?);
+        stream.compress()?;
+        let content_id = doc.add_object(stream);
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), PAGE_WIDTH_PT.into(), PAGE_HEIGHT_PT.into()],
+            "Contents" => content_id,
+            "Resources" => drawing.resources(&fonts),
+        });
+        kids.push(Object::Reference(page_id));
+        outline.push((page.title(index + 1), page_id));
+    };
+
+    doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+        "Type" => "Pages",
+        "Count" => kids.len() as i64,
+        "Kids" => kids,
+    }));
+    let mut catalog = dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    };
+    if let Some(outline_id) = add_outline(&mut doc, &outline) {
+        catalog.set("Outlines", outline_id);
+        catalog.set("PageMode", "UseOutlines");
+    };
+    let catalog_id = doc.add_object(catalog);
+    let mut info = dictionary! {
+        "Producer" => text_string("rmconvert"),
+    };
+    if !title.is_empty() {
+        info.set("Title", text_string(title));
+    };
+    let info_id = doc.add_object(info);
+    doc.trailer.set("Root", catalog_id);
+    doc.trailer.set("Info", info_id);
+
+    let mut bytes = Vec::new();
+    doc.save_to(&mut bytes).map_err(RMError::IoError)?;
+    Ok(bytes)
+}
+
+/// How the tablet sizes the pages of a PDF to its screen, from `.content`'s
+/// `zoomMode`. Annotations are stored in screen units, so this decides where
+/// they land on the original page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageFit {
+    /// The whole page on the screen. What the tablet does unless told otherwise.
+    Best,
+    Width,
+    Height,
+    /// The page laid out `page_width` units wide, then zoomed by `zoom` about
+    /// `center`: the point of the laid out page, in page units with x from the
+    /// middle, that's shown at the middle of the screen.
+    Custom { page_width: f32, center: (f32, f32), zoom: f32 },
+}
+
+impl PageFit {
+    pub fn from_content(content: &super::content::Content) -> Self {
+        let number = |n: &Option<serde_json::Number>| n.as_ref().and_then(|n| n.as_f64()).map(|n| n as f32);
+        match content.zoom_mode.as_deref() {
+            Some("fitToWidth") => PageFit::Width,
+            Some("fitToHeight") => PageFit::Height,
+            Some("customFit") => match number(&content.custom_zoom_page_width) {
+                Some(page_width) if page_width > 0.0 => PageFit::Custom {
+                    page_width,
+                    center: (number(&content.custom_zoom_center_x).unwrap_or(0.0),
+                             number(&content.custom_zoom_center_y).unwrap_or(HEIGHT / 2.0)),
+                    zoom: number(&content.custom_zoom_scale).filter(|z| *z > 0.0).unwrap_or(1.0),
+                },
+                _ => PageFit::Best,
+            },
+            _ => PageFit::Best,
+        }
+    }
+
+    /// Page units per PDF point, for a PDF page `width` by `height` points, before
+    /// any zoom.
+    pub fn scale(&self, width: f32, height: f32) -> f32 {
+        match self {
+            PageFit::Best => (WIDTH / width).min(HEIGHT / height),
+            PageFit::Width => WIDTH / width,
+            PageFit::Height => HEIGHT / height,
+            PageFit::Custom { page_width, .. } => page_width / width,
+        }
+    }
+
+    /// The point shown at the middle of the screen, and the zoom about it.
+    fn view(&self) -> ((f32, f32), f32) {
+        match self {
+            PageFit::Custom { center, zoom, .. } => (*center, *zoom),
+            _ => ((0.0, HEIGHT / 2.0), 1.0),
+        }
+    }
+}
+
+/// From page units to the space of a PDF page showing `bbox` (`x0 y0 x1 y1`, in
+/// points), turned `rotate` degrees clockwise by its `/Rotate`. The tablet shows
+/// the page turned, centred across the screen with its top at the top, and then
+/// zoomed as `fit` says.
+pub fn overlay_transform(bbox: [f32; 4], rotate: i64, fit: PageFit) -> [f32; 6] {
+    let [x0, y0, x1, y1] = bbox.map(f64::from);
+    let (width, height) = (x1 - x0, y1 - y0);
+    let rotate = rotate.rem_euclid(360);
+    let (shown_width, shown_height) = match rotate {
+        90 | 270 => (height, width),
+        _ => (width, height),
+    };
+
+    let scale = fit.scale(shown_width as f32, shown_height as f32) as f64;
+    let ((center_x, center_y), zoom) = fit.view();
+    let units = scale * zoom as f64;
+    // onto the page as it's shown, with the origin at its bottom left
+    let shown = Transform::matrix(1.0 / units, 0.0, 0.0, -1.0 / units,
+                                  shown_width / 2.0 + center_x as f64 / scale - HALF_WIDTH as f64 / units,
+                                  shown_height - center_y as f64 / scale + HEIGHT as f64 / 2.0 / units);
+    // and turned back to the page as it's stored
+    let stored = match rotate {
+        90 => Transform::matrix(0.0, 1.0, -1.0, 0.0, x0 + width, y0),
+        180 => Transform::matrix(-1.0, 0.0, 0.0, -1.0, x0 + width, y0 + height),
+        270 => Transform::matrix(0.0, -1.0, 1.0, 0.0, x0, y0 + height),
+        _ => Transform::translate(x0, y0),
+    };
+    let m = stored * shown;
+    [m.a, m.b, m.c, m.d, m.e, m.f].map(|v| v as f32)
+}
+
+/// A page of an annotated document: what's drawn on it, and the page of the
+/// original PDF it's drawn over.
+#[derive(Debug, Clone, Default)]
+pub struct AnnotatedPage {
+    /// Counting from 0. `None` for pages inserted on the tablet, which come out blank.
+    pub original_page: Option<usize>,
+    pub page: PdfPage,
+}
+
+/// Page attributes a page can inherit from the page tree above it.
+const INHERITED: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// The original PDF with `pages` drawn over it, in the document's page order.
+/// Original pages the document doesn't show are dropped, pages shown twice are
+/// copied, and inserted pages are added at the tablet's size.
+pub fn annotate_pdf(original: &[u8], pages: &[AnnotatedPage], fit: PageFit) -> Result<Vec<u8>> {
+    let mut doc = lopdf::Document::load_mem(original)?;
+    // taken before any are drawn on, so a page shown twice doesn't get its first
+    // copy's annotations as well
+    let originals = doc.get_pages().into_values()
+                       .map(|id| Ok((id, flattened_page(&doc, id)?)))
+                       .collect::<Result<Vec<(ObjectId, Dictionary)>>>()?;
+    let pages_id = doc.catalog()?.get(b"Pages")?.as_reference()?;
+    let fonts = Fonts::add_to(&mut doc);
+
+    let mut used = HashSet::new();
+    let mut kids = Vec::new();
+    for page in pages {
+        let drawing = PageDrawing::new(&page.page);
+        let page_id = match page.original_page.and_then(|index| originals.get(index)) {
+            Some((original_id, original)) => {
+                let mut dict = original.clone();
+                dict.set("Parent", pages_id);
+                overlay(&mut doc, &mut dict, &drawing, fit, &fonts)?;
+                match used.insert(*original_id) {
+                    true => {
+                        doc.objects.insert(*original_id, Object::Dictionary(dict));
+                        *original_id
+                    },
+                    false => doc.add_object(dict),
+                }
+            },
+            None => {
+                let mut stream = Stream::new(Dictionary::new(), drawing.content(page_transform())?);
+                stream.compress()?;
+                let content_id = doc.add_object(stream);
+                doc.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "MediaBox" => vec![0.into(), 0.into(), PAGE_WIDTH_PT.into(), PAGE_HEIGHT_PT.into()],
+                    "Contents" => content_id,
+                    "Resources" => drawing.resources(&fonts),
+                })
+            },
+        };
+        kids.push(Object::Reference(page_id));
+    };
+
+    let root = doc.get_object_mut(pages_id)?.as_dict_mut()?;
+    root.set("Count", kids.len() as i64);
+    root.set("Kids", kids);
+    doc.prune_objects();
+
+    let mut bytes = Vec::new();
+    doc.save_to(&mut bytes).map_err(RMError::IoError)?;
+    Ok(bytes)
+}
+
+/// A copy of a page's dictionary with what it inherits filled in, so it can be
+/// moved straight under the root of the page tree.
+fn flattened_page(doc: &lopdf::Document, page_id: ObjectId) -> Result<Dictionary> {
+    let mut page = doc.get_dictionary(page_id)?.clone();
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+    while let Some(parent_id) = parent {
+        let node = doc.get_dictionary(parent_id)?;
+        for key in INHERITED {
+            if !page.has(key) {
+                if let Ok(value) = node.get(key) {
+                    page.set(key, value.clone());
+                };
+            };
+        };
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    };
+    Ok(page)
+}
+
+/// Add `drawing` on top of the page's own content, in a graphics state of its own,
+/// and merge in the resources it needs.
+fn overlay(doc: &mut lopdf::Document, page: &mut Dictionary, drawing: &PageDrawing, fit: PageFit, fonts: &Fonts) -> Result<()> {
+    let bbox = page.get(b"CropBox").or_else(|_| page.get(b"MediaBox"))?;
+    let bbox = match doc.dereference(bbox)?.1.as_array()?.as_slice() {
+        [x0, y0, x1, y1] => {
+            let corner = |v: &Object| doc.dereference(v).and_then(|(_, v)| v.as_float());
+            let (x0, y0, x1, y1) = (corner(x0)?, corner(y0)?, corner(x1)?, corner(y1)?);
+            [x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)]
+        },
+        _ => Err(lopdf::Error::Type)?,
+    };
+
+    let rotate = match page.get(b"Rotate") {
+        Ok(rotate) => doc.dereference(rotate)?.1.as_i64()?,
+        Err(_) => 0,
+    };
+
+    let mut contents = match page.get(b"Contents") {
+        Ok(Object::Reference(id)) => match doc.get_object(*id)? {
+            Object::Array(streams) => streams.clone(),
+            _ => vec![Object::Reference(*id)],
+        },
+        Ok(Object::Array(streams)) => streams.clone(),
+        _ => Vec::new(),
+    };
+    // the original's content may leave the graphics state changed, so it's saved
+    // before and restored after. Streams are joined as they are, hence the newlines.
+    let before = doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let mut after = b"\nQ\n".to_vec();
+    after.extend(drawing.content(overlay_transform(bbox, rotate, fit))?);
+    let mut after = Stream::new(Dictionary::new(), after);
+    after.compress()?;
+    contents.insert(0, Object::Reference(before));
+    contents.push(Object::Reference(doc.add_object(after)));
+    page.set("Contents", contents);
+
+    let mut resources = match page.get(b"Resources") {
+        Ok(resources) => resolved_dictionary(doc, resources)?,
+        Err(_) => Dictionary::new(),
+    };
+    for (key, ours) in drawing.resources(fonts).iter() {
+        let mut merged = match resources.get(key) {
+            Ok(theirs) => resolved_dictionary(doc, theirs)?,
+            Err(_) => Dictionary::new(),
+        };
+        merged.extend(ours.as_dict()?);
+        resources.set(key.clone(), merged);
+    };
+    page.set("Resources", resources);
+    Ok(())
+}
+
+fn resolved_dictionary(doc: &lopdf::Document, object: &Object) -> Result<Dictionary> {
+    Ok(doc.dereference(object)?.1.as_dict()?.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::document::Document;
+    use crate::scene::Layer;
+
+    const RM_DIR: &str = "assets/test_notebooks";
+
+    fn pages(id: &str) -> Vec<PdfPage> {
+        let document = Document::open(RM_DIR, id).unwrap();
+        document.pages.iter().map(|p| {
+            let notebook = p.read().unwrap();
+            PdfPage {
+                layers: notebook.layers(),
+                text: notebook.blocks.iter().filter_map(|b| match b {
+                    Block::TextDef(t) => Some(t.clone()),
+                    _ => None,
+                }).collect(),
+                ..PdfPage::default()
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn pdf_pages() {
+        let pages = pages("e9453e30-cd4a-4a9b-aef4-41e04c047ff5");
+        let bytes = pdf_from_pages("tester3", &pages).unwrap();
+        let pdf = lopdf::Document::load_mem(&bytes).unwrap();
+
+        let page_ids = pdf.get_pages();
+        assert_eq!(page_ids.len(), 3);
+        let media_box = pdf.get_dictionary(page_ids[&1]).unwrap().get(b"MediaBox").unwrap().as_array().unwrap();
+        assert!((media_box[2].as_float().unwrap() - 447.29).abs() < 0.01);
+        assert!((media_box[3].as_float().unwrap() - 596.39).abs() < 0.01);
+
+        // typed text is in the content stream as text
+        let content = Content::decode(&pdf.get_page_content(page_ids[&3]).unwrap()).unwrap();
+        let shown: Vec<&[u8]> = content.operations.iter()
+                                       .filter(|op| op.operator == "Tj")
+                                       .filter_map(|op| op.operands[0].as_str().ok())
+                                       .collect();
+        assert!(shown.contains(&&b"i am big text"[..]));
+        let content = Content::decode(&pdf.get_page_content(page_ids[&2]).unwrap()).unwrap();
+        assert!(content.operations.iter().any(|op| op.operator == "S"));
+
+        let catalog = pdf.catalog().unwrap();
+        let outline = pdf.get_dictionary(catalog.get(b"Outlines").unwrap().as_reference().unwrap()).unwrap();
+        assert_eq!(outline.get(b"Count").unwrap().as_i64().unwrap(), 3);
+        let last = pdf.get_dictionary(outline.get(b"Last").unwrap().as_reference().unwrap()).unwrap();
+        assert_eq!(last.get(b"Title").unwrap().as_str().unwrap(), b"Page 3: i am big text");
+    }
+
+    /// A two-page PDF: a letter page inheriting its size and font from the page
+    /// tree, and a landscape A4 page with its own.
+    fn original_pdf() -> Vec<u8> {
+        let mut doc = lopdf::Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+        });
+        let text = doc.add_object(Stream::new(Dictionary::new(), b"BT /F1 12 Tf 72 720 Td (first) Tj ET".to_vec()));
+        let first = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => text,
+        });
+        let rule = doc.add_object(Stream::new(Dictionary::new(), b"0 0 m 842 595 l S".to_vec()));
+        let second = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 842.into(), 595.into()],
+            "Contents" => vec![rule.into()],
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![first.into(), second.into()],
+            "Count" => 2,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+        }));
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn annotations() -> PdfPage {
+        let line = Line {
+            pen_type: Some(PenType::Fineliner2),
+            points: [(-702.0, 0.0), (0.0, 100.0)].iter().map(|(x, y)| Point {
+                x: *x,
+                y: *y,
+                width: 8,
+                ..Point::default()
+            }).collect(),
+            ..Line::default()
+        };
+        let highlight = Glyph {
+            header: ItemHeader::default(),
+            value: Some(GlyphValue {
+                start: None,
+                length: 5,
+                color: PenColor::Yellow,
+                text: "first".to_string(),
+                rectangles: vec![Rect { x: -500.0, y: 200.0, w: 100.0, h: 30.0 }],
+            }),
+            unknown_unsized: Vec::new(),
+        };
+        PdfPage {
+            layers: vec![Layer {
+                id: CrdtId::new(0, 11),
+                name: "Layer 1".to_string(),
+                visible: true,
+                lines: vec![line],
+                highlights: vec![highlight],
+                text: Vec::new(),
+            }],
+            ..PdfPage::default()
+        }
+    }
+
+    fn media_box(pdf: &lopdf::Document, page: ObjectId) -> Vec<f32> {
+        pdf.get_dictionary(page).unwrap().get(b"MediaBox").unwrap()
+           .as_array().unwrap().iter().map(|v| v.as_float().unwrap()).collect()
+    }
+
+    #[test]
+    fn annotate_original() {
+        // the pages swapped, a page inserted between them, and the first page twice
+        let pages = [
+            AnnotatedPage { original_page: Some(1), page: PdfPage::default() },
+            AnnotatedPage { original_page: None, page: annotations() },
+            AnnotatedPage { original_page: Some(0), page: annotations() },
+            AnnotatedPage { original_page: Some(0), page: PdfPage::default() },
+        ];
+        let bytes = annotate_pdf(&original_pdf(), &pages, PageFit::Best).unwrap();
+        let pdf = lopdf::Document::load_mem(&bytes).unwrap();
+        let page_ids = pdf.get_pages();
+        assert_eq!(page_ids.len(), 4);
+
+        assert_eq!(media_box(&pdf, page_ids[&1]), [0.0, 0.0, 842.0, 595.0]);
+        let blank = media_box(&pdf, page_ids[&2]);
+        assert!((blank[2] - PAGE_WIDTH_PT).abs() < 0.01 && (blank[3] - PAGE_HEIGHT_PT).abs() < 0.01);
+        // inherited size and resources come along with the page
+        assert_eq!(media_box(&pdf, page_ids[&3]), [0.0, 0.0, 612.0, 792.0]);
+        assert_ne!(page_ids[&3], page_ids[&4]);
+
+        // the second copy of the first page has nothing drawn after the original's content
+        let content = Content::decode(&pdf.get_page_content(page_ids[&4]).unwrap()).unwrap();
+        let operators: Vec<&str> = content.operations.iter().map(|op| op.operator.as_str()).collect();
+        let shown = operators.iter().position(|op| *op == "Tj").unwrap();
+        assert!(!operators[shown..].iter().any(|op| *op == "S" || *op == "f"));
+
+        // the original's content comes first, then the annotations
+        let content = Content::decode(&pdf.get_page_content(page_ids[&3]).unwrap()).unwrap();
+        let operators: Vec<&str> = content.operations.iter().map(|op| op.operator.as_str()).collect();
+        let shown = operators.iter().position(|op| *op == "Tj").unwrap();
+        let filled = operators.iter().position(|op| *op == "f").unwrap();
+        let stroked = operators.iter().position(|op| *op == "S").unwrap();
+        assert!(operators[0] == "q" && shown < filled && filled < stroked);
+        let cm = content.operations.iter().find(|op| op.operator == "cm").unwrap();
+        let scale = PageFit::Best.scale(612.0, 792.0);
+        assert!((cm.operands[0].as_float().unwrap() - 1.0 / scale).abs() < 0.001);
+
+        let resources = pdf.get_dictionary(page_ids[&3]).unwrap().get(b"Resources").unwrap().as_dict().unwrap();
+        let fonts = resources.get(b"Font").unwrap().as_dict().unwrap();
+        assert!(fonts.has(b"F1"));
+        let states = resources.get(b"ExtGState").unwrap().as_dict().unwrap();
+        assert!(states.has(b"RM-GS30") && states.has(b"RM-GS100"));
+    }
+
+    #[test]
+    fn page_fit() {
+        // where a point in page units lands on the PDF page
+        let at = |m: [f32; 6], (x, y): (f32, f32)| (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5]);
+        let near = |(x, y): (f32, f32), (ex, ey): (f32, f32)| (x - ex).abs() < 0.01 && (y - ey).abs() < 0.01;
+
+        // a letter page fills the screen's width, with its top at the top
+        let m = overlay_transform([0.0, 0.0, 612.0, 792.0], 0, PageFit::Best);
+        assert!((m[0] * WIDTH - 612.0).abs() < 0.01 && m[3] == -m[0]);
+        assert!(near(at(m, (0.0, 0.0)), (0.0, 792.0)));
+
+        // a landscape page fits the height, centred across the screen
+        let m = overlay_transform([0.0, 0.0, 842.0, 595.0], 0, PageFit::Height);
+        assert!((m[0] * HEIGHT - 595.0).abs() < 0.01);
+        assert!(near(at(m, (HALF_WIDTH, 0.0)), (421.0, 595.0)));
+
+        let custom = |page_width, center, zoom| PageFit::Custom { page_width, center, zoom };
+        let m = overlay_transform([0.0, 0.0, 612.0, 792.0], 0, custom(2808.0, (0.0, HEIGHT / 2.0), 1.0));
+        assert!((m[0] * 2808.0 - 612.0).abs() < 0.01);
+
+        // zoomed in twice over, with the page's top right quarter in the middle
+        // of the screen: 100 points per 300 units there, and 50 per 300 on screen
+        let fit = custom(1836.0, (459.0, 594.0), 2.0);
+        let m = overlay_transform([0.0, 0.0, 612.0, 792.0], 0, fit);
+        assert!(near(at(m, (HALF_WIDTH, HEIGHT / 2.0)), (459.0, 594.0)));
+        assert!(near(at(m, (HALF_WIDTH + 300.0, HEIGHT / 2.0)), (509.0, 594.0)));
+
+        // a portrait page turned on its side is shown as landscape, fitting the width
+        let m = overlay_transform([0.0, 0.0, 612.0, 792.0], 90, PageFit::Best);
+        assert!((m[1] * WIDTH - 792.0).abs() < 0.01);
+        assert!(near(at(m, (0.0, 0.0)), (0.0, 0.0)));
+        assert!(near(at(m, (WIDTH, 0.0)), (0.0, 792.0)));
+        let m = overlay_transform([0.0, 0.0, 612.0, 792.0], -90, PageFit::Best);
+        assert!(near(at(m, (0.0, 0.0)), (612.0, 792.0)));
+        let m = overlay_transform([0.0, 0.0, 612.0, 792.0], 180, PageFit::Best);
+        assert!(near(at(m, (0.0, 0.0)), (612.0, 0.0)));
+
+        let content = crate::content::Content::from_value(serde_json::json!({
+            "zoomMode": "customFit",
+            "customZoomCenterX": 459,
+            "customZoomCenterY": 594,
+            "customZoomPageWidth": 1836,
+            "customZoomScale": 2,
+        })).unwrap();
+        assert_eq!(PageFit::from_content(&content), fit);
+    }
+
+    #[test]
+    fn text_encoding() {
+        let mut replaced = BTreeSet::new();
+        assert_eq!(win_ansi("caf\u{e9} \u{2022} \u{2610}", &mut replaced), b"caf\xe9 \x95 ?");
+        assert_eq!(replaced, BTreeSet::from(['\u{2610}']));
+
+        let mut notebook = Notebook::blank(AuthorIds { authors: Vec::new() });
+        notebook.push_text("\u{65e5}\u{672c} ok");
+        let page = PdfPage {
+            text: notebook.blocks.into_iter().filter_map(|b| match b {
+                Block::TextDef(t) => Some(t),
+                _ => None,
+            }).collect(),
+            ..PdfPage::default()
+        };
+        assert_eq!(PageDrawing::new(&page).replaced, BTreeSet::from(['\u{65e5}', '\u{672c}']));
+        assert_eq!(text_string("Page 1"), Object::string_literal("Page 1"));
+        assert_eq!(text_string("\u{e9}"), Object::String(vec![0xfe, 0xff, 0x00, 0xe9], StringFormat::Hexadecimal));
+    }
+}
@@ -0,0 +1,510 @@
+
+//! The scene tree: how a page's groups nest, and which items belong to each.
+//!
+//! A v6 page is a tree of groups hanging off `ROOT_ID`. The groups directly under
+//! the root are the layers the tablet shows; lines, highlights and text items each
+//! name the group they belong to in their `parent_id`. The blocks describing all
+//! this are scattered through the file, so `SceneTree` gathers them up.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde::Serialize;
+
+use super::types::*;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SceneNode {
+    pub id: CrdtId,
+    pub parent: Option<CrdtId>,
+    pub name: Option<String>,
+    pub visible: bool,
+    /// Groups under this one, in the order they're stacked, bottom first
+    pub groups: Vec<CrdtId>,
+    /// Lines, highlights and text placed directly in this group, in file order
+    pub items: Vec<CrdtId>,
+}
+
+impl SceneNode {
+    fn new(id: CrdtId) -> Self {
+        SceneNode {
+            id,
+            parent: None,
+            name: None,
+            visible: true,
+            groups: Vec::new(),
+            items: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct SceneTree {
+    pub nodes: BTreeMap<CrdtId, SceneNode>,
+    /// The group each item was placed in
+    pub item_parents: HashMap<CrdtId, CrdtId>,
+}
+
+impl SceneTree {
+    pub fn root(&self) -> &SceneNode {
+        &self.nodes[&ROOT_ID]
+    }
+
+    pub fn node(&self, id: CrdtId) -> Option<&SceneNode> {
+        self.nodes.get(&id)
+    }
+
+    /// The groups directly under the root, i.e. the page's layers.
+    pub fn layers(&self) -> impl Iterator<Item = &SceneNode> {
+        self.root().groups.iter().filter_map(|id| self.nodes.get(id))
+    }
+
+    /// The group an item (line, highlight or text) was placed in.
+    pub fn parent_of(&self, item: CrdtId) -> Option<CrdtId> {
+        self.item_parents.get(&item).copied()
+    }
+
+    /// The layer an item or group is in, however deeply it's nested.
+    /// Things placed on the root itself aren't in any layer.
+    pub fn layer_of(&self, id: CrdtId) -> Option<CrdtId> {
+        let mut current = self.parent_of(id).unwrap_or(id);
+        // bounded, in case a broken file has a loop in it
+        for _ in 0..=self.nodes.len() {
+            let parent = self.nodes.get(&current)?.parent?;
+            if parent == ROOT_ID {
+                return Some(current);
+            };
+            current = parent;
+        };
+        None
+    }
+
+    fn node_mut(&mut self, id: CrdtId) -> &mut SceneNode {
+        self.nodes.entry(id).or_insert_with(|| SceneNode::new(id))
+    }
+
+    fn add_group(&mut self, parent: CrdtId, group: CrdtId) {
+        if group == ROOT_ID {
+            return;
+        };
+        self.node_mut(group).parent.get_or_insert(parent);
+        let siblings = &mut self.node_mut(parent).groups;
+        if !siblings.contains(&group) {
+            siblings.push(group);
+        };
+    }
+
+    fn add_item(&mut self, parent: CrdtId, item: CrdtId) {
+        self.item_parents.insert(item, parent);
+        self.node_mut(parent).items.push(item);
+    }
+}
+
+impl From<&Notebook> for SceneTree {
+    fn from(notebook: &Notebook) -> Self {
+        let mut tree = SceneTree::default();
+        tree.node_mut(ROOT_ID);
+        // where each group sits in its parent's sequence: (item, left, right, group)
+        let mut placed: HashMap<CrdtId, Vec<(CrdtId, CrdtId, CrdtId, CrdtId)>> = HashMap::new();
+
+        for block in &notebook.blocks {
+            match block {
+                Block::LayerDef(def) => {
+                    tree.add_group(def.parent_id, def.layer_id);
+                },
+                Block::LayerInfo(info) => {
+                    if let Some(layer_id) = info.layer_id {
+                        tree.add_group(info.id_field_0, layer_id);
+                        placed.entry(info.id_field_0).or_default()
+                              .push((info.id_field_1, info.id_field_2, info.id_field_3, layer_id));
+                    };
+                },
+                Block::LayerName(name) => {
+                    let node = tree.node_mut(name.layer_id);
+                    node.name = Some(name.name.value.clone());
+                    node.visible = name.visible.value;
+                },
+                Block::Line(line) => {
+                    tree.add_item(line.layer_id, line.line_id);
+                },
+                Block::Glyph(Glyph { header, .. }) | Block::TextItem(TextItem { header, .. }) => {
+                    tree.add_item(header.parent_id, header.item_id);
+                },
+                Block::TextDef(text) => {
+                    tree.add_item(ROOT_ID, text.id_field_0);
+                },
+                _ => {},
+            };
+        };
+
+        // groups are stacked in their parent's sequence, whatever order they're
+        // declared in; any that aren't in it stay after, in file order
+        for (parent, infos) in placed {
+            let group_of: HashMap<CrdtId, CrdtId> = infos.iter().map(|(id, _, _, group)| (*id, *group)).collect();
+            let mut groups: Vec<CrdtId> = Vec::new();
+            for id in sequence_order(infos.iter().map(|(id, left, right, _)| (*id, *left, *right))) {
+                if !groups.contains(&group_of[&id]) {
+                    groups.push(group_of[&id]);
+                };
+            };
+            let node = tree.node_mut(parent);
+            groups.extend(node.groups.iter().filter(|g| !groups.contains(g)).copied().collect::<Vec<_>>());
+            node.groups = groups;
+        };
+
+        tree
+    }
+}
+
+/// A layer with everything in it resolved and in the order the tablet draws it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Layer {
+    pub id: CrdtId,
+    pub name: String,
+    pub visible: bool,
+    pub lines: Vec<Line>,
+    pub highlights: Vec<Glyph>,
+    pub text: Vec<TextItem>,
+}
+
+impl Layer {
+    /// Whether `name` picks out this layer: either its name or its id, e.g. `0:11`.
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name || self.id.to_string() == name
+    }
+}
+
+/// One child of a group, with its place in the group's sequence.
+enum Child<'a> {
+    Line(&'a Line),
+    Glyph(&'a Glyph),
+    Text(&'a TextItem),
+    Group(CrdtId),
+    /// Only needed to put its neighbours in order
+    Tombstone,
+}
+
+impl Notebook {
+    /// Every layer on the page, in the order they're stacked. Items in groups
+    /// nested inside a layer are flattened into it; deleted items are left out.
+    pub fn layers(&self) -> Vec<Layer> {
+        self.layers_with(false)
+    }
+
+    /// Like `layers`, but keeping deleted lines and highlights that still have
+    /// something to show, e.g. to recover strokes that were erased.
+    pub fn layers_including_deleted(&self) -> Vec<Layer> {
+        self.layers_with(true)
+    }
+
+    fn layers_with(&self, include_deleted: bool) -> Vec<Layer> {
+        let tree = SceneTree::from(self);
+
+        let mut children: HashMap<CrdtId, Vec<(CrdtId, CrdtId, CrdtId, Child)>> = HashMap::new();
+        for block in &self.blocks {
+            let (parent, child) = match block {
+                Block::Line(l) => (l.layer_id, (l.line_id, l.last_line_id, l.id_field_0, Child::Line(l))),
+                Block::Glyph(g) => {
+                    let h = &g.header;
+                    (h.parent_id, (h.item_id, h.left_id, h.right_id, Child::Glyph(g)))
+                },
+                Block::TextItem(t) => {
+                    let h = &t.header;
+                    (h.parent_id, (h.item_id, h.left_id, h.right_id, Child::Text(t)))
+                },
+                Block::Tombstone(h) => (h.parent_id, (h.item_id, h.left_id, h.right_id, Child::Tombstone)),
+                Block::LayerInfo(i) => match i.layer_id {
+                    Some(group) => (i.id_field_0, (i.id_field_1, i.id_field_2, i.id_field_3, Child::Group(group))),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            children.entry(parent).or_default().push(child);
+        };
+
+        tree.layers().map(|node| {
+            let mut layer = Layer {
+                id: node.id,
+                name: node.name.clone().unwrap_or_default(),
+                visible: node.visible,
+                lines: Vec::new(),
+                highlights: Vec::new(),
+                text: Vec::new(),
+            };
+            collect_group(&children, node.id, &mut layer, include_deleted, 0);
+            layer
+        }).collect()
+    }
+}
+
+impl Notebook {
+    /// The id of the layer picked out by `name` (see `Layer::matches`).
+    pub fn find_layer(&self, name: &str) -> Option<CrdtId> {
+        self.layers().into_iter().find(|l| l.matches(name)).map(|l| l.id)
+    }
+
+    /// Every layer, or just the one picked out by `only` (see `Layer::matches`),
+    /// which must exist.
+    pub fn select_layers(&self, only: Option<&str>, include_deleted: bool) -> Result<Vec<Layer>, RMError> {
+        let layers = self.layers_with(include_deleted);
+        match only {
+            None => Ok(layers),
+            Some(name) => {
+                let found: Vec<Layer> = layers.into_iter().filter(|l| l.matches(name)).collect();
+                if found.is_empty() {
+                    Err(RMError::ArgsError(format!("no layer named {:?}", name)))?
+                };
+                Ok(found)
+            },
+        }
+    }
+}
+
+fn collect_group(
+    children: &HashMap<CrdtId, Vec<(CrdtId, CrdtId, CrdtId, Child)>>,
+    group: CrdtId,
+    layer: &mut Layer,
+    include_deleted: bool,
+    depth: usize,
+) {
+    let Some(items) = children.get(&group) else { return };
+    // bounded, in case a broken file has a loop in it
+    if depth > children.len() {
+        return;
+    };
+
+    let order = sequence_order(items.iter().map(|(id, left, right, _)| (*id, *left, *right)));
+    let by_id: HashMap<CrdtId, &Child> = items.iter().map(|(id, _, _, c)| (*id, c)).collect();
+    for id in order {
+        match by_id[&id] {
+            Child::Line(l) if include_deleted || !l.is_deleted() => layer.lines.push((*l).clone()),
+            Child::Glyph(g) if include_deleted || !g.header.is_deleted() => layer.highlights.push((*g).clone()),
+            Child::Text(t) if !t.header.is_deleted() => layer.text.push((*t).clone()),
+            Child::Group(g) => collect_group(children, *g, layer, include_deleted, depth + 1),
+            _ => {},
+        };
+    };
+}
+
+/// A place in a CRDT sequence: one of its ends, or an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum SeqNode {
+    Start,
+    Item(CrdtId),
+    End,
+}
+
+/// Put the items of a CRDT sequence in order, given each item's `(id, left, right)`.
+///
+/// Each item sits somewhere after its left neighbour and before its right one, with
+/// 0:0 standing for the ends of the sequence. Items that are equally placed go in
+/// id order. Anything that can't be placed (only possible in a broken file) is
+/// added at the end in the order it was given.
+pub fn sequence_order<I>(items: I) -> Vec<CrdtId>
+    where I: IntoIterator<Item = (CrdtId, CrdtId, CrdtId)>
+{
+    const END: CrdtId = CrdtId::new(0, 0);
+
+    let items: Vec<(CrdtId, CrdtId, CrdtId)> = items.into_iter().collect();
+    let known: HashSet<CrdtId> = items.iter().map(|i| i.0).collect();
+
+    // each item comes after its left neighbour, and its right neighbour after it
+    let mut after: BTreeMap<SeqNode, BTreeSet<SeqNode>> = BTreeMap::new();
+    for (id, left, right) in &items {
+        let left = if *left == END { SeqNode::Start } else { SeqNode::Item(*left) };
+        let right = if *right == END { SeqNode::End } else { SeqNode::Item(*right) };
+        after.entry(SeqNode::Item(*id)).or_default().insert(left);
+        after.entry(right).or_default().insert(SeqNode::Item(*id));
+    };
+    let mentioned: Vec<SeqNode> = after.values().flatten().copied().collect();
+    for node in mentioned {
+        after.entry(node).or_default();
+    };
+
+    // Kahn's algorithm, a round at a time so that items placed equally are sorted
+    let mut waiting: HashMap<SeqNode, usize> = HashMap::new();
+    let mut dependents: HashMap<SeqNode, Vec<SeqNode>> = HashMap::new();
+    for (node, deps) in &after {
+        waiting.insert(*node, deps.len());
+        for dep in deps {
+            dependents.entry(*dep).or_default().push(*node);
+        };
+    };
+
+    let mut order = Vec::with_capacity(items.len());
+    let mut ready: BTreeSet<SeqNode> = waiting.iter()
+                                              .filter(|(node, n)| **n == 0 && **node != SeqNode::End)
+                                              .map(|(node, _)| *node)
+                                              .collect();
+    while !ready.is_empty() {
+        let mut next = BTreeSet::new();
+        for node in &ready {
+            if let SeqNode::Item(id) = node {
+                if known.contains(id) {
+                    order.push(*id);
+                };
+            };
+            for dependent in dependents.get(node).into_iter().flatten() {
+                let n = waiting.get_mut(dependent).expect("every node is counted");
+                *n -= 1;
+                if *n == 0 && *dependent != SeqNode::End {
+                    next.insert(*dependent);
+                };
+            };
+        };
+        ready = next;
+    };
+
+    let mut placed: HashSet<CrdtId> = order.iter().copied().collect();
+    for (id, _, _) in items {
+        if placed.insert(id) {
+            order.push(id);
+        };
+    };
+    order
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::*;
+
+    use std::fs::read;
+
+    #[test]
+    fn lines_are_in_layers() {
+        let bytes = read(TEST_FILE_01).unwrap();
+        let notebook = parse_full(&bytes).unwrap();
+        let tree = SceneTree::from(&notebook);
+
+        let layers: Vec<&SceneNode> = tree.layers().collect();
+        assert!(!layers.is_empty());
+        assert_eq!(layers[0].name.as_deref(), Some("Layer 1"));
+
+        for block in &notebook.blocks {
+            if let Block::Line(line) = block {
+                let layer = tree.layer_of(line.line_id).unwrap();
+                assert_eq!(tree.node(layer).unwrap().parent, Some(ROOT_ID));
+                assert!(tree.node(line.layer_id).unwrap().items.contains(&line.line_id));
+            };
+        };
+    }
+
+    #[test]
+    fn nested_groups() {
+        let mut tree = SceneTree::default();
+        tree.node_mut(ROOT_ID);
+        let (layer, group, line) = (CrdtId::new(0, 11), CrdtId::new(1, 20), CrdtId::new(1, 21));
+        tree.add_group(ROOT_ID, layer);
+        tree.add_group(layer, group);
+        tree.add_item(group, line);
+
+        assert_eq!(tree.parent_of(line), Some(group));
+        assert_eq!(tree.layer_of(line), Some(layer));
+        assert_eq!(tree.layer_of(group), Some(layer));
+        assert_eq!(tree.layer_of(ROOT_ID), None);
+    }
+
+    #[test]
+    fn layer_order() {
+        let (first, second) = (CrdtId::new(0, 11), CrdtId::new(1, 30));
+        let end = CrdtId::new(0, 0);
+        let layer = |layer_id, parent_id| Block::LayerDef(LayerDef {
+            layer_id,
+            node_id: end,
+            is_update: true,
+            parent_id,
+        });
+        let place = |item, left, layer_id| Block::LayerInfo(LayerInfo {
+            id_field_0: ROOT_ID,
+            id_field_1: item,
+            id_field_2: left,
+            id_field_3: end,
+            deleted_length: 0,
+            layer_id: Some(layer_id),
+        });
+        // the second layer is declared first, but stacked above the first
+        let notebook = Notebook {
+            frontmatter: Frontmatter::new(6),
+            blocks: vec!(
+                layer(second, ROOT_ID),
+                place(CrdtId::new(1, 31), CrdtId::new(0, 13), second),
+                layer(first, ROOT_ID),
+                place(CrdtId::new(0, 13), end, first),
+            ),
+        };
+        let tree = SceneTree::from(&notebook);
+        let ids: Vec<CrdtId> = tree.layers().map(|l| l.id).collect();
+        assert_eq!(ids, vec!(first, second));
+        let ids: Vec<CrdtId> = notebook.layers().iter().map(|l| l.id).collect();
+        assert_eq!(ids, vec!(first, second));
+    }
+
+    #[test]
+    fn order_sequence() {
+        let id = |n| CrdtId::new(1, n);
+        let end = CrdtId::new(0, 0);
+        // 3 was inserted between 1 and 2 after they were written; 4 went on the end
+        let items = [
+            (id(4), id(2), end),
+            (id(2), id(1), end),
+            (id(3), id(1), id(2)),
+            (id(1), end, end),
+        ];
+        assert_eq!(sequence_order(items), vec!(id(1), id(3), id(2), id(4)));
+    }
+
+    #[test]
+    fn page_layers() {
+        let bytes = read(TEST_FILE_01).unwrap();
+        let notebook = parse_full(&bytes).unwrap();
+        let layers = notebook.layers();
+
+        let num_lines = notebook.blocks.iter().filter(|b| matches!(b, Block::Line(_))).count();
+        assert_eq!(layers.iter().map(|l| l.lines.len()).sum::<usize>(), num_lines);
+        assert!(layers[0].matches("Layer 1"));
+        assert!(layers[0].matches("0:11"));
+        assert!(layers[0].visible);
+    }
+
+    #[test]
+    fn deleted_items() {
+        let id = |n| CrdtId::new(1, n);
+        let layer = CrdtId::new(0, 11);
+        let line = |n, left, deleted_length| Block::Line(Line {
+            layer_id: layer,
+            line_id: id(n),
+            last_line_id: left,
+            deleted_length,
+            points: vec!(Point::default()),
+            ..Line::default()
+        });
+        let notebook = Notebook {
+            frontmatter: Frontmatter::new(6),
+            blocks: vec!(
+                Block::LayerDef(LayerDef {
+                    layer_id: layer,
+                    node_id: CrdtId::default(),
+                    is_update: true,
+                    parent_id: ROOT_ID,
+                }),
+                line(24, id(23), 0),
+                Block::Tombstone(ItemHeader {
+                    parent_id: layer,
+                    item_id: id(21),
+                    left_id: id(20),
+                    deleted_length: 1,
+                    ..ItemHeader::default()
+                }),
+                line(20, CrdtId::default(), 0),
+                line(23, id(22), 0),
+                line(22, id(21), 1),
+            ),
+        };
+
+        let ids = |layers: Vec<Layer>| -> Vec<CrdtId> {
+            layers[0].lines.iter().map(|l| l.line_id).collect()
+        };
+        assert_eq!(ids(notebook.layers()), vec!(id(20), id(23), id(24)));
+        assert_eq!(ids(notebook.layers_including_deleted()), vec!(id(20), id(22), id(23), id(24)));
+    }
+}
@@ -3,156 +3,609 @@ use std::io::Write;
 
 mod s {
     pub use svg::Document;
-    pub use svg::node::element::Path;
-    pub use svg::node::element::path::{Command, Data, Position, Parameters};
+    pub use svg::Node;
+    pub use svg::node::element::{Group, Path, Text};
+    pub use svg::node::Text as TextNode;
+    pub use svg::node::Attributes;
+    pub use svg::node::element::path::Data;
     pub use svg::parser::Event;
+    pub use svg::node::element::tag::{self, Type};
 }
 
 use crate::types::*;
+use crate::scene::Layer;
+use crate::content::Content;
+use crate::text::{Paragraph, ParagraphStyle, RichText};
+use crate::geometry::{flatten_path, parse_numbers, Transform, DEFAULT_TOLERANCE};
 
 type Result<T> = std::result::Result<T, RMError>;
 
 pub const HALF_WIDTH: f32 = 702.0;
 pub const WIDTH: f32 = 1404.0;
 pub const HEIGHT: f32 = 1872.0;
+/// Dots per inch of the page coordinates.
+pub const DPI: f64 = 226.0;
 
-impl TryFrom<s::Data> for SimpleLine {
-    type Error = RMError;
-
-    fn try_from(data: s::Data) -> Result<SimpleLine> {
-        let base_line = SimpleLine::default();
-        let mut curr_position: (f32, f32) = (0.0, 0.0);
-        let mut curr_id = IdField::default();
-        let last_id = IdField::default();
-        let mut lines: Vec<SimpleLine> = Vec::new();
-
-        for cmd in data.iter() {
-            let mut line = base_line.clone();
-            line.line_id = curr_id;
-            line.last_line_id = last_id.clone();
-            curr_id = last_id.clone();
-            let pts = match cmd {
-                s::Command::Move(pos,params) => {
-                    cmd_to_xy(&mut curr_position, pos, params)
-                },
-                s::Command::Line(pos,params) => {
-                    cmd_to_xy(&mut curr_position, pos, params)
-                },
-                s::Command::CubicCurve(pos,params) => {
-                    let pts = cmd_to_xy(&mut curr_position, pos, params);
-                    cubic_to_points(
-                        pts.get(0).unwrap().clone(), 
-                        pts.get(1).unwrap().clone(), 
-                        pts.get(2).unwrap().clone(), 
-                    )
-                },
-                _ => {
-                    Err(RMError::NotImplementedError)?
-                },
+/// Turn path data into lines, one per subpath, with curves flattened to within
+/// `geometry::DEFAULT_TOLERANCE` once `transform` has been applied.
+pub fn lines_from_data(data: &s::Data, transform: &Transform) -> Result<Vec<SimpleLine>> {
+    let tolerance = DEFAULT_TOLERANCE / transform.scale_factor().max(1e-6);
+    let subpaths = flatten_path(data, tolerance)?;
+    Ok(subpaths.into_iter().map(|points| SimpleLine {
+        points: points.into_iter()
+                      .map(|p| {
+                          let (x, y) = transform.apply(p);
+                          SimplePoint::from((x as f32, y as f32))
+                      })
+                      .collect(),
+        ..SimpleLine::default()
+    }).collect())
+}
+
+/// The lines drawn by one SVG element, inside groups whose transforms add up to
+/// `ctm`. Paths and the basic shapes are drawn; anything else draws nothing.
+pub fn lines_from_event(event: &s::Event, ctm: &Transform) -> Result<Vec<Line>> {
+    let data = match event {
+        s::Event::Tag(tag, s::Type::Start | s::Type::Empty, attributes) => shape_data(tag, attributes)?,
+        _ => None,
+    };
+    match (data, event) {
+        (Some(data), s::Event::Tag(_, _, attributes)) => {
+            let ctm = *ctm * element_transform(attributes)?;
+            let lines = lines_from_data(&data, &ctm)?;
+            Ok(lines.into_iter().map(Line::from).collect())
+        },
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// The outline of a path or basic shape, as path data. `None` for other elements,
+/// and for shapes that don't render, like a circle with no radius.
+fn shape_data(tag: &str, attributes: &s::Attributes) -> Result<Option<s::Data>> {
+    let number = |name: &str, reference: f32| -> Result<Option<f64>> {
+        match attributes.get(name) {
+            None => Ok(None),
+            Some(value) => parse_length(value, reference as f64)
+                .map(Some)
+                .ok_or_else(|| RMError::ArgsError(format!("bad {}: {:?}", name, value.to_string()))),
+        }
+    };
+    let x = |name: &str| Ok::<f64, RMError>(number(name, WIDTH)?.unwrap_or(0.0));
+    let y = |name: &str| Ok::<f64, RMError>(number(name, HEIGHT)?.unwrap_or(0.0));
+
+    let d = match tag {
+        s::tag::Path => match attributes.get("d") {
+            Some(d) => d.to_string(),
+            None => return Ok(None),
+        },
+        s::tag::Rectangle => {
+            let (left, top, w, h) = (x("x")?, y("y")?, x("width")?, y("height")?);
+            if w <= 0.0 || h <= 0.0 {
+                return Ok(None);
             };
-            let points: Vec<SimplePoint> = pts.into_iter().map(SimplePoint::from).collect();
-            line.points = points;
-            lines.push(line);
-        };
-        Ok(SimpleLine::default())
-    }
-}
-
-impl <'a>TryFrom<s::Event<'a>> for Line {
-    type Error = RMError;
-
-    fn try_from(event: s::Event) -> Result<Line> {
-        match event {
-            s::Event::Tag(tag, _, attributes) => {
-                if tag==svg::node::element::tag::Path {
-                    let data = attributes.get("d").ok_or(RMError::NotImplementedError)?;
-                    let data = s::Data::parse(data)?;
-                    let mut simple_line = SimpleLine::try_from(data)?;
-                    match attributes.get("transform") {
-                        None => {},
-                        Some(tfm) => {
-                            simple_line.transform(tfm)?;
-                        },
-                    };
-                    Ok(Line::from(simple_line))
-                }
-                else {
-                    Err(RMError::NotImplementedError)
+            // a missing radius is the same as the other one
+            let (rx, ry) = match (number("rx", WIDTH)?, number("ry", HEIGHT)?) {
+                (Some(rx), Some(ry)) => (rx, ry),
+                (Some(r), None) | (None, Some(r)) => (r, r),
+                (None, None) => (0.0, 0.0),
+            };
+            let (rx, ry) = (rx.clamp(0.0, w / 2.0), ry.clamp(0.0, h / 2.0));
+            let (right, bottom) = (left + w, top + h);
+            if rx == 0.0 || ry == 0.0 {
+                format!("M {left} {top} H {right} V {bottom} H {left} Z")
+            } else {
+                let arc = format!("A {rx} {ry} 0 0 1");
+                format!("M {} {top} H {} {arc} {right} {} V {} {arc} {} {bottom} H {} {arc} {left} {} V {} {arc} {} {top} Z",
+                        left + rx, right - rx, top + ry, bottom - ry, right - rx, left + rx, bottom - ry, top + ry, left + rx)
+            }
+        },
+        s::tag::Circle | s::tag::Ellipse => {
+            let (cx, cy) = (x("cx")?, y("cy")?);
+            let (rx, ry) = if tag == s::tag::Circle {
+                let r = x("r")?;
+                (r, r)
+            } else {
+                match (number("rx", WIDTH)?, number("ry", HEIGHT)?) {
+                    (Some(rx), Some(ry)) => (rx, ry),
+                    (Some(r), None) | (None, Some(r)) => (r, r),
+                    (None, None) => (0.0, 0.0),
                 }
-            },
-            _ => {
-                Err(RMError::NotImplementedError)
-            },
-        }
+            };
+            if rx <= 0.0 || ry <= 0.0 {
+                return Ok(None);
+            };
+            format!("M {} {cy} A {rx} {ry} 0 0 1 {} {cy} A {rx} {ry} 0 0 1 {} {cy} Z", cx + rx, cx - rx, cx + rx)
+        },
+        s::tag::Line => {
+            format!("M {} {} L {} {}", x("x1")?, y("y1")?, x("x2")?, y("y2")?)
+        },
+        s::tag::Polyline | s::tag::Polygon => {
+            let points = attributes.get("points").map(|p| p.to_string()).unwrap_or_default();
+            let numbers = parse_numbers(&points)
+                .ok_or_else(|| RMError::ArgsError(format!("bad points: {:?}", points)))?;
+            // an odd number out is an error, but everything before it is still drawn
+            let pairs: Vec<String> = numbers.chunks_exact(2).map(|p| format!("{} {}", p[0], p[1])).collect();
+            if pairs.len() < 2 {
+                return Ok(None);
+            };
+            let close = if tag == s::tag::Polygon { " Z" } else { "" };
+            format!("M {}{}", pairs.join(" L "), close)
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(s::Data::parse(&d)?))
+}
+
+/// An element's own `transform` attribute.
+fn element_transform(attributes: &s::Attributes) -> Result<Transform> {
+    match attributes.get("transform") {
+        Some(tfm) => tfm.parse(),
+        None => Ok(Transform::IDENTITY),
     }
 }
 
-// TODO: make `num_points` not-magic
-pub fn cubic_to_points(p1: (f32,f32), p2: (f32, f32), pe: (f32, f32)) -> Vec<(f32,f32)> {
-    let mut points = Vec::new();
+/// The transform an `<svg>` element sets up for its children: its viewBox mapped
+/// onto its viewport. The page is the viewport of the outermost `<svg>` if it
+/// doesn't give a size, so our own exports come back where they started.
+fn viewport_transform(attributes: &s::Attributes) -> Result<Transform> {
+    let length = |name: &str, reference: f32| -> Result<f64> {
+        match attributes.get(name) {
+            None => Ok(reference as f64),
+            Some(value) => parse_length(value, reference as f64)
+                .ok_or_else(|| RMError::ArgsError(format!("bad {}: {:?}", name, value.to_string()))),
+        }
+    };
+    let viewport = (length("x", 0.0)?, length("y", 0.0)?, length("width", WIDTH)?, length("height", HEIGHT)?);
 
-    let num_points = 20;
-    for _t in 0..num_points {
-        let t = (_t as f32)/(num_points as f32);
+    let view_box = match attributes.get("viewBox") {
+        None => return Ok(Transform::translate(viewport.0, viewport.1)),
+        Some(value) => match parse_numbers(value).as_deref() {
+            Some(&[x, y, w, h]) => (x, y, w, h),
+            _ => return Err(RMError::ArgsError(format!("bad viewBox: {:?}", value.to_string()))),
+        },
+    };
+    let preserve = attributes.get("preserveAspectRatio").map(|v| v.to_string());
+    Transform::view_box(view_box, viewport, preserve.as_deref())
+}
 
-        let x = (1.0-t)*(1.0-t)*p1.0 + 2.0*t*(1.0-t)*p2.0 + t*t*pe.0;
-        let y = (1.0-t)*(1.0-t)*p1.1 + 2.0*t*(1.0-t)*p2.1 + t*t*pe.1;
-        points.push((x,y));
+/// Parse an SVG length into page units. Physical units are converted at the
+/// tablet's resolution, so a 10mm line in the SVG is 10mm long on the page;
+/// percentages are of `reference`.
+pub fn parse_length(value: &str, reference: f64) -> Option<f64> {
+    let value = value.trim();
+    let split = value.find(|c: char| c.is_ascii_alphabetic() || c == '%').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.trim().parse().ok()?;
+    let scale = match unit {
+        "" | "px" => 1.0,
+        "in" => DPI,
+        "cm" => DPI / 2.54,
+        "mm" => DPI / 25.4,
+        "pt" => DPI / 72.0,
+        "pc" => DPI / 6.0,
+        "%" => reference / 100.0,
+        _ => return None,
     };
-    points
+    Some(number * scale)
 }
 
-fn cmd_to_xy(curr_pos: &mut (f32, f32), pos: &s::Position, params: &s::Parameters) -> Vec<(f32, f32)> {
+/// What a percentage `stroke-width` is of: the page's diagonal over the square
+/// root of two, as SVG has it.
+const STROKE_REFERENCE: f64 = 1654.63;
 
-    let mut out = Vec::new();
+/// How strokes from one pen and color look. The numbers are eyeballed against the
+/// tablet, not taken from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PenStyle {
+    /// `#rrggbb`
+    pub color: String,
+    pub opacity: f64,
+    /// `stroke-linecap`
+    pub cap: &'static str,
+    /// Whether the width changes along the stroke, with pressure and speed.
+    pub variable_width: bool,
+}
 
-    for _i in 0..params.len()/2 {
-        let i = _i*2;
+/// The RGB color for a pen color. Unknown colors are drawn black.
+pub fn pen_color(color: PenColor) -> &'static str {
+    match color {
+        PenColor::Black => "#000000",
+        PenColor::Gray => "#909090",
+        PenColor::White => "#ffffff",
+        PenColor::Yellow => "#fbf719",
+        PenColor::Green => "#00ff00",
+        PenColor::Pink => "#ffc0cb",
+        PenColor::Blue => "#4e69c9",
+        PenColor::Red => "#b33e39",
+        PenColor::GrayOverlap => "#7d7d7d",
+        PenColor::Highlight => "#ffed75",
+        PenColor::Green2 => "#a1d87d",
+        PenColor::Cyan => "#8bd0e5",
+        PenColor::Magenta => "#b782cd",
+        PenColor::Yellow2 => "#f7e851",
+        PenColor::Unknown(_) => "#000000",
+    }
+}
 
-        let this = match pos {
-            s::Position::Absolute => {
-                let x = params.get(i).unwrap().to_owned();
-                let y = params.get(i+1).unwrap().to_owned();
-                (x,y)
-            },
-            s::Position::Relative => {
-                let x = curr_pos.0 + params.get(i).unwrap().to_owned();
-                let y = curr_pos.1 + params.get(i+1).unwrap().to_owned();
-                (x,y)
-            },
-        };
+/// The pen color closest to an RGB color.
+pub fn nearest_pen_color((r, g, b): (u8, u8, u8)) -> PenColor {
+    let distance = |color: &PenColor| {
+        let (r2, g2, b2) = parse_color(pen_color(*color)).unwrap_or_default();
+        [(r, r2), (g, g2), (b, b2)].iter()
+                                   .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+                                   .sum::<i32>()
+    };
+    (0..=13).map(PenColor::from)
+            .filter(|c| !matches!(c, PenColor::GrayOverlap | PenColor::Highlight))
+            .min_by_key(distance)
+            .unwrap_or_default()
+}
 
-        out.push(this);
+/// Parse an SVG color: `#rgb`, `#rrggbb`, `rgb(r, g, b)` or a few common names.
+/// `none` and anything else unrecognized give None.
+pub fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    let color = color.trim().to_lowercase();
+    let hex = |h: &str| u8::from_str_radix(h, 16).ok();
+
+    if let Some(h) = color.strip_prefix('#') {
+        return match h.len() {
+            3 => Some((hex(&h[0..1])? * 17, hex(&h[1..2])? * 17, hex(&h[2..3])? * 17)),
+            6 => Some((hex(&h[0..2])?, hex(&h[2..4])?, hex(&h[4..6])?)),
+            _ => None,
+        };
     };
-    match out.last() {
-        None => {},
-        Some(last) => {
-            _ = std::mem::replace(curr_pos, last.to_owned());
-        },
+    if let Some(args) = color.strip_prefix("rgb(").and_then(|c| c.strip_suffix(')')) {
+        let parts: Vec<u8> = args.split(',')
+                                 .map(|p| p.trim().parse::<f64>().map(|v| v.clamp(0.0, 255.0) as u8))
+                                 .collect::<std::result::Result<_, _>>()
+                                 .ok()?;
+        return match parts[..] {
+            [r, g, b] => Some((r, g, b)),
+            _ => None,
+        };
     };
-    out
+    match &color[..] {
+        "black" => Some((0, 0, 0)),
+        "white" => Some((255, 255, 255)),
+        "gray" | "grey" => Some((128, 128, 128)),
+        "red" => Some((255, 0, 0)),
+        "green" => Some((0, 128, 0)),
+        "lime" => Some((0, 255, 0)),
+        "blue" => Some((0, 0, 255)),
+        "yellow" => Some((255, 255, 0)),
+        "pink" => Some((255, 192, 203)),
+        "cyan" => Some((0, 255, 255)),
+        "magenta" => Some((255, 0, 255)),
+        _ => None,
+    }
 }
 
+/// The stroke styling of an SVG element, from its `stroke`, `stroke-width` and
+/// `stroke-opacity` attributes or the same properties in its `style`. The width
+/// is in the element's user units.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SvgStroke {
+    pub color: Option<(u8, u8, u8)>,
+    pub width: Option<f64>,
+    pub opacity: Option<f64>,
+}
 
-pub fn path_from_line(line: &Line) -> Option<s::Path> {
-    let mut points = line.points.clone().into_iter();
-    let Point{x:start_x, y:start_y,..} = points.next()?;
+impl SvgStroke {
+    pub fn from_attributes(attributes: &svg::node::Attributes) -> Self {
+        let mut props: Vec<(String, String)> = attributes.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        // `style` wins over the presentation attributes
+        if let Some(style) = attributes.get("style") {
+            props.extend(style.split(';').filter_map(|decl| {
+                let (k, v) = decl.split_once(':')?;
+                Some((k.trim().to_string(), v.trim().to_string()))
+            }));
+        };
+
+        let mut stroke = SvgStroke::default();
+        for (key, value) in props {
+            match &key[..] {
+                "stroke" => stroke.color = parse_color(&value),
+                "stroke-width" => stroke.width = parse_length(&value, STROKE_REFERENCE),
+                "stroke-opacity" => stroke.opacity = value.parse().ok(),
+                _ => {},
+            };
+        };
+        stroke
+    }
+
+    /// The element's stroke, with what it doesn't set taken from its parent's.
+    pub fn inherit(self, parent: &SvgStroke) -> Self {
+        SvgStroke {
+            color: self.color.or(parent.color),
+            width: self.width.or(parent.width),
+            opacity: self.opacity.or(parent.opacity),
+        }
+    }
+
+    /// Style `line` to match. See-through strokes become highlighter.
+    pub fn apply(&self, line: &mut Line) {
+        let pen = self.opacity.filter(|o| *o < 0.5).map(|_| PenType::Highlighter2);
+        line.restyle(pen, self.color.map(nearest_pen_color), self.width);
+    }
+}
+
+/// The style for a pen, or None for pens that don't leave a mark (the area eraser).
+pub fn pen_style(pen_type: PenType, color: PenColor) -> Option<PenStyle> {
+    use PenType::*;
+
+    let style = |opacity, cap, variable_width| Some(PenStyle {
+        color: pen_color(color).to_string(),
+        opacity,
+        cap,
+        variable_width,
+    });
 
-    let mut data = s::Data::new().move_to((start_x+HALF_WIDTH, start_y));
+    match pen_type {
+        Paintbrush1 | Paintbrush2 => style(1.0, "round", true),
+        Pencil1 | Pencil2 => style(0.9, "round", true),
+        Ballpoint1 | Ballpoint2 => style(1.0, "round", true),
+        Marker1 | Marker2 => style(1.0, "round", true),
+        Fineliner1 | Fineliner2 => style(1.0, "round", false),
+        Highlighter1 | Highlighter2 => style(0.3, "square", false),
+        // painted over in white
+        Eraser => Some(PenStyle {
+            color: pen_color(PenColor::White).to_string(),
+            opacity: 1.0,
+            cap: "round",
+            variable_width: false,
+        }),
+        // the outline of an erased area, not a mark
+        EraserArea => None,
+        MechanicalPencil1 | MechanicalPencil2 => style(0.8, "round", false),
+        Calligraphy => style(1.0, "round", true),
+        Shader => style(0.1, "round", false),
+        Unknown(_) => style(1.0, "round", false),
+    }
+}
+
+/// The width of the stroke at `point`, in page units.
+///
+/// Points store four times the width the tablet draws. Pencils and ballpoints also
+/// get a little lighter as they go faster and heavier as they're pressed harder.
+pub fn point_width(pen_type: PenType, point: &Point) -> f64 {
+    let width = point.width as f64 / 4.0;
+    let speed = point.speed as f64 / 4.0;
+    let pressure = point.pressure as f64 / 255.0;
 
-    for Point {x,y,..} in points {
-        data = data.line_to((x+HALF_WIDTH, y));
+    let w = match pen_type {
+        PenType::Ballpoint1 | PenType::Ballpoint2 => width * (0.7 + 0.5 * pressure) - 0.01 * speed,
+        PenType::Pencil1 | PenType::Pencil2 => width * (0.5 + 0.5 * pressure) - 0.005 * speed,
+        _ => width,
     };
+    w.max(0.5)
+}
 
+/// The centre line of `points` as SVG path data.
+fn polyline(points: &[Point]) -> Option<s::Data> {
+    let (first, rest) = points.split_first()?;
+    let mut data = s::Data::new().move_to((first.x+HALF_WIDTH, first.y));
+    for Point {x,y,..} in rest {
+        data = data.line_to((x+HALF_WIDTH, *y));
+    };
+    Some(data)
+}
+
+/// A plain path along the line, with no styling.
+pub fn path_from_line(line: &Line) -> Option<s::Path> {
     Some(
         s::Path::new()
         .set("fill", "none")
-        .set("stroke", "black")
-        .set("stroke-width", 3)
-        .set("d", data)
+        .set("d", polyline(&line.points)?)
     )
 }
 
+/// The SVG for a line, styled for its pen, color and width.
+///
+/// Pens with a constant width make a single path. Pens whose width varies make a
+/// path per segment, each as wide as the stroke at its start, with round caps to
+/// hide the joins.
+pub fn paths_from_line(line: &Line) -> Vec<s::Path> {
+    let pen_type = line.pen_type.unwrap_or_default();
+    let Some(style) = pen_style(pen_type, line.color.unwrap_or_default()) else {
+        return Vec::new();
+    };
+    let styled = |path: s::Path, width: f64| {
+        path.set("stroke", style.color.clone())
+            .set("stroke-width", format!("{:.3}", width))
+            .set("stroke-linecap", style.cap)
+            .set("stroke-linejoin", "round")
+            .set("stroke-opacity", style.opacity)
+    };
+
+    if style.variable_width && line.points.len() > 1 {
+        line.points.windows(2).filter_map(|pair| {
+            let path = s::Path::new().set("fill", "none").set("d", polyline(pair)?);
+            Some(styled(path, point_width(pen_type, &pair[0])))
+        }).collect()
+    } else {
+        let widths: Vec<f64> = line.points.iter().map(|p| point_width(pen_type, p)).collect();
+        let mean = widths.iter().sum::<f64>() / widths.len().max(1) as f64;
+        path_from_line(line).map(|p| styled(p, mean)).into_iter().collect()
+    }
+}
+
+/// An SVG group holding every line in `layer`, labelled so that Inkscape and the
+/// like show it as a layer. Hidden layers are kept, but not displayed.
+pub fn group_from_layer(layer: &Layer) -> s::Group {
+    let mut group = s::Group::new()
+        .set("id", format!("layer-{}-{}", layer.id.author, layer.id.counter))
+        .set("inkscape:groupmode", "layer")
+        .set("inkscape:label", layer.name.clone());
+    if !layer.visible {
+        group = group.set("display", "none");
+    };
+
+    for line in &layer.lines {
+        for mut path in paths_from_line(line) {
+            if line.is_deleted() {
+                path = path.set("class", "deleted")
+                           .set("stroke-dasharray", "4 4")
+                           .set("opacity", 0.5);
+            };
+            group = group.add(path);
+        };
+    };
+    group
+}
+
+/// One group per layer, or just the layer picked out by `only` (see `Layer::matches`).
+///
+/// With `include_deleted`, erased lines that still have their points are drawn too,
+/// dashed and with `class="deleted"`.
+pub fn groups_from_notebook(notebook: &Notebook, only: Option<&str>, include_deleted: bool) -> Result<Vec<s::Group>> {
+    let layers = notebook.select_layers(only, include_deleted)?;
+    Ok(layers.iter().map(group_from_layer).collect())
+}
+
+/// How typed text is laid out. The tablet doesn't store sizes in the page, so the
+/// defaults are for its medium text size; `from_content` scales them by a
+/// document's text settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextLayout {
+    /// Font size, in page units
+    pub font_size: f64,
+    /// Distance from one line's baseline to the next, in page units
+    pub line_height: f64,
+    /// Average width of a character, as a fraction of the font size. We don't know
+    /// the font the text ends up in, so lines are wrapped using this.
+    pub char_width: f64,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        TextLayout {
+            font_size: 30.0,
+            line_height: 70.0,
+            char_width: 0.5,
+        }
+    }
+}
+
+impl TextLayout {
+    /// The layout for a document's `.content`: `textScale` scales the text, and
+    /// `lineHeight`, when it's set, is the line spacing as a percentage of the default.
+    pub fn from_content(content: &Content) -> Self {
+        let default = TextLayout::default();
+        let scale = content.text_scale.as_ref().and_then(|s| s.as_f64()).filter(|s| *s > 0.0).unwrap_or(1.0);
+        let spacing = content.line_height.filter(|h| *h > 0).map_or(1.0, |h| h as f64 / 100.0);
+        TextLayout {
+            font_size: default.font_size * scale,
+            line_height: default.line_height * scale * spacing,
+            ..default
+        }
+    }
+
+    /// How much bigger a paragraph style's text is than plain text.
+    pub fn scale(style: ParagraphStyle) -> f64 {
+        match style {
+            ParagraphStyle::Heading => 1.5,
+            _ => 1.0,
+        }
+    }
+
+    /// How many characters of a paragraph in `style` fit on a line `width` units wide.
+    pub fn chars_per_line(&self, width: f32, style: ParagraphStyle) -> usize {
+        let char_width = self.font_size * TextLayout::scale(style) * self.char_width;
+        (width as f64 / char_width).floor().max(1.0) as usize
+    }
+
+    /// The paragraph's lines once wrapped to a text box `width` units wide, with
+    /// `bullet` starting the first one: what each line starts with, how far its
+    /// text is indented, and its text. Later lines line up with the first's text.
+    pub fn wrap(&self, paragraph: &Paragraph, width: f32, bullet: &'static str) -> Vec<(&'static str, f64, Paragraph)> {
+        let indent = bullet.chars().count();
+        let char_width = self.font_size * TextLayout::scale(paragraph.style) * self.char_width;
+        let lines = paragraph.wrap(self.chars_per_line(width, paragraph.style).saturating_sub(indent));
+        lines.into_iter().enumerate().map(|(i, line)| match i {
+            0 => (bullet, 0.0, line),
+            _ => ("", indent as f64 * char_width, line),
+        }).collect()
+    }
+}
+
+/// What starts the first line of a paragraph in `style`.
+pub fn bullet(style: ParagraphStyle) -> &'static str {
+    match style {
+        ParagraphStyle::Bullet | ParagraphStyle::Bullet2 => "\u{2022} ",
+        ParagraphStyle::Checkbox => "\u{2610} ",
+        ParagraphStyle::CheckboxChecked => "\u{2611} ",
+        _ => "",
+    }
+}
+
+/// The page's typed text, one `<text>` per line, placed where the tablet's text
+/// box is. Paragraphs are wrapped to the box's width.
+pub fn group_from_text(text: &TextDef, layout: &TextLayout) -> s::Group {
+    let x = text.pos_x + HALF_WIDTH as f64;
+    let mut group = s::Group::new()
+        .set("class", "text")
+        .set("font-family", "sans-serif")
+        .set("font-size", layout.font_size);
+
+    let mut top = text.pos_y;
+    for paragraph in RichText::from(text).paragraphs {
+        let scale = TextLayout::scale(paragraph.style);
+        if paragraph.text().trim().is_empty() {
+            top += layout.line_height * scale;
+            continue;
+        };
+
+        for (bullet, indent, line) in layout.wrap(&paragraph, text.width, bullet(paragraph.style)) {
+            let y = top + layout.font_size * scale;
+            top += layout.line_height * scale;
+
+            let mut element = s::Text::new()
+                .set("x", x + indent)
+                .set("y", y)
+                .set("class", paragraph.style.to_string());
+            if scale != 1.0 {
+                element = element.set("font-size", layout.font_size * scale);
+            };
+            if matches!(paragraph.style, ParagraphStyle::Heading | ParagraphStyle::Bold) {
+                element = element.set("font-weight", "bold");
+            };
+            group = group.add(element.add(s::TextNode::new(paragraph_markup(bullet, &line))));
+        };
+    };
+    group
+}
+
+/// A paragraph's contents, with a `<tspan>` for each formatted span. This is one
+/// text node rather than child elements, as the svg crate would put newlines (and
+/// so spaces) between them.
+fn paragraph_markup(bullet: &str, paragraph: &Paragraph) -> String {
+    let mut markup = bullet.to_string();
+    for span in &paragraph.spans {
+        let text = escape_xml(&span.text);
+        markup.push_str(&match (span.bold, span.italic) {
+            (false, false) => text,
+            (true, false) => format!(r#"<tspan font-weight="bold">{}</tspan>"#, text),
+            (false, true) => format!(r#"<tspan font-style="italic">{}</tspan>"#, text),
+            (true, true) => format!(r#"<tspan font-weight="bold" font-style="italic">{}</tspan>"#, text),
+        });
+    };
+    markup
+}
+
+/// A group for each of the page's text boxes. There's normally just one.
+pub fn text_groups_from_notebook(notebook: &Notebook, layout: &TextLayout) -> Vec<s::Group> {
+    notebook.blocks.iter().filter_map(|b| match b {
+        Block::TextDef(text) => Some(group_from_text(text, layout)),
+        _ => None,
+    }).collect()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
 pub fn create_border_path() -> s::Path {
     let data = 
         s::Data::new()
@@ -170,10 +623,12 @@ pub fn create_border_path() -> s::Path {
 }
 
 fn prepare_svg<I>(paths: I) -> s::Document
-    where I: IntoIterator<Item=s::Path>
+    where I: IntoIterator,
+          I::Item: s::Node,
 {
     let margin = 0.0; //50.0;
     let mut document = s::Document::new()
+        .set("xmlns:inkscape", "http://www.inkscape.org/namespaces/inkscape")
         .set("viewBox", (-margin, -margin, WIDTH+margin, HEIGHT+margin));
     
     for p in paths {
@@ -183,7 +638,8 @@ fn prepare_svg<I>(paths: I) -> s::Document
 }
 
 pub fn write_svg<I, T>(paths: I, filepath: T) -> std::io::Result<()> 
-    where I: IntoIterator<Item=s::Path>,
+    where I: IntoIterator,
+          I::Item: s::Node,
           T: AsRef<std::path::Path>,
 {
     let document = prepare_svg(paths);
@@ -192,11 +648,43 @@ pub fn write_svg<I, T>(paths: I, filepath: T) -> std::io::Result<()>
 }
 
 pub fn write_svg_to_stdout<I>(paths: I) -> std::io::Result<()> 
-    where I: IntoIterator<Item=s::Path>
+    where I: IntoIterator,
+          I::Item: s::Node,
 {
-    let document = prepare_svg(paths);
-    let out_bytes = document.to_string().into_bytes();
-    std::io::stdout().write_all(&out_bytes)
+    std::io::stdout().write_all(svg_to_string(paths).as_bytes())
+}
+
+/// The whole SVG document, as written by `write_svg`.
+pub fn svg_to_string<I>(paths: I) -> String
+    where I: IntoIterator,
+          I::Item: s::Node,
+{
+    prepare_svg(paths).to_string()
+}
+
+/// Several pages in one SVG document, one below the other. Each page is a group
+/// with the class `page`, and the ids of its groups are prefixed with the page's
+/// so layers on different pages don't clash.
+pub fn pages_svg_to_string(pages: Vec<Vec<s::Group>>) -> String {
+    let count = pages.len().max(1) as f32;
+    let mut document = s::Document::new()
+        .set("xmlns:inkscape", "http://www.inkscape.org/namespaces/inkscape")
+        .set("viewBox", (0.0, 0.0, WIDTH, HEIGHT * count));
+
+    for (index, groups) in pages.into_iter().enumerate() {
+        let mut page = s::Group::new()
+            .set("class", "page")
+            .set("id", format!("page-{}", index + 1))
+            .set("transform", format!("translate(0 {})", HEIGHT * index as f32));
+        for mut group in groups {
+            if let Some(id) = group.get_attributes().get("id").map(|id| id.to_string()) {
+                s::Node::assign(&mut group, "id", format!("page-{}-{}", index + 1, id));
+            };
+            page = page.add(group);
+        };
+        document = document.add(page);
+    };
+    document.to_string()
 }
 
 
@@ -204,23 +692,32 @@ pub fn write_svg_to_stdout<I>(paths: I) -> std::io::Result<()>
 ///
 /// TODO: make this interface better
 pub fn read_svg_file<P: AsRef<std::path::Path>>(filepath: P) -> Result<Vec<Line>> {
-    let mut content = String::new();
-    let events = match svg::open(filepath, &mut content){
-        Ok(parser) => {
-            parser
-        },
-        Err(error) => {
-            return Err(RMError::IoError(error))
-        },
-    };
-    let mut lines = Vec::new();
-    for event in events {
-        lines.push(Line::try_from(event)?);
-    };
-    Ok(lines)
+    let content = std::fs::read_to_string(filepath).map_err(RMError::IoError)?;
+    read_svg_buffer(&content)
 }
 
-pub fn read_svg_buffer<'a>(svg_buf: &str) -> Result<Vec<Line>> {
+pub fn read_svg_buffer(svg_buf: &str) -> Result<Vec<Line>> {
+    read_svg_buffer_with(svg_buf, &ImportOptions::default())
+}
+
+/// Elements whose contents are only drawn when referenced from elsewhere, or not
+/// at all.
+const NOT_RENDERED: &[&str] = &[
+    s::tag::Definitions, s::tag::ClipPath, s::tag::Mask, s::tag::Marker, s::tag::Pattern,
+    s::tag::Symbol, s::tag::Title, s::tag::Description, s::tag::Style, s::tag::Script,
+    s::tag::LinearGradient, s::tag::RadialGradient, s::tag::Filter, s::tag::ForeignObject,
+    "metadata",
+];
+
+/// Options for turning SVG into lines.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Style each line from its element's stroke (see `SvgStroke`), rather than
+    /// leaving it as a default black fineliner.
+    pub style_from_svg: bool,
+}
+
+pub fn read_svg_buffer_with(svg_buf: &str, options: &ImportOptions) -> Result<Vec<Line>> {
     let events = match svg::read(svg_buf) {
         Ok(parser) => {
             parser
@@ -230,11 +727,310 @@ pub fn read_svg_buffer<'a>(svg_buf: &str) -> Result<Vec<Line>> {
         },
     };
 
+    // the transform and the inherited stroke of each open <svg> or <g>, outermost first
+    let mut stack = vec!((Transform::IDENTITY, SvgStroke::default()));
+    // how many elements deep we are inside things that are never drawn directly
+    let mut hidden = 0;
     let mut lines = Vec::new();
     for event in events {
-        lines.push(Line::try_from(event)?);
+        if let s::Event::Error(error) = event {
+            return Err(RMError::SvgError(error));
+        };
+        let (ctm, inherited) = stack.last().cloned().unwrap_or_default();
+        if let s::Event::Tag(tag, kind, attributes) = &event {
+            if NOT_RENDERED.contains(tag) || hidden > 0 {
+                match kind {
+                    s::Type::Start => hidden += 1,
+                    s::Type::End => hidden -= 1,
+                    s::Type::Empty => {},
+                };
+                continue;
+            };
+
+            let container = match *tag {
+                s::tag::SVG => Some(viewport_transform(attributes)? * element_transform(attributes)?),
+                s::tag::Group | s::tag::Link => Some(element_transform(attributes)?),
+                _ => None,
+            };
+            if let Some(transform) = container {
+                match kind {
+                    s::Type::Start => stack.push((ctm * transform, SvgStroke::from_attributes(attributes).inherit(&inherited))),
+                    s::Type::End => { stack.pop(); },
+                    s::Type::Empty => {},
+                };
+                continue;
+            };
+        };
+
+        let stroke = match &event {
+            s::Event::Tag(_, _, attributes) if options.style_from_svg => {
+                let mut stroke = SvgStroke::from_attributes(attributes).inherit(&inherited);
+                // the width is in the element's units, so it's scaled as its outline is
+                let scale = (ctm * element_transform(attributes)?).scale_factor();
+                stroke.width = stroke.width.map(|w| w * scale);
+                Some(stroke)
+            },
+            _ => None,
+        };
+        let mut drawn = lines_from_event(&event, &ctm)?;
+        if let Some(stroke) = stroke {
+            for line in drawn.iter_mut() {
+                stroke.apply(line);
+            };
+        };
+        lines.extend(drawn);
     };
     Ok(lines)
-    
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line(pen_type: PenType, color: PenColor, widths: &[u16]) -> Line {
+        Line {
+            pen_type: Some(pen_type),
+            color: Some(color),
+            points: widths.iter().enumerate().map(|(i, w)| Point {
+                x: i as f32,
+                y: 0.0,
+                width: *w,
+                pressure: 255,
+                ..Point::default()
+            }).collect(),
+            ..Line::default()
+        }
+    }
+
+    #[test]
+    fn constant_width_pens() {
+        let paths = paths_from_line(&line(PenType::Fineliner2, PenColor::Blue, &[8, 12]));
+        assert_eq!(paths.len(), 1);
+        let attrs = paths[0].get_attributes();
+        assert_eq!(attrs["stroke"].to_string(), "#4e69c9");
+        assert_eq!(attrs["stroke-width"].to_string(), "2.500");
+
+        let highlight = pen_style(PenType::Highlighter2, PenColor::Highlight).unwrap();
+        assert_eq!(highlight.opacity, 0.3);
+        assert!(!highlight.variable_width);
+    }
+
+    #[test]
+    fn variable_width_pens() {
+        let paths = paths_from_line(&line(PenType::Ballpoint2, PenColor::Black, &[8, 12, 16]));
+        assert_eq!(paths.len(), 2);
+        let widths: Vec<String> = paths.iter().map(|p| p.get_attributes()["stroke-width"].to_string()).collect();
+        assert_eq!(widths, vec!("2.400", "3.600"));
+    }
+
+    #[test]
+    fn stroke_from_svg() {
+        let mut attributes = svg::node::Attributes::new();
+        attributes.insert("stroke".to_string(), "#f00".into());
+        attributes.insert("stroke-width".to_string(), "3".into());
+        attributes.insert("style".to_string(), "stroke-opacity: 0.25; stroke-width: 6px".into());
+        let stroke = SvgStroke::from_attributes(&attributes);
+        assert_eq!(stroke, SvgStroke { color: Some((255, 0, 0)), width: Some(6.0), opacity: Some(0.25) });
+
+        let mut line = line(PenType::Fineliner2, PenColor::Black, &[16, 16]);
+        stroke.apply(&mut line);
+        assert_eq!(line.pen_type, Some(PenType::Highlighter2));
+        assert_eq!(line.color, Some(PenColor::Red));
+        assert_eq!(line.points[0].width, 24);
+    }
+
+    #[test]
+    fn stroke_from_groups() {
+        // drawn at 14.04 times the size of the viewBox
+        let svg = r##"<svg viewBox="0 0 100 100" width="1404" height="1404">
+            <g stroke="#f00" stroke-width="1">
+                <line x1="10" y1="10" x2="90" y2="90"/>
+                <path d="M 0 0 L 50 50" style="stroke-width: 1mm; stroke: blue"/>
+            </g>
+            <path d="M 0 0 L 50 50"/>
+        </svg>"##;
+        let options = ImportOptions { style_from_svg: true };
+        let lines = read_svg_buffer_with(svg, &options).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].color, Some(PenColor::Red));
+        assert_eq!(lines[0].points[0].width, (14.04f64 * 4.0).round() as u16);
+        assert_eq!(lines[1].color, Some(PenColor::Blue));
+        assert_eq!(lines[1].points[0].width, (DPI / 25.4 * 14.04 * 4.0).round() as u16);
+        // outside the group, nothing is inherited
+        assert_eq!(lines[2].color, Line::default().color);
+    }
+
+    #[test]
+    fn svg_colors() {
+        assert_eq!(parse_color("rgb(0, 0, 255)"), Some((0, 0, 255)));
+        assert_eq!(parse_color("none"), None);
+        assert_eq!(nearest_pen_color((0, 0, 255)), PenColor::Blue);
+        assert_eq!(nearest_pen_color((20, 20, 20)), PenColor::Black);
+        assert_eq!(nearest_pen_color((250, 250, 30)), PenColor::Yellow);
+    }
+
+    #[test]
+    fn import_subpaths() {
+        let lines = read_svg_buffer(r#"<path d="M 702 10 c 0 10 10 10 10 0 z M 802 0 v 5"/>"#).unwrap();
+        assert_eq!(lines.len(), 2);
+
+        let first = &lines[0].points;
+        assert_eq!((first[0].x, first[0].y), (0.0, 10.0));
+        assert_eq!((first.last().unwrap().x, first.last().unwrap().y), (0.0, 10.0));
+        assert!(first.iter().any(|p| (p.y - 17.5).abs() < 0.01));
+
+        let second = &lines[1].points;
+        assert_eq!(second.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>(), vec!((100.0, 0.0), (100.0, 5.0)));
+        assert_eq!(second[0].width, DEFAULT_POINT_WIDTH);
+    }
+
+    #[test]
+    fn import_transforms() {
+        // the viewBox doubles everything, the groups move right 10 then scale by 3
+        let doc = concat!(
+            r#"<svg width="200" height="200" viewBox="0 0 100 100">"#,
+            r#"<g transform="translate(10)"><g transform="scale(3)">"#,
+            r#"<path d="M 0 0 L 1 0" transform="rotate(90)"/>"#,
+            r#"</g></g>"#,
+            r#"<path d="M 0 0 h 1"/>"#,
+            r#"</svg>"#,
+        );
+        let lines = read_svg_buffer(doc).unwrap();
+        let points: Vec<Vec<(f32, f32)>> = lines.iter()
+            .map(|l| l.points.iter().map(|p| (p.x + HALF_WIDTH, p.y)).collect())
+            .collect();
+        assert_eq!(points, vec!(
+            vec!((20.0, 0.0), (20.0, 6.0)),
+            vec!((0.0, 0.0), (2.0, 0.0)),
+        ));
+
+        // a page exported by us comes back unchanged
+        let lines = read_svg_buffer(r#"<svg viewBox="0 0 1404 1872"><path d="M 702 0 L 703 1"/></svg>"#).unwrap();
+        assert_eq!((lines[0].points[1].x, lines[0].points[1].y), (1.0, 1.0));
+
+        let mut line = lines[0].clone();
+        line.transform("translate(5 5)").unwrap();
+        assert_eq!((line.points[1].x, line.points[1].y), (6.0, 6.0));
+    }
+
+    fn page_points(line: &Line) -> Vec<(f32, f32)> {
+        line.points.iter().map(|p| (p.x + HALF_WIDTH, p.y)).collect()
+    }
+
+    #[test]
+    fn import_shapes() {
+        let doc = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- made by hand -->
+<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1404 1872">
+  <title>Shapes</title>
+  <metadata><rdf:RDF><cc:Work/></rdf:RDF></metadata>
+  <defs><circle id="hidden" r="5"/></defs>
+  <rect x="10" y="10" width="20" height="10"/>
+  <rect x="10" y="10" width="20" height="10" rx="2"/>
+  <rect x="10" y="10" width="0" height="10"/>
+  <circle cx="100" cy="100" r="10"/>
+  <ellipse cx="100" cy="100" rx="20" ry="10"/>
+  <line x1="0" y1="0" x2="5" y2="5"/>
+  <polyline points="0,0 10,0 10,10 3"/>
+  <polygon points="0 0 10 0 10 10"/>
+  <text x="0" y="0">not drawn</text>
+</svg>"#;
+        let lines = read_svg_buffer(doc).unwrap();
+        assert_eq!(lines.len(), 7);
+
+        assert_eq!(page_points(&lines[0]), vec!((10.0, 10.0), (30.0, 10.0), (30.0, 20.0), (10.0, 20.0), (10.0, 10.0)));
+
+        // rounded corners stay inside the square ones
+        let rounded = page_points(&lines[1]);
+        assert!(rounded.len() > 5);
+        assert!(!rounded.contains(&(10.0, 10.0)));
+        assert!(rounded.iter().all(|(x, y)| (10.0..=30.0).contains(x) && (10.0..=20.0).contains(y)));
+
+        for p in page_points(&lines[2]) {
+            assert!(((p.0 - 100.0).hypot(p.1 - 100.0) - 10.0).abs() < 0.01);
+        };
+        for p in page_points(&lines[3]) {
+            assert!((((p.0 - 100.0) / 20.0).powi(2) + ((p.1 - 100.0) / 10.0).powi(2) - 1.0).abs() < 0.01);
+        };
+
+        assert_eq!(page_points(&lines[4]), vec!((0.0, 0.0), (5.0, 5.0)));
+        assert_eq!(page_points(&lines[5]), vec!((0.0, 0.0), (10.0, 0.0), (10.0, 10.0)));
+        assert_eq!(page_points(&lines[6]), vec!((0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 0.0)));
+    }
+
+    #[test]
+    fn svg_lengths() {
+        assert_eq!(parse_length("10", 0.0), Some(10.0));
+        assert_eq!(parse_length("1in", 0.0), Some(DPI));
+        assert_eq!(parse_length("50%", 1404.0), Some(702.0));
+        assert_eq!(parse_length("3em", 0.0), None);
+    }
+
+    #[test]
+    fn text_paragraphs() {
+        let mut text = TextDef::new();
+        text.texts.push(TextChunk {
+            chunk_id: CrdtId::new(1, 16),
+            other_chunk_id_0: CrdtId::default(),
+            other_chunk_id_1: CrdtId::default(),
+            deleted_length: 0,
+            text: "one\n\nthree & <four>".to_string(),
+            inline_format: None,
+        });
+        let layout = TextLayout::default();
+        let svg = group_from_text(&text, &layout).to_string();
+
+        assert_eq!(svg.matches("<text").count(), 2);
+        assert!(svg.contains(&format!(r#"x="{}""#, TEXT_BOX_X + HALF_WIDTH as f64)));
+        assert!(svg.contains(&format!(r#"y="{}""#, TEXT_BOX_Y + layout.font_size)));
+        assert!(svg.contains(&format!(r#"y="{}""#, TEXT_BOX_Y + layout.font_size + 2.0 * layout.line_height)));
+        assert!(svg.contains("three &amp; &lt;four&gt;"));
+
+        let left = text.last_char_id();
+        text.texts.push(TextChunk { chunk_id: CrdtId::new(1, 40), other_chunk_id_0: left, inline_format: Some(1), text: String::new(), ..text.texts[0].clone() });
+        text.texts.push(TextChunk { chunk_id: CrdtId::new(1, 41), other_chunk_id_0: CrdtId::new(1, 40), text: " bold".to_string(), ..text.texts[0].clone() });
+        let svg = group_from_text(&text, &layout).to_string();
+        assert!(svg.contains(r#"three &amp; &lt;four&gt;<tspan font-weight="bold"> bold</tspan>"#));
+        assert!(svg.contains(r#"class="plain""#));
+    }
+
+    #[test]
+    fn wrapped_text() {
+        let mut text = TextDef::new();
+        text.width = 300.0;
+        text.texts.push(TextChunk {
+            chunk_id: CrdtId::new(1, 16),
+            other_chunk_id_0: CrdtId::default(),
+            other_chunk_id_1: CrdtId::default(),
+            deleted_length: 0,
+            text: "a paragraph that is far too long for its box\nnext".to_string(),
+            inline_format: None,
+        });
+        let layout = TextLayout::default();
+        assert_eq!(layout.chars_per_line(text.width, ParagraphStyle::Plain), 20);
+        let svg = group_from_text(&text, &layout).to_string();
+
+        // three lines for the first paragraph, and the next one below them
+        assert_eq!(svg.matches("<text").count(), 4);
+        assert!(svg.contains("\na paragraph that is \n</text>"));
+        let last = TEXT_BOX_Y + layout.font_size + 3.0 * layout.line_height;
+        assert!(svg.contains(&format!("y=\"{}\">\nnext\n</text>", last)));
+    }
+
+    #[test]
+    fn text_layout_from_content() {
+        let content: Content = serde_json::from_str(r#"{"textScale": 1.5, "lineHeight": 200}"#).unwrap();
+        let layout = TextLayout::from_content(&content);
+        assert_eq!(layout.font_size, 45.0);
+        assert_eq!(layout.line_height, 210.0);
+
+        let content: Content = serde_json::from_str(r#"{"textScale": 1, "lineHeight": -1}"#).unwrap();
+        assert_eq!(TextLayout::from_content(&content), TextLayout::default());
+    }
+
+    #[test]
+    fn area_eraser_is_not_drawn() {
+        assert!(paths_from_line(&line(PenType::EraserArea, PenColor::Black, &[8, 8])).is_empty());
+    }
+}
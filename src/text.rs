@@ -0,0 +1,521 @@
+
+//! Typed text, with its formatting.
+//!
+//! A page's typed text is one sequence of characters (see `TextDef`). Paragraph
+//! styles are kept beside it, keyed by the id of the newline that starts each
+//! paragraph (0:0 for the first one). Inline bold and italic are switched on and off
+//! by empty items in the sequence itself.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::types::*;
+
+/// How a whole paragraph is formatted, as picked from the tablet's text toolbar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Default)]
+pub enum ParagraphStyle {
+    Basic,
+    #[default]
+    Plain,
+    Heading,
+    Bold,
+    Bullet,
+    Bullet2,
+    Checkbox,
+    CheckboxChecked,
+    Unknown(u8),
+}
+
+impl From<u8> for ParagraphStyle {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => ParagraphStyle::Basic,
+            1 => ParagraphStyle::Plain,
+            2 => ParagraphStyle::Heading,
+            3 => ParagraphStyle::Bold,
+            4 => ParagraphStyle::Bullet,
+            5 => ParagraphStyle::Bullet2,
+            6 => ParagraphStyle::Checkbox,
+            7 => ParagraphStyle::CheckboxChecked,
+            other => ParagraphStyle::Unknown(other),
+        }
+    }
+}
+
+impl ParagraphStyle {
+    /// Whether paragraphs in this style are list items.
+    pub fn is_list(&self) -> bool {
+        matches!(self, ParagraphStyle::Bullet | ParagraphStyle::Bullet2
+                     | ParagraphStyle::Checkbox | ParagraphStyle::CheckboxChecked)
+    }
+}
+
+impl From<ParagraphStyle> for u8 {
+    fn from(style: ParagraphStyle) -> Self {
+        match style {
+            ParagraphStyle::Basic => 0,
+            ParagraphStyle::Plain => 1,
+            ParagraphStyle::Heading => 2,
+            ParagraphStyle::Bold => 3,
+            ParagraphStyle::Bullet => 4,
+            ParagraphStyle::Bullet2 => 5,
+            ParagraphStyle::Checkbox => 6,
+            ParagraphStyle::CheckboxChecked => 7,
+            ParagraphStyle::Unknown(code) => code,
+        }
+    }
+}
+
+impl std::fmt::Display for ParagraphStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ParagraphStyle::Basic => "basic",
+            ParagraphStyle::Plain => "plain",
+            ParagraphStyle::Heading => "heading",
+            ParagraphStyle::Bold => "bold",
+            ParagraphStyle::Bullet => "bullet",
+            ParagraphStyle::Bullet2 => "bullet-2",
+            ParagraphStyle::Checkbox => "checkbox",
+            ParagraphStyle::CheckboxChecked => "checkbox-checked",
+            ParagraphStyle::Unknown(code) => return write!(f, "{}", code),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What one of the empty formatting items in the text does (`TextChunk::inline_format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InlineFormat {
+    BoldOn = 1,
+    BoldOff = 2,
+    ItalicOn = 3,
+    ItalicOff = 4,
+}
+
+impl InlineFormat {
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(InlineFormat::BoldOn),
+            2 => Some(InlineFormat::BoldOff),
+            3 => Some(InlineFormat::ItalicOn),
+            4 => Some(InlineFormat::ItalicOff),
+            _ => None,
+        }
+    }
+}
+
+/// What's at one place in the text: a character, or an inline formatting switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextValue {
+    Char(char),
+    Format(u32),
+    /// A deleted character. Only its id is left.
+    Deleted,
+}
+
+/// One place in the text, with its own CRDT id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextChar {
+    pub id: CrdtId,
+    pub value: TextValue,
+}
+
+/// Every character and formatting switch in a text box, in the order they're
+/// shown, deleted ones included.
+///
+/// Chunks are stored in no particular order; each names the characters it was
+/// typed between (`other_chunk_id_0` and `_1`). Splitting every chunk into single
+/// characters, each between the one before it and the chunk's right neighbour,
+/// makes one CRDT sequence that can be put in order like any other.
+pub fn text_sequence(text: &TextDef) -> Vec<TextChar> {
+    let mut items = HashMap::new();
+    let mut links = Vec::new();
+
+    for chunk in &text.texts {
+        let values: Vec<TextValue> = if chunk.is_deleted() {
+            vec!(TextValue::Deleted; chunk.deleted_length as usize)
+        } else if let Some(format) = chunk.inline_format {
+            vec!(TextValue::Format(format))
+        } else {
+            chunk.text.chars().map(TextValue::Char).collect()
+        };
+
+        let mut left = chunk.other_chunk_id_0;
+        for (i, value) in values.iter().enumerate() {
+            let id = chunk.chunk_id.offset(i as u64);
+            let right = if i + 1 == values.len() { chunk.other_chunk_id_1 } else { id.offset(1) };
+            items.insert(id, *value);
+            links.push((id, left, right));
+            left = id;
+        };
+    };
+
+    crate::scene::sequence_order(links).into_iter()
+                                       .map(|id| TextChar { id, value: items[&id] })
+                                       .collect()
+}
+
+/// A run of text with the same inline formatting.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct Span {
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct Paragraph {
+    pub style: ParagraphStyle,
+    pub spans: Vec<Span>,
+}
+
+impl Paragraph {
+    pub fn new(style: ParagraphStyle) -> Self {
+        Paragraph { style, spans: Vec::new() }
+    }
+
+    /// The paragraph's text, without formatting.
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    /// Add a character, extending the last span if it's formatted the same.
+    pub fn push(&mut self, c: char, bold: bool, italic: bool) {
+        match self.spans.last_mut() {
+            Some(span) if span.bold == bold && span.italic == italic => span.text.push(c),
+            _ => self.spans.push(Span { text: c.to_string(), bold, italic }),
+        };
+    }
+
+    /// The paragraph split into lines of at most `width` characters, breaking after
+    /// spaces where it can and inside words where it can't. The spaces a line ends
+    /// with stay on it, and don't count towards its width.
+    pub fn wrap(&self, width: usize) -> Vec<Paragraph> {
+        let width = width.max(1);
+        let chars: Vec<(char, bool, bool)> = self.spans.iter()
+            .flat_map(|s| s.text.chars().map(move |c| (c, s.bold, s.italic)))
+            .collect();
+
+        // each word with the spaces after it: (start, end of the word, end)
+        let mut words = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            while i < chars.len() && !chars[i].0.is_whitespace() {
+                i += 1;
+            };
+            let word_end = i;
+            while i < chars.len() && chars[i].0.is_whitespace() {
+                i += 1;
+            };
+            words.push((start, word_end, i));
+        };
+
+        let mut lines = Vec::new();
+        let (mut line_start, mut line_end) = (0, 0);
+        for (start, word_end, end) in words {
+            if line_end > line_start && word_end - line_start > width {
+                lines.push((line_start, line_end));
+                line_start = start;
+            };
+            while word_end - line_start > width {
+                lines.push((line_start, line_start + width));
+                line_start += width;
+            };
+            line_end = end;
+        };
+        if line_end > line_start || lines.is_empty() {
+            lines.push((line_start, line_end));
+        };
+
+        lines.into_iter().map(|(start, end)| {
+            let mut line = Paragraph::new(self.style);
+            for (c, bold, italic) in &chars[start..end] {
+                line.push(*c, *bold, *italic);
+            };
+            line
+        }).collect()
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let text = self.text();
+        if text.trim().is_empty() {
+            return String::new();
+        };
+
+        let prefix = match self.style {
+            ParagraphStyle::Heading => "# ",
+            ParagraphStyle::Bullet => "- ",
+            ParagraphStyle::Bullet2 => "    - ",
+            ParagraphStyle::Checkbox => "- [ ] ",
+            ParagraphStyle::CheckboxChecked => "- [x] ",
+            _ => "",
+        };
+        // a bold paragraph is all bold, whatever its spans say
+        let all_bold = self.style == ParagraphStyle::Bold;
+
+        let mut out = prefix.to_string();
+        for span in &self.spans {
+            let marker = match (span.bold || all_bold, span.italic) {
+                (true, true) => "***",
+                (true, false) => "**",
+                (false, true) => "*",
+                (false, false) => "",
+            };
+            let core = span.text.trim();
+            if marker.is_empty() || core.is_empty() {
+                out.push_str(&escape_markdown(&span.text));
+                continue;
+            };
+            // markers next to whitespace aren't emphasis, so keep it outside them
+            let lead = &span.text[..span.text.len() - span.text.trim_start().len()];
+            let trail = &span.text[span.text.trim_end().len()..];
+            out.push_str(&format!("{}{}{}{}{}", lead, marker, escape_markdown(core), marker, trail));
+        };
+        out
+    }
+}
+
+fn escape_markdown(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('*', "\\*")
+}
+
+/// A page's typed text, split into paragraphs.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct RichText {
+    pub paragraphs: Vec<Paragraph>,
+}
+
+impl RichText {
+    /// The text and formatting of a text box. Deleted text is left out.
+    pub fn from_text(text: &TextDef) -> Self {
+        // the latest style set for each paragraph
+        let mut styles: HashMap<CrdtId, (CrdtId, u8)> = HashMap::new();
+        for back in &text.backmatter {
+            let entry = styles.entry(back.char_id).or_insert((back.timestamp, back.format));
+            if back.timestamp > entry.0 {
+                *entry = (back.timestamp, back.format);
+            };
+        };
+        let style_of = |id: CrdtId| styles.get(&id).map_or(ParagraphStyle::default(), |s| s.1.into());
+
+        let mut paragraphs = Vec::new();
+        let mut paragraph = Paragraph::new(style_of(CrdtId::default()));
+        let (mut bold, mut italic) = (false, false);
+
+        for item in text_sequence(text) {
+            match item.value {
+                TextValue::Char('\n') => {
+                    let next = Paragraph::new(style_of(item.id));
+                    paragraphs.push(std::mem::replace(&mut paragraph, next));
+                },
+                TextValue::Char(c) => paragraph.push(c, bold, italic),
+                TextValue::Format(code) => match InlineFormat::from_code(code) {
+                    Some(InlineFormat::BoldOn) => bold = true,
+                    Some(InlineFormat::BoldOff) => bold = false,
+                    Some(InlineFormat::ItalicOn) => italic = true,
+                    Some(InlineFormat::ItalicOff) => italic = false,
+                    None => {},
+                },
+                TextValue::Deleted => {},
+            };
+        };
+        paragraphs.push(paragraph);
+
+        RichText { paragraphs }
+    }
+
+    /// The text without formatting, one line per paragraph.
+    pub fn plain_text(&self) -> String {
+        self.paragraphs.iter().map(Paragraph::text).collect::<Vec<_>>().join("\n")
+    }
+
+    /// The text as Markdown, one line per paragraph. Paragraphs are separated by a
+    /// blank line, so Markdown doesn't run them together, except between items of
+    /// the same list. Empty paragraphs are left out.
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        let mut last: Option<ParagraphStyle> = None;
+        for paragraph in &self.paragraphs {
+            let markdown = paragraph.to_markdown();
+            if markdown.is_empty() {
+                continue;
+            };
+            if let Some(last) = last {
+                out.push_str(if last.is_list() && paragraph.style.is_list() { "\n" } else { "\n\n" });
+            };
+            out.push_str(&markdown);
+            last = Some(paragraph.style);
+        };
+        out
+    }
+}
+
+impl From<&TextDef> for RichText {
+    fn from(text: &TextDef) -> Self {
+        RichText::from_text(text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::parse_full;
+
+    const FORMATTED: &str = "assets/test_notebooks/e9453e30-cd4a-4a9b-aef4-41e04c047ff5/5b6148bf-2906-4281-adb5-a428d83de792.rm";
+    const EDITED: &str = "assets/test_notebooks/213001cb-42c0-4628-8ed0-8320c15da2a8/3a9e640a-eca5-4b6f-a961-794fc47260d9.rm";
+
+    fn chunk(id: u64, text: &str, inline_format: Option<u32>) -> TextChunk {
+        TextChunk {
+            chunk_id: CrdtId::new(1, id),
+            other_chunk_id_0: CrdtId::default(),
+            other_chunk_id_1: CrdtId::default(),
+            deleted_length: 0,
+            text: text.to_string(),
+            inline_format,
+        }
+    }
+
+    /// Link chunks one after another, as if typed in order.
+    fn typed(mut chunks: Vec<TextChunk>) -> Vec<TextChunk> {
+        let mut left = CrdtId::default();
+        for chunk in chunks.iter_mut() {
+            chunk.other_chunk_id_0 = left;
+            left = chunk.last_char_id();
+        };
+        chunks
+    }
+
+    fn style(char_id: CrdtId, timestamp: u64, style: ParagraphStyle) -> TextBackmatter {
+        TextBackmatter { char_id, timestamp: CrdtId::new(1, timestamp), format: style.into() }
+    }
+
+    #[test]
+    fn paragraph_styles() {
+        let mut text = TextDef::new();
+        text.texts = vec!(chunk(10, "Notes\nfirst\nsecond\ndone\nlater", None));
+        text.backmatter = vec!(
+            style(CrdtId::default(), 1, ParagraphStyle::Heading),
+            style(CrdtId::new(1, 15), 2, ParagraphStyle::Bullet),
+            style(CrdtId::new(1, 21), 3, ParagraphStyle::Bullet),
+            style(CrdtId::new(1, 28), 4, ParagraphStyle::Checkbox),
+            style(CrdtId::new(1, 28), 5, ParagraphStyle::CheckboxChecked),
+            style(CrdtId::new(1, 33), 6, ParagraphStyle::Bold),
+        );
+
+        let rich = RichText::from(&text);
+        let styles: Vec<ParagraphStyle> = rich.paragraphs.iter().map(|p| p.style).collect();
+        assert_eq!(styles, vec!(
+            ParagraphStyle::Heading, ParagraphStyle::Bullet, ParagraphStyle::Bullet,
+            ParagraphStyle::CheckboxChecked, ParagraphStyle::Bold,
+        ));
+        assert_eq!(rich.to_markdown(), "# Notes\n\n- first\n- second\n- [x] done\n\n**later**");
+    }
+
+    #[test]
+    fn markdown_blocks() {
+        let mut text = TextDef::new();
+        text.texts = vec!(chunk(10, "item\nafter\n\nanother", None));
+        text.backmatter = vec!(style(CrdtId::default(), 1, ParagraphStyle::Bullet));
+
+        // the plain paragraph isn't taken as more of the item
+        let markdown = RichText::from(&text).to_markdown();
+        assert_eq!(markdown, "- item\n\nafter\n\nanother");
+    }
+
+    #[test]
+    fn inline_formatting() {
+        let mut text = TextDef::new();
+        text.texts = typed(vec!(
+            chunk(10, "a ", None),
+            chunk(12, "", Some(InlineFormat::BoldOn as u32)),
+            chunk(13, "bold ", None),
+            chunk(18, "", Some(InlineFormat::ItalicOn as u32)),
+            chunk(19, "both", None),
+            chunk(23, "", Some(InlineFormat::BoldOff as u32)),
+            chunk(24, " italic", None),
+            chunk(31, "", Some(InlineFormat::ItalicOff as u32)),
+            chunk(32, " 2*3", None),
+        ));
+        let rich = RichText::from(&text);
+        assert_eq!(rich.paragraphs.len(), 1);
+        assert_eq!(rich.paragraphs[0].spans.len(), 5);
+        assert_eq!(rich.plain_text(), "a bold both italic 2*3");
+        assert_eq!(rich.to_markdown(), "a **bold** ***both*** *italic* 2\\*3");
+    }
+
+    #[test]
+    fn wrap_paragraphs() {
+        let mut paragraph = Paragraph::new(ParagraphStyle::Bullet);
+        for c in "the quick ".chars() {
+            paragraph.push(c, false, false);
+        };
+        for c in "brown fox jumped".chars() {
+            paragraph.push(c, true, false);
+        };
+        let lines: Vec<String> = paragraph.wrap(10).iter().map(Paragraph::text).collect();
+        assert_eq!(lines, ["the quick ", "brown fox ", "jumped"]);
+        let lines = paragraph.wrap(10);
+        assert!(lines.iter().all(|l| l.style == ParagraphStyle::Bullet));
+        assert_eq!(lines[1].spans, [Span { text: "brown fox ".to_string(), bold: true, italic: false }]);
+
+        // words longer than a line are broken up
+        let lines: Vec<String> = paragraph.wrap(4).iter().map(Paragraph::text).collect();
+        assert_eq!(lines, ["the ", "quic", "k ", "brow", "n ", "fox ", "jump", "ed"]);
+
+        assert_eq!(Paragraph::new(ParagraphStyle::Plain).wrap(10), [Paragraph::new(ParagraphStyle::Plain)]);
+    }
+
+    #[test]
+    fn edited_text_order() {
+        // "helo" typed, then an "l" put in the middle, "XY" typed at the start and
+        // the X deleted, stored in a muddled order
+        let mut text = TextDef::new();
+        let mut insert = chunk(20, "l", None);
+        insert.other_chunk_id_0 = CrdtId::new(1, 12);
+        insert.other_chunk_id_1 = CrdtId::new(1, 13);
+        let mut typed = chunk(10, "helo", None);
+        typed.other_chunk_id_0 = CrdtId::default();
+        let mut deleted = chunk(30, "", None);
+        deleted.deleted_length = 1;
+        deleted.other_chunk_id_1 = CrdtId::new(1, 10);
+        let mut kept = chunk(31, "Y", None);
+        kept.other_chunk_id_0 = CrdtId::new(1, 30);
+        kept.other_chunk_id_1 = CrdtId::new(1, 10);
+        text.texts = vec!(insert, kept, typed, deleted);
+
+        let sequence = text_sequence(&text);
+        assert_eq!(sequence.len(), 7);
+        assert_eq!(sequence[0], TextChar { id: CrdtId::new(1, 30), value: TextValue::Deleted });
+        assert_eq!(text.plain_text(), "Yhello");
+        assert_eq!(RichText::from(&text).plain_text(), "Yhello");
+        assert_eq!(text.last_char_id(), CrdtId::new(1, 13));
+    }
+
+    #[test]
+    fn edited_notebook() {
+        let notebook = parse_full(&std::fs::read(EDITED).unwrap()).unwrap();
+        let text = notebook.blocks.iter().find_map(|b| match b {
+            Block::TextDef(t) => Some(t),
+            _ => None,
+        }).unwrap();
+
+        // in the order the chunks are stored, this reads "omg text\nwha\nt lol..."
+        assert!(text.plain_text().starts_with("omg text\nwhat lol\nicant move it?\nSCREAMING\n"));
+    }
+
+    #[test]
+    fn formatted_notebook() {
+        let notebook = parse_full(&std::fs::read(FORMATTED).unwrap()).unwrap();
+        let text = notebook.blocks.iter().find_map(|b| match b {
+            Block::TextDef(t) => Some(t),
+            _ => None,
+        }).unwrap();
+        let markdown = RichText::from(text).to_markdown();
+
+        assert!(markdown.starts_with("# i am big text\n"));
+        assert!(markdown.contains("**bold** *italic* text. ***both***"));
+        assert!(markdown.contains("**i am med text**"));
+    }
+}
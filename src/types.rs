@@ -1,4 +1,5 @@
 
+use std::collections::HashMap;
 use std::vec::Vec;
 use std::default::Default;
 
@@ -8,16 +9,49 @@ use serde::Serialize;
 use nom::HexDisplay;
 use thiserror;
 
+use crate::geometry::Transform;
+use crate::text::{text_sequence, TextValue};
+use crate::legacy::FIRST_LAYER_ID;
+use crate::scene::sequence_order;
+
 type Result<T> = std::result::Result<T, RMError>;
 
 pub const HALF_WIDTH: f32 = 702.0;
 
+/// Every lines file starts with this string, followed by the version number
+/// padded with spaces to `HEADER_LEN` bytes total.
+pub const VERSION_HEADER: &str = "reMarkable .lines file, version=";
+pub const HEADER_LEN: usize = 43;
+
+/// Lines file versions we know how to read.
+pub const SUPPORTED_VERSIONS: [u8;3] = [3, 5, 6];
+
+// Block flags, as they appear after each block's length.
+pub const LAYER_DEF_FLAG: [u8;4] = [0,1,1,1];
+pub const LAYER_NAME_FLAG: [u8;4] = [0,1,2,2];
+pub const LAYER_INFO_FLAG: [u8;4] = [0,1,1,4];
+pub const LINE_FLAG: [u8;4] = [0,2,2,5];
+pub const TEXT_DEF_FLAG: [u8;4] = [0,1,1,7];
+pub const MIGRATION_INFO_FLAG: [u8;4] = [0,1,1,0];
+pub const GLYPH_FLAG: [u8;4] = [0,1,1,3];
+pub const TEXT_ITEM_FLAG: [u8;4] = [0,1,1,6];
+pub const AUTHOR_IDS_FLAG: [u8;4] = [0,1,1,9];
+pub const PAGE_INFO_FLAG: [u8;4] = [0,0,1,10];
+pub const TOMBSTONE_FLAG: [u8;4] = [0,1,1,8];
+pub const SCENE_INFO_FLAG: [u8;4] = [0,0,1,13];
+
+/// The root of every page's scene tree. Layers are the groups directly under it.
+pub const ROOT_ID: CrdtId = CrdtId::new(0, 1);
+
+/// The byte before every paragraph format code in a TextDef.
+pub const TEXT_FORMAT_MARKER: u8 = 17;
+
 #[derive(thiserror::Error, Debug)]
 pub enum RMError {
     #[error("Bad arguments: {0}")]
     ArgsError(String),
 
-    #[error("Can't parse: {}", .0.to_hex(.1.clone()))]
+    #[error("Can't parse: {}", .0.to_hex(*.1))]
     ParseError(Vec<u8>, usize),
 
     #[error("SVG parsing error: {0}")]
@@ -29,11 +63,23 @@ pub enum RMError {
     #[error("IO error")]
     IoError(std::io::Error),
 
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("PDF error: {0}")]
+    PdfError(#[from] lopdf::Error),
+
     #[error("Error with config")]
     ConfigError(config::ConfigError),
 
     #[error("Functionality not yet implemented")]
     NotImplementedError,
+
+    #[error("Not a reMarkable lines file, header was {0:?}")]
+    HeaderError(String),
+
+    #[error("Unsupported lines file version {0}")]
+    UnsupportedVersion(u8),
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -42,95 +88,363 @@ pub struct Notebook {
     pub blocks: Vec<Block>,
 }
 
-#[derive(Debug, Clone, Serialize, Default)]
-pub struct IdField(pub [u8;3]);
-
-impl IdField {
-    /// This is almost defninitely not accurate to what the tablet does,
-    /// but seems to work well enough.
-    pub fn inc(&mut self, pos: usize) {
-        if self.0[pos] >= 128{
-            if pos == 0 {
-                self.inc(1);
-                self.0[pos] = 0;
-            } 
-            else if pos == 1 {
-                self.inc(2);
-                self.0[pos] = 0;
-            }
-            else {
-                self.inc(0);
-                self.0[pos] = 0;
-            };
+impl Notebook {
+    /// A page with nothing on it, as the tablet starts one: the header blocks, then
+    /// the root group holding one empty layer, `Layer 1`.
+    pub fn blank(authors: AuthorIds) -> Self {
+        const END: CrdtId = CrdtId::new(0, 0);
+        let layer_id = CrdtId::new(0, FIRST_LAYER_ID);
+        let blocks = vec![
+            Block::AuthorIds(authors),
+            Block::MigrationInfo(MigrationInfo {
+                migration_id: CrdtId::new(LOCAL_AUTHOR, 1),
+                is_device: true,
+                unknown: Some(false),
+            }),
+            Block::PageInfo(PageInfo {
+                loads_count: 1,
+                merges_count: 0,
+                text_chars_count: 0,
+                text_lines_count: 0,
+                type_folio_use_count: Some(0),
+            }),
+            Block::SceneInfo(SceneInfo {
+                current_layer: LwwValue::default(),
+                background_visible: Some(LwwValue { timestamp: END, value: true }),
+                root_document_visible: Some(LwwValue { timestamp: END, value: true }),
+                paper_size: None,
+            }),
+            Block::LayerDef(LayerDef { layer_id, node_id: END, is_update: true, parent_id: ROOT_ID }),
+            Block::LayerName(LayerName {
+                layer_id: ROOT_ID,
+                name: LwwValue { timestamp: END, value: String::new() },
+                visible: LwwValue { timestamp: END, value: true },
+                unknown_unsized: Vec::new(),
+            }),
+            Block::LayerName(LayerName {
+                layer_id,
+                name: LwwValue { timestamp: CrdtId::new(0, FIRST_LAYER_ID + 1), value: "Layer 1".to_string() },
+                visible: LwwValue { timestamp: END, value: true },
+                unknown_unsized: Vec::new(),
+            }),
+            Block::LayerInfo(LayerInfo {
+                id_field_0: ROOT_ID,
+                id_field_1: CrdtId::new(0, FIRST_LAYER_ID + 2),
+                id_field_2: END,
+                id_field_3: END,
+                deleted_length: 0,
+                layer_id: Some(layer_id),
+            }),
+        ];
+        Notebook { frontmatter: Frontmatter::new(6), blocks }
+    }
+
+    /// The id with the highest counter anywhere in the Notebook's known blocks.
+    pub fn max_id(&self) -> Option<CrdtId> {
+        self.blocks.iter()
+                   .flat_map(Block::ids)
+                   .max_by_key(|id| id.counter)
+    }
+
+    /// Append `lines` to the end of their layers, giving each a fresh id and
+    /// linking it after the last item in that layer's sequence, or the line pushed
+    /// before it.
+    pub fn push_lines(&mut self, lines: Vec<Line>) {
+        let mut ids = IdGenerator::new(self, LOCAL_AUTHOR);
+        let mut last: HashMap<CrdtId, CrdtId> = HashMap::new();
+        for mut line in lines {
+            let left = *last.entry(line.layer_id).or_insert_with(|| {
+                sequence_order(self.sequence_items(line.layer_id)).last().copied().unwrap_or_default()
+            });
+            line.line_id = ids.next_id();
+            line.last_line_id = left;
+            line.id_field_0 = CrdtId::default();
+            last.insert(line.layer_id, line.line_id);
+            self.blocks.push(Block::Line(line));
+        };
+    }
+
+    /// Everything in the group `parent`'s sequence, erased items and the groups
+    /// inside it included, as `(id, left, right)` for `scene::sequence_order`.
+    fn sequence_items(&self, parent: CrdtId) -> Vec<(CrdtId, CrdtId, CrdtId)> {
+        self.blocks.iter().filter_map(|b| match b {
+            Block::Line(l) if l.layer_id == parent => Some((l.line_id, l.last_line_id, l.id_field_0)),
+            Block::LayerInfo(i) if i.id_field_0 == parent => Some((i.id_field_1, i.id_field_2, i.id_field_3)),
+            Block::Glyph(Glyph { header, .. })
+            | Block::TextItem(TextItem { header, .. })
+            | Block::Tombstone(header) if header.parent_id == parent => {
+                Some((header.item_id, header.left_id, header.right_id))
+            },
+            _ => None,
+        }).collect()
+    }
+
+    /// Add `text` as a new paragraph at the end of the page's typed text, making
+    /// the page's text box if it doesn't have one yet.
+    pub fn push_text(&mut self, text: &str) {
+        let mut ids = IdGenerator::new(self, LOCAL_AUTHOR);
+
+        if !self.blocks.iter().any(|b| matches!(b, Block::TextDef(_))) {
+            let mut def = TextDef::new();
+            def.backmatter.push(TextBackmatter {
+                char_id: CrdtId::default(),
+                timestamp: ids.next_id(),
+                format: PLAIN_FORMAT,
+            });
+            // the text comes after the scene tree, before the layers' contents
+            let at = self.blocks.iter()
+                                .position(|b| !matches!(b, Block::MigrationInfo(_) | Block::AuthorIds(_)
+                                                        | Block::PageInfo(_) | Block::SceneInfo(_)
+                                                        | Block::LayerDef(_)))
+                                .unwrap_or(self.blocks.len());
+            self.blocks.insert(at, Block::TextDef(def));
+        };
+
+        let def = self.blocks.iter_mut().find_map(|b| match b {
+            Block::TextDef(def) => Some(def),
+            _ => None,
+        }).expect("a text block was just added");
+
+        let existing = def.plain_text();
+        let text = if existing.is_empty() || existing.ends_with('\n') {
+            text.to_string()
+        } else {
+            format!("\n{}", text)
+        };
+        let length = text.chars().count() as u64;
+        if length == 0 {
+            return;
         };
-        self.0[pos] = self.0[pos]+1;
+
+        let chunk = TextChunk {
+            chunk_id: ids.reserve(length),
+            other_chunk_id_0: def.last_char_id(),
+            other_chunk_id_1: CrdtId::default(),
+            deleted_length: 0,
+            text,
+            inline_format: None,
+        };
+        def.texts.push(chunk);
     }
 }
 
+/// A CRDT id, as used for every item and layer in a v6 file: the id of the author
+/// and a per-author counter. Author 0 is for ids the format fixes, like the root
+/// group `0:1` and the first layer `0:11`; 1 and up are devices, as listed in the
+/// `AuthorIds` block, with the tablet that made the page being 1. On disk it's the
+/// author byte followed by the counter as a LEB128 varint.
+///
+/// Ids order by author, then counter.
+#[derive(Debug, Clone, Copy, Serialize, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CrdtId {
+    pub author: u8,
+    pub counter: u64,
+}
+
+impl CrdtId {
+    pub const fn new(author: u8, counter: u64) -> Self {
+        CrdtId { author, counter }
+    }
+
+    /// The id `n` places after this one, from the same author. Text spans use one
+    /// id per character, counting up from the span's own id.
+    pub fn offset(&self, n: u64) -> Self {
+        CrdtId::new(self.author, self.counter + n)
+    }
+}
+
+impl std::fmt::Display for CrdtId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.author, self.counter)
+    }
+}
+
+/// Reads ids in the `author:counter` form from `Display`, as used in `.content` files.
+impl std::str::FromStr for CrdtId {
+    type Err = RMError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split_once(':')
+         .and_then(|(author, counter)| Some(CrdtId::new(author.parse().ok()?, counter.parse().ok()?)))
+         .ok_or(RMError::ArgsError(format!("not a CRDT id: {:?}", s)))
+    }
+}
+
+/// The author used for ids we create: the tablet's own. An author number the
+/// page's `AuthorIds` doesn't list has no device behind it, and `IdGenerator`
+/// counts up from above every counter in use, so sharing the tablet's number
+/// can't clash with ids already in the page.
+pub const LOCAL_AUTHOR: u8 = 1;
+
+/// Hands out CRDT ids that don't clash with any already in a Notebook.
+///
+/// Counters start above the highest counter in use by *any* author, so new ids
+/// also sort after everything already in the file.
+#[derive(Debug, Clone)]
+pub struct IdGenerator {
+    author: u8,
+    next: u64,
+}
+
+impl IdGenerator {
+    pub fn new(notebook: &Notebook, author: u8) -> Self {
+        let next = notebook.max_id().map_or(1, |id| id.counter + 1);
+        IdGenerator { author, next }
+    }
+
+    pub fn next_id(&mut self) -> CrdtId {
+        self.reserve(1)
+    }
+
+    /// Reserve `n` consecutive ids and return the first, e.g. for a run of text.
+    pub fn reserve(&mut self, n: u64) -> CrdtId {
+        let id = CrdtId::new(self.author, self.next);
+        self.next += n.max(1);
+        id
+    }
+}
+
+/// The low nibble of every tag, saying what kind of value follows it. The rest of
+/// the tag is the field's index within its block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagType {
+    /// A CRDT id
+    Id = 0xf,
+    /// A subblock: u32 length, then that many bytes
+    Length4 = 0xc,
+    /// f64
+    Byte8 = 0x8,
+    /// u32 or f32
+    Byte4 = 0x4,
+    /// u8 or bool
+    Byte1 = 0x1,
+}
+
+/// A last-write-wins value: the value itself, and the id of the edit that set it.
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct LwwValue<T> {
+    pub timestamp: CrdtId,
+    pub value: T,
+}
+
 pub type RawBytes = Vec<u8>;
 
+/// The fixed-size header at the start of every lines file, e.g.
+/// `reMarkable .lines file, version=6          `.
 #[derive(Debug, Clone, Serialize)]
 pub struct Frontmatter {
+    /// Format version. 3 and 5 are the older fixed-layout formats (firmware 2.x),
+    /// 6 is the tagged-block format (firmware 3.x).
     pub version: u8,
-    pub unknown: Vec<u8>,
+    /// Number of spaces after the version number that fill the header out to
+    /// `HEADER_LEN` bytes.
+    pub padding: usize,
+}
+
+impl Frontmatter {
+    pub fn new(version: u8) -> Self {
+        let padding = HEADER_LEN - VERSION_HEADER.len() - version.to_string().len();
+        Frontmatter { version, padding }
+    }
+
+    /// Whether this is one of the pre-v6 formats.
+    pub fn is_legacy(&self) -> bool {
+        self.version < 6
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Default)]
 pub struct SimpleLine {
-   pub layer_id: IdField, 
-   pub line_id: IdField, 
-   pub last_line_id: IdField, 
-   pub id_field_0: IdField, 
+   pub layer_id: CrdtId, 
+   pub line_id: CrdtId, 
+   pub last_line_id: CrdtId, 
+   pub id_field_0: CrdtId, 
    pub points: Vec<SimplePoint>,
 }
 
-#[allow(dead_code, unused_variables)]
 impl SimpleLine {
+    /// Apply an SVG `transform` attribute, in SVG coordinates (x measured from the
+    /// left edge of the page).
     pub fn transform(&mut self, commands: &str) -> Result<()> {
-        Err(RMError::NotImplementedError)
+        let transform: Transform = commands.parse()?;
+        for point in self.points.iter_mut() {
+            (point.x, point.y) = transform_page_point(&transform, point.x, point.y);
+        };
+        Ok(())
     }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Line {
-   pub layer_id: IdField, 
-   pub line_id: IdField, 
-   pub last_line_id: IdField, 
-   pub id_field_0: IdField, 
-   pub pen_type: Option<u32>, 
-   pub color: Option<u32>, 
-   pub brush_size: Option<f32>, 
+   pub layer_id: CrdtId, 
+   pub line_id: CrdtId, 
+   pub last_line_id: CrdtId, 
+   pub id_field_0: CrdtId, 
+   /// Non-zero once the line has been erased. Erased lines usually have no pen,
+   /// color or points left, but sometimes they do.
+   pub deleted_length: u32,
+   pub pen_type: Option<PenType>, 
+   pub color: Option<PenColor>, 
+   pub brush_size: Option<f64>, 
+   pub starting_length: f32,
    pub points: Vec<Point>,
+   pub timestamp: CrdtId,
+   pub move_id: Option<CrdtId>,
+   /// Anything after the fields we know, written back as it was.
+   pub unknown_unsized: Vec<u8>,
 }
 
-#[allow(dead_code, unused_variables)]
 impl Line {
-    pub fn transform(&mut self, commands: &str) -> Result<()> {
-        Err(RMError::NotImplementedError)
-        //let tfm = tfm.strip_prefix("matrix(").unwrap();
-        //let tfm = tfm.trim_end_matches(")");
-        //let mut coeffs = Vec::new();
-        //for coeff in tfm.split(",") {
-        //    coeffs.push(coeff.trim_start().parse::<f32>().unwrap());
-        //};
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_length > 0
+    }
+
+    /// Set how wide the whole line is drawn, in page units.
+    pub fn set_width(&mut self, width: f64) {
+        let width = (width * 4.0).round().clamp(0.0, u16::MAX as f64) as u16;
+        for point in self.points.iter_mut() {
+            point.width = width;
+        };
+    }
+
+    /// Change whichever of the pen, color and width are given.
+    pub fn restyle(&mut self, pen: Option<PenType>, color: Option<PenColor>, width: Option<f64>) {
+        if let Some(pen) = pen {
+            self.pen_type = Some(pen);
+        };
+        if let Some(color) = color {
+            self.color = Some(color);
+        };
+        if let Some(width) = width {
+            self.set_width(width);
+        };
+    }
 
-        //pts = pts.iter().map(|(_x, _y)| {
-        //    let x = coeffs.get(0).unwrap() * _x  + coeffs.get(2).unwrap() * _y + coeffs.get(4).unwrap();
-        //    let y = coeffs.get(1).unwrap() * _x  + coeffs.get(3).unwrap() * _y + coeffs.get(5).unwrap();
-        //    (x,y)
-        //}).collect();
+    /// Apply an SVG `transform` attribute, as for `SimpleLine::transform`.
+    pub fn transform(&mut self, commands: &str) -> Result<()> {
+        let transform: Transform = commands.parse()?;
+        for point in self.points.iter_mut() {
+            (point.x, point.y) = transform_page_point(&transform, point.x, point.y);
+        };
+        Ok(())
     }
 }
 
+fn transform_page_point(transform: &Transform, x: f32, y: f32) -> (f32, f32) {
+    let (x, y) = transform.apply(((x + HALF_WIDTH) as f64, y as f64));
+    (x as f32 - HALF_WIDTH, y as f32)
+}
+
 impl From<SimpleLine> for Line {
     fn from(line: SimpleLine) -> Self {
-        let mut out = Line::default();
-        out.layer_id = line.layer_id;
-        out.line_id = line.line_id;
-        out.last_line_id = line.last_line_id;
-        out.id_field_0 = line.id_field_0;
-        out.points = line.points.into_iter().map(Point::from).collect();
-        out
+        Line {
+            layer_id: line.layer_id,
+            line_id: line.line_id,
+            last_line_id: line.last_line_id,
+            id_field_0: line.id_field_0,
+            points: line.points.into_iter().map(Point::from).collect(),
+            ..Line::default()
+        }
     }
 
 }
@@ -138,18 +452,208 @@ impl From<SimpleLine> for Line {
 impl Default for Line {
     fn default() -> Line {
         Line {
-            layer_id: IdField([0x00, 0x0b, 0x00]), 
-            line_id: IdField::default(),
-            last_line_id: IdField::default(),
-            id_field_0: IdField::default(),
-            pen_type: Some(17),
-            color: Some(0),
+            layer_id: CrdtId::new(0, 11), 
+            line_id: CrdtId::default(),
+            last_line_id: CrdtId::default(),
+            id_field_0: CrdtId::default(),
+            deleted_length: 0,
+            pen_type: Some(PenType::default()),
+            color: Some(PenColor::default()),
             brush_size: Some(2.0),
+            starting_length: 0.0,
             points: Vec::new(),
+            timestamp: CrdtId::new(0, 1),
+            move_id: None,
+            unknown_unsized: Vec::new(),
+        }
+    }
+}
+
+/// The pen a line was drawn with. Most pens have two codes: an older one, ending
+/// in 1, and the one current firmware uses, ending in 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Default)]
+pub enum PenType {
+    Paintbrush1,
+    Pencil1,
+    Ballpoint1,
+    Marker1,
+    Fineliner1,
+    Highlighter1,
+    Eraser,
+    MechanicalPencil1,
+    EraserArea,
+    Paintbrush2,
+    MechanicalPencil2,
+    Pencil2,
+    Ballpoint2,
+    Marker2,
+    #[default]
+    Fineliner2,
+    Highlighter2,
+    Calligraphy,
+    Shader,
+    /// A code we don't know, kept so the line can be written back unchanged.
+    Unknown(u32),
+}
+
+impl PenType {
+    /// Every known pen, with its code and name. The current version of each pen
+    /// gets the plain name, e.g. `fineliner`; the old one gets `fineliner-1`.
+    const KNOWN: [(PenType, u32, &'static str); 18] = [
+        (PenType::Paintbrush1, 0, "paintbrush-1"),
+        (PenType::Pencil1, 1, "pencil-1"),
+        (PenType::Ballpoint1, 2, "ballpoint-1"),
+        (PenType::Marker1, 3, "marker-1"),
+        (PenType::Fineliner1, 4, "fineliner-1"),
+        (PenType::Highlighter1, 5, "highlighter-1"),
+        (PenType::Eraser, 6, "eraser"),
+        (PenType::MechanicalPencil1, 7, "mechanical-pencil-1"),
+        (PenType::EraserArea, 8, "eraser-area"),
+        (PenType::Paintbrush2, 12, "paintbrush"),
+        (PenType::MechanicalPencil2, 13, "mechanical-pencil"),
+        (PenType::Pencil2, 14, "pencil"),
+        (PenType::Ballpoint2, 15, "ballpoint"),
+        (PenType::Marker2, 16, "marker"),
+        (PenType::Fineliner2, 17, "fineliner"),
+        (PenType::Highlighter2, 18, "highlighter"),
+        (PenType::Calligraphy, 21, "calligraphy"),
+        (PenType::Shader, 23, "shader"),
+    ];
+}
+
+impl From<u32> for PenType {
+    fn from(value: u32) -> Self {
+        PenType::KNOWN.iter()
+                      .find(|(_, code, _)| *code == value)
+                      .map_or(PenType::Unknown(value), |(pen, _, _)| *pen)
+    }
+}
+
+impl From<PenType> for u32 {
+    fn from(value: PenType) -> Self {
+        match value {
+            PenType::Unknown(code) => code,
+            pen => PenType::KNOWN.iter().find(|(p, _, _)| *p == pen).map(|(_, code, _)| *code).unwrap(),
+        }
+    }
+}
+
+impl std::fmt::Display for PenType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match PenType::KNOWN.iter().find(|(p, _, _)| p == self) {
+            Some((_, _, name)) => write!(f, "{}", name),
+            None => write!(f, "{}", u32::from(*self)),
+        }
+    }
+}
+
+/// Accepts the names from `Display`, ignoring case and treating `_` and spaces
+/// like `-`, or a raw pen code.
+impl std::str::FromStr for PenType {
+    type Err = RMError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = normalize_name(s);
+        if let Ok(code) = name.parse::<u32>() {
+            return Ok(PenType::from(code));
+        };
+        PenType::KNOWN.iter()
+                      .find(|(_, _, n)| *n == name)
+                      .map(|(pen, _, _)| *pen)
+                      .ok_or(RMError::ArgsError(format!("unknown pen {:?}", s)))
+    }
+}
+
+/// The color a line was drawn in. Which colors are available depends on the pen
+/// and the tablet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Default)]
+pub enum PenColor {
+    #[default]
+    Black,
+    Gray,
+    White,
+    Yellow,
+    Green,
+    Pink,
+    Blue,
+    Red,
+    GrayOverlap,
+    Highlight,
+    Green2,
+    Cyan,
+    Magenta,
+    Yellow2,
+    /// A code we don't know, kept so the line can be written back unchanged.
+    Unknown(u32),
+}
+
+impl PenColor {
+    /// Every known color, with its code and name.
+    const KNOWN: [(PenColor, u32, &'static str); 14] = [
+        (PenColor::Black, 0, "black"),
+        (PenColor::Gray, 1, "gray"),
+        (PenColor::White, 2, "white"),
+        (PenColor::Yellow, 3, "yellow"),
+        (PenColor::Green, 4, "green"),
+        (PenColor::Pink, 5, "pink"),
+        (PenColor::Blue, 6, "blue"),
+        (PenColor::Red, 7, "red"),
+        (PenColor::GrayOverlap, 8, "gray-overlap"),
+        (PenColor::Highlight, 9, "highlight"),
+        (PenColor::Green2, 10, "green-2"),
+        (PenColor::Cyan, 11, "cyan"),
+        (PenColor::Magenta, 12, "magenta"),
+        (PenColor::Yellow2, 13, "yellow-2"),
+    ];
+}
+
+impl From<u32> for PenColor {
+    fn from(value: u32) -> Self {
+        PenColor::KNOWN.iter()
+                       .find(|(_, code, _)| *code == value)
+                       .map_or(PenColor::Unknown(value), |(color, _, _)| *color)
+    }
+}
+
+impl From<PenColor> for u32 {
+    fn from(value: PenColor) -> Self {
+        match value {
+            PenColor::Unknown(code) => code,
+            color => PenColor::KNOWN.iter().find(|(c, _, _)| *c == color).map(|(_, code, _)| *code).unwrap(),
+        }
+    }
+}
+
+impl std::fmt::Display for PenColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match PenColor::KNOWN.iter().find(|(c, _, _)| c == self) {
+            Some((_, _, name)) => write!(f, "{}", name),
+            None => write!(f, "{}", u32::from(*self)),
         }
     }
 }
 
+/// Accepts the names from `Display` (and `grey`), ignoring case and treating `_`
+/// and spaces like `-`, or a raw color code.
+impl std::str::FromStr for PenColor {
+    type Err = RMError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = normalize_name(s).replace("grey", "gray");
+        if let Ok(code) = name.parse::<u32>() {
+            return Ok(PenColor::from(code));
+        };
+        PenColor::KNOWN.iter()
+                       .find(|(_, _, n)| *n == name)
+                       .map(|(color, _, _)| *color)
+                       .ok_or(RMError::ArgsError(format!("unknown color {:?}", s)))
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase().replace(['_', ' '], "-")
+}
+
 #[derive(Debug, Clone, Serialize, Default)]
 pub struct SimplePoint {
     pub x: f32,
@@ -157,7 +661,7 @@ pub struct SimplePoint {
 }
 
 impl SimplePoint {
-    pub fn to_point(&self, speed: u8, width: u8, direction: u8, pressure: u8) -> Point {
+    pub fn to_point(&self, speed: u16, width: u16, direction: u8, pressure: u8) -> Point {
         Point {
             x: self.x,
             y: self.y,
@@ -172,8 +676,8 @@ impl SimplePoint {
 impl From<(f32,f32)> for SimplePoint {
     fn from((x,y): (f32,f32)) -> Self {
         SimplePoint {
-            x: x.clone()-HALF_WIDTH,
-            y: y.clone(),
+            x: x-HALF_WIDTH,
+            y,
         }
     }
 }
@@ -182,69 +686,179 @@ impl From<(f32,f32)> for SimplePoint {
 pub struct Point {
     pub x: f32,
     pub y: f32,
-    pub speed: u8,
-    pub width: u8,
+    pub speed: u16,
+    pub width: u16,
     pub direction: u8,
     pub pressure: u8,
 }
 
+/// The width new points get: that of a medium fineliner.
+pub const DEFAULT_POINT_WIDTH: u16 = 16;
+
 impl From<SimplePoint> for Point {
     fn from(point: SimplePoint) -> Self {
-        let mut out = Point::default();
-        out.x = point.x;
-        out.y = point.y;
-        out
+        Point {
+            x: point.x,
+            y: point.y,
+            width: DEFAULT_POINT_WIDTH,
+            pressure: u8::MAX,
+            ..Point::default()
+        }
     }
 }
 
+/// A scene tree block: declares that the group `layer_id` lives under `parent_id`.
 #[derive(Debug, Clone, Serialize)]
 pub struct LayerDef {
-    pub layer_id: IdField,
-    pub unknown_1: Vec<u8>, //4 bytes
-    pub unknown_2: Vec<u8>, //?? bytes
+    pub layer_id: CrdtId,
+    pub node_id: CrdtId,
+    pub is_update: bool,
+    pub parent_id: CrdtId,
 }
 
+/// A tree node block: the name and visibility of the group `layer_id`.
 #[derive(Debug, Clone, Serialize)]
 pub struct LayerName {
-    pub layer_id: IdField,
-    pub id_field_0: IdField,
-    pub name: String,
-    pub unknown_rest: Vec<u8>,
+    pub layer_id: CrdtId,
+    pub name: LwwValue<String>,
+    pub visible: LwwValue<bool>,
+    /// Anything after the visibility, e.g. the anchor fields some firmware adds.
+    pub unknown_unsized: Vec<u8>,
 }
 
+/// A group item: places the group `layer_id` in the children of `id_field_0`.
 #[derive(Debug, Clone, Serialize)]
 pub struct LayerInfo {
-    pub id_field_0: IdField,
-    pub id_field_1: IdField,
-    pub id_field_2: IdField,
-    pub id_field_3: IdField,
-    pub layer_id: Option<IdField>
+    pub id_field_0: CrdtId,
+    pub id_field_1: CrdtId,
+    pub id_field_2: CrdtId,
+    pub id_field_3: CrdtId,
+    pub deleted_length: u32,
+    pub layer_id: Option<CrdtId>
 }
 
+/// The root text block, holding the page's typed text.
 #[derive(Debug, Clone, Serialize)]
 pub struct TextDef {
-    pub id_field_0: IdField,
+    pub id_field_0: CrdtId,
     pub texts: Vec<TextChunk>,
     pub backmatter: Vec<TextBackmatter>,
-    pub unknown_sized: String,
-    pub unknown_unsized: String,
+    pub pos_x: f64,
+    pub pos_y: f64,
+    pub width: f32,
+    /// Anything after the fields we know, written back as it was.
+    pub unknown_unsized: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct TextChunk {
-    pub chunk_id: IdField,
-    pub other_chunk_id_0: IdField,
-    pub other_chunk_id_1: IdField,
-    pub done_flag: u32,
+    pub chunk_id: CrdtId,
+    pub other_chunk_id_0: CrdtId,
+    pub other_chunk_id_1: CrdtId,
+    /// How many characters, from `chunk_id` on, have been deleted. Deleted
+    /// chunks have no text.
+    pub deleted_length: u32,
     pub text: String,
-    pub magic_dollar: Option<u32>,
+    /// Set on the empty items that switch inline formatting on or off; see
+    /// `text::InlineFormat`.
+    pub inline_format: Option<u32>,
 }
 
+/// Formatting for the text starting at `char_id`.
 #[derive(Debug, Clone, Serialize)]
 pub struct TextBackmatter {
-    pub id_field_0: IdField,
-    pub id_field_1: IdField,
-    pub id_field_2: IdField,
+    pub char_id: CrdtId,
+    pub timestamp: CrdtId,
+    pub format: u8,
+}
+
+/// The fields every scene item starts with.
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ItemHeader {
+    /// The group the item belongs to
+    pub parent_id: CrdtId,
+    pub item_id: CrdtId,
+    /// The items either side of this one in its parent's children; 0:0 for the ends.
+    pub left_id: CrdtId,
+    pub right_id: CrdtId,
+    pub deleted_length: u32,
+}
+
+impl ItemHeader {
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_length > 0
+    }
+}
+
+/// A highlight over text in an underlying PDF or EPUB.
+#[derive(Debug, Clone, Serialize)]
+pub struct Glyph {
+    pub header: ItemHeader,
+    pub value: Option<GlyphValue>,
+    /// Anything after the fields we know, written back as it was.
+    pub unknown_unsized: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GlyphValue {
+    pub start: Option<u32>,
+    pub length: u32,
+    pub color: PenColor,
+    /// The highlighted text
+    pub text: String,
+    pub rectangles: Vec<Rect>,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+/// A text item in a group. Its value isn't understood yet, so it's kept as bytes.
+#[derive(Debug, Clone, Serialize)]
+pub struct TextItem {
+    pub header: ItemHeader,
+    pub value: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationInfo {
+    pub migration_id: CrdtId,
+    pub is_device: bool,
+    pub unknown: Option<bool>,
+}
+
+/// Maps the author numbers in CRDT ids to the UUIDs of the devices behind them.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthorIds {
+    pub authors: Vec<AuthorId>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthorId {
+    pub uuid: Vec<u8>,
+    pub author: u16,
+}
+
+/// Usage counters the tablet keeps for each page.
+#[derive(Debug, Clone, Serialize)]
+pub struct PageInfo {
+    pub loads_count: u32,
+    pub merges_count: u32,
+    pub text_chars_count: u32,
+    pub text_lines_count: u32,
+    pub type_folio_use_count: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SceneInfo {
+    pub current_layer: LwwValue<CrdtId>,
+    pub background_visible: Option<LwwValue<bool>>,
+    pub root_document_visible: Option<LwwValue<bool>>,
+    pub paper_size: Option<(u32, u32)>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -254,27 +868,115 @@ pub enum Block{
     TextDef(TextDef),
     LayerName(LayerName),
     LayerInfo(LayerInfo),
+    Glyph(Glyph),
+    TextItem(TextItem),
+    /// What's left of a deleted item: just its place in its group.
+    Tombstone(ItemHeader),
+    MigrationInfo(MigrationInfo),
+    AuthorIds(AuthorIds),
+    PageInfo(PageInfo),
+    SceneInfo(SceneInfo),
     Unknown(Vec<u8>, Vec<u8>),
 }
 
-impl TextChunk {
-    pub fn to_markdown(&self) -> String {
-        match self.magic_dollar {
-            None => {
-                todo!()
+impl Block {
+    /// Every id this block uses, including one per character of text.
+    pub fn ids(&self) -> Vec<CrdtId> {
+        match self {
+            Block::Line(l) => {
+                let mut ids = vec!(l.layer_id, l.line_id, l.last_line_id, l.id_field_0, l.timestamp);
+                ids.extend(l.move_id);
+                ids
             },
-            Some(4) => {
-                todo!()
+            Block::LayerDef(l) => vec!(l.layer_id, l.node_id, l.parent_id),
+            Block::LayerName(l) => vec!(l.layer_id, l.name.timestamp, l.visible.timestamp),
+            Block::LayerInfo(i) => {
+                let mut ids = vec!(i.id_field_0, i.id_field_1, i.id_field_2, i.id_field_3);
+                ids.extend(i.layer_id);
+                ids
+            },
+            Block::TextDef(t) => {
+                let mut ids = vec!(t.id_field_0);
+                for chunk in &t.texts {
+                    ids.extend([chunk.last_char_id(), chunk.other_chunk_id_0, chunk.other_chunk_id_1]);
+                };
+                for back in &t.backmatter {
+                    ids.extend([back.char_id, back.timestamp]);
+                };
+                ids
             },
-            _ => {
-                todo!()
+            Block::Glyph(Glyph { header, .. })
+            | Block::TextItem(TextItem { header, .. })
+            | Block::Tombstone(header) => {
+                vec!(header.parent_id, header.item_id, header.left_id, header.right_id)
             },
+            Block::MigrationInfo(m) => vec!(m.migration_id),
+            Block::SceneInfo(i) => {
+                let mut ids = vec!(i.current_layer.timestamp, i.current_layer.value);
+                ids.extend(i.background_visible.iter().map(|v| v.timestamp));
+                ids.extend(i.root_document_visible.iter().map(|v| v.timestamp));
+                ids
+            },
+            Block::AuthorIds(_) | Block::PageInfo(_) | Block::Unknown(_, _) => Vec::new(),
         }
+    }
+}
+
+/// Where the tablet puts a new page's text box, and how wide it is.
+pub const TEXT_BOX_X: f64 = -468.0;
+pub const TEXT_BOX_Y: f64 = 234.0;
+pub const TEXT_BOX_WIDTH: f32 = 936.0;
+
+/// The paragraph format code for plain text.
+pub const PLAIN_FORMAT: u8 = 1;
+
+impl TextDef {
+    /// A text box in the tablet's default place, with no text in it.
+    pub fn new() -> Self {
+        TextDef {
+            id_field_0: CrdtId::default(),
+            texts: Vec::new(),
+            backmatter: Vec::new(),
+            pos_x: TEXT_BOX_X,
+            pos_y: TEXT_BOX_Y,
+            width: TEXT_BOX_WIDTH,
+            unknown_unsized: Vec::new(),
+        }
+    }
+
+    /// All the text that hasn't been deleted, in the order it's shown.
+    pub fn plain_text(&self) -> String {
+        text_sequence(self).into_iter()
+                           .filter_map(|item| match item.value {
+                               TextValue::Char(c) => Some(c),
+                               _ => None,
+                           })
+                           .collect()
+    }
+
+    /// The id of the last character shown, or deleted from the end; 0:0 if
+    /// there are none.
+    pub fn last_char_id(&self) -> CrdtId {
+        text_sequence(self).last().map_or(CrdtId::default(), |item| item.id)
+    }
+}
+
+impl Default for TextDef {
+    fn default() -> Self {
+        TextDef::new()
+    }
+}
 
+impl TextChunk {
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_length > 0
     }
 
-    pub fn to_svg(&self) -> String {
-        todo!()
+    /// Each character has its own id, counting up from `chunk_id`. This is the
+    /// id of the last one.
+    pub fn last_char_id(&self) -> CrdtId {
+        let len = self.text.chars().count().max(self.deleted_length as usize) as u64;
+        self.chunk_id.offset(len.saturating_sub(1))
     }
 
     pub fn to_raw_text(&self) -> String {
@@ -282,7 +984,7 @@ impl TextChunk {
     }
 }
 
-impl <'a> std::fmt::Display for Block {
+impl std::fmt::Display for Block {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -296,11 +998,32 @@ impl <'a> std::fmt::Display for Block {
                 write!(f, "Block::TextDef: id {:?}", text.id_field_0)
             },
             Block::LayerName(name) => {
-                write!(f, "Block::LayerName: id {:?}, name '{}'", name.layer_id, name.name)
+                write!(f, "Block::LayerName: id {:?}, name '{}'", name.layer_id, name.name.value)
             },
             Block::LayerInfo(info) => {
                 write!(f, "Block::LayerInfo: id {:?}", info.layer_id)
             },
+            Block::Glyph(glyph) => {
+                write!(f, "Block::Glyph: id {:?}", glyph.header.item_id)
+            },
+            Block::TextItem(item) => {
+                write!(f, "Block::TextItem: id {:?}", item.header.item_id)
+            },
+            Block::Tombstone(header) => {
+                write!(f, "Block::Tombstone: id {:?}", header.item_id)
+            },
+            Block::MigrationInfo(info) => {
+                write!(f, "Block::MigrationInfo: id {:?}", info.migration_id)
+            },
+            Block::AuthorIds(ids) => {
+                write!(f, "Block::AuthorIds: {} authors", ids.authors.len())
+            },
+            Block::PageInfo(info) => {
+                write!(f, "Block::PageInfo: loaded {} times", info.loads_count)
+            },
+            Block::SceneInfo(info) => {
+                write!(f, "Block::SceneInfo: current layer {:?}", info.current_layer.value)
+            },
             Block::Unknown(flag, raw) => {
                 write!(f, "Block::Unknown: flag {}, bytelen {}", flag.to_hex(4), raw.len())
             },
@@ -310,109 +1033,370 @@ impl <'a> std::fmt::Display for Block {
 
 impl From<Block> for RawBytes {
     fn from(value: Block) -> Self {
-        match value {
-            Block::Line(l) => {
-                let mut raw_line = RawBytes::from(l);
-                let len = raw_line.len() as u32;
-                let mut out = Vec::new();
-                out.append(&mut len.to_le_bytes().to_vec());
-                out.append(&mut vec!(0,2,2,5));
-                out.append(&mut raw_line);
-                out
-            },
-            _ => {
-                Vec::new()
-            }
-        }
+        let (flag, mut body) = match value {
+            Block::Line(l) => (LINE_FLAG.to_vec(), RawBytes::from(l)),
+            Block::LayerDef(l) => (LAYER_DEF_FLAG.to_vec(), RawBytes::from(l)),
+            Block::TextDef(t) => (TEXT_DEF_FLAG.to_vec(), RawBytes::from(t)),
+            Block::LayerName(n) => (LAYER_NAME_FLAG.to_vec(), RawBytes::from(n)),
+            Block::LayerInfo(i) => (LAYER_INFO_FLAG.to_vec(), RawBytes::from(i)),
+            Block::Glyph(g) => (GLYPH_FLAG.to_vec(), RawBytes::from(g)),
+            Block::TextItem(t) => (TEXT_ITEM_FLAG.to_vec(), RawBytes::from(t)),
+            Block::Tombstone(h) => (TOMBSTONE_FLAG.to_vec(), RawBytes::from(h)),
+            Block::MigrationInfo(m) => (MIGRATION_INFO_FLAG.to_vec(), RawBytes::from(m)),
+            Block::AuthorIds(a) => (AUTHOR_IDS_FLAG.to_vec(), RawBytes::from(a)),
+            Block::PageInfo(p) => (PAGE_INFO_FLAG.to_vec(), RawBytes::from(p)),
+            Block::SceneInfo(i) => (SCENE_INFO_FLAG.to_vec(), RawBytes::from(i)),
+            Block::Unknown(flag, raw) => (flag, raw),
+        };
+
+        let mut out = Vec::new();
+        out.extend((body.len() as u32).to_le_bytes());
+        out.extend(flag);
+        out.append(&mut body);
+        out
     }
 }
 
-impl From<&[u8]> for IdField {
-    fn from(value: &[u8]) -> Self {
-        IdField(value.try_into().unwrap())
+impl From<Frontmatter> for RawBytes {
+    fn from(value: Frontmatter) -> Self {
+        format!("{}{}{}", VERSION_HEADER, value.version, " ".repeat(value.padding)).into_bytes()
     }
 }
 
-impl From<Vec<u8>> for IdField {
-    fn from(value: Vec<u8>) -> Self {
-        IdField(value.try_into().unwrap())
+impl From<Notebook> for RawBytes {
+    fn from(value: Notebook) -> Self {
+        let mut out = RawBytes::from(value.frontmatter);
+        for block in value.blocks {
+            out.append(&mut RawBytes::from(block));
+        };
+        out
     }
 }
 
-impl From<IdField> for RawBytes {
-    fn from(value: IdField) -> Self {
-        let mut out = Vec::new();
-        out.push(value.0[0]);
-        out.push(value.0[1]);
-        if value.0[2] > 0 {
-            out.push(value.0[2]);
+/// Encode `value` as a LEB128 varint.
+pub fn varuint(mut value: u64) -> RawBytes {
+    let mut out = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return out;
         };
-        out
+        out.push(byte | 0x80);
+    }
+}
+
+/// A field's tag: its index and type packed into one varint.
+pub fn field_tag(index: u8, tag_type: TagType) -> RawBytes {
+    varuint(((index as u64) << 4) | tag_type as u64)
+}
+
+fn put_id(index: u8, value: CrdtId) -> RawBytes {
+    [field_tag(index, TagType::Id), RawBytes::from(value)].concat()
+}
+
+fn put_u8(index: u8, value: u8) -> RawBytes {
+    [field_tag(index, TagType::Byte1), vec!(value)].concat()
+}
+
+fn put_u32(index: u8, value: u32) -> RawBytes {
+    [field_tag(index, TagType::Byte4), value.to_le_bytes().to_vec()].concat()
+}
+
+fn put_f32(index: u8, value: f32) -> RawBytes {
+    [field_tag(index, TagType::Byte4), value.to_le_bytes().to_vec()].concat()
+}
+
+fn put_f64(index: u8, value: f64) -> RawBytes {
+    [field_tag(index, TagType::Byte8), value.to_le_bytes().to_vec()].concat()
+}
+
+/// Write a length-prefixed subblock: the tag, the length of `body` as a
+/// little-endian u32, and then `body` itself.
+fn subblock(index: u8, mut body: RawBytes) -> RawBytes {
+    let mut out = field_tag(index, TagType::Length4);
+    out.extend((body.len() as u32).to_le_bytes());
+    out.append(&mut body);
+    out
+}
+
+/// A string is its length as a varint, a flag that's always 1, then the UTF-8 bytes.
+fn put_string(value: String) -> RawBytes {
+    let bytes = value.into_bytes();
+    [varuint(bytes.len() as u64), vec!(0x01), bytes].concat()
+}
+
+/// A last-write-wins value is a subblock holding the timestamp as field 1 and
+/// the value as field 2.
+fn lww(index: u8, timestamp: CrdtId, value: RawBytes) -> RawBytes {
+    subblock(index, [put_id(1, timestamp), value].concat())
+}
+
+impl From<CrdtId> for RawBytes {
+    fn from(value: CrdtId) -> Self {
+        [vec!(value.author), varuint(value.counter)].concat()
     }
 }
 
 impl From<Point> for RawBytes {
     fn from(value: Point) -> Self {
-        let list = vec!(
+        let list = [
             value.x.to_le_bytes().to_vec(),
             value.y.to_le_bytes().to_vec(),
-            vec!(value.speed),
-            vec!(0),
-            vec!(value.width),
-            vec!(0),
+            value.speed.to_le_bytes().to_vec(),
+            value.width.to_le_bytes().to_vec(),
             vec!(value.direction),
             vec!(value.pressure),
-        );
+        ];
 
         list.concat()
     }
 }
 
-#[allow(unused_variables)]
 impl From<Line> for RawBytes {
     fn from(value: Line) -> Self {
-        let mut out = Vec::new();
-        out.push(0x1f);
-        out.append(&mut RawBytes::from(value.layer_id));
-        out.push(0x2f);
-        out.append(&mut RawBytes::from(value.line_id));
-        out.push(0x3f);
-        out.append(&mut RawBytes::from(value.last_line_id));
-        out.push(0x4f);
-        out.append(&mut RawBytes::from(value.id_field_0));
-        out.push(0x54);
-
-        match value.pen_type {
-            None => {},
-            Some(pt) => {
-                out.append(&mut vec!(0,0,0,0));
-                out.push(0x6c);
-                
-                let mut rest = Vec::new();
-                rest.append(&mut vec!(0x03, 0x14));
-                rest.append(&mut pt.to_le_bytes().to_vec());
-                rest.push(0x24);
-                rest.append(&mut value.color.unwrap().to_le_bytes().to_vec());
-                rest.append(&mut vec!(0x38,0,0,0,0));
-                rest.append(&mut value.brush_size.unwrap().to_le_bytes().to_vec());
-                rest.append(&mut vec!(0x44,0,0,0,0,0x5c));
-                rest.append(&mut (14*value.points.len() as u32).to_le_bytes().to_vec());
-                for p in value.points {
-                    rest.append(&mut RawBytes::from(p));
+        let mut out = [
+            put_id(1, value.layer_id),
+            put_id(2, value.line_id),
+            put_id(3, value.last_line_id),
+            put_id(4, value.id_field_0),
+            put_u32(5, value.deleted_length),
+        ].concat();
+
+        if let Some(pt) = value.pen_type {
+            let mut points = Vec::new();
+            for p in value.points {
+                points.append(&mut RawBytes::from(p));
+            };
+
+            let mut rest = vec!(0x03);
+            rest.append(&mut put_u32(1, pt.into()));
+            rest.append(&mut put_u32(2, value.color.unwrap_or_default().into()));
+            rest.append(&mut put_f64(3, value.brush_size.unwrap_or_default()));
+            rest.append(&mut put_f32(4, value.starting_length));
+            rest.append(&mut subblock(5, points));
+            rest.append(&mut put_id(6, value.timestamp));
+            if let Some(move_id) = value.move_id {
+                rest.append(&mut put_id(7, move_id));
+            };
+
+            out.append(&mut subblock(6, rest));
+        };
+        out.extend(value.unknown_unsized);
+
+        out
+    }
+}
+
+impl From<LayerDef> for RawBytes {
+    fn from(value: LayerDef) -> Self {
+        [
+            put_id(1, value.layer_id),
+            put_id(2, value.node_id),
+            put_u8(3, value.is_update as u8),
+            subblock(4, put_id(1, value.parent_id)),
+        ].concat()
+    }
+}
+
+impl From<LayerName> for RawBytes {
+    fn from(value: LayerName) -> Self {
+        let name = subblock(2, put_string(value.name.value));
+        let visible = put_u8(2, value.visible.value as u8);
+
+        [
+            put_id(1, value.layer_id),
+            lww(2, value.name.timestamp, name),
+            lww(3, value.visible.timestamp, visible),
+            value.unknown_unsized,
+        ].concat()
+    }
+}
+
+impl From<LayerInfo> for RawBytes {
+    fn from(value: LayerInfo) -> Self {
+        let mut out = [
+            put_id(1, value.id_field_0),
+            put_id(2, value.id_field_1),
+            put_id(3, value.id_field_2),
+            put_id(4, value.id_field_3),
+            put_u32(5, value.deleted_length),
+        ].concat();
+
+        if let Some(layer_id) = value.layer_id {
+            let group = [vec!(0x02), put_id(2, layer_id)].concat();
+            out.append(&mut subblock(6, group));
+        };
+        out
+    }
+}
+
+impl From<TextDef> for RawBytes {
+    fn from(value: TextDef) -> Self {
+        let mut chunks = varuint(value.texts.len() as u64);
+        for chunk in value.texts {
+            chunks.append(&mut RawBytes::from(chunk));
+        };
+        let chunks = subblock(1, subblock(1, chunks));
+
+        let mut backmatter = varuint(value.backmatter.len() as u64);
+        for back in value.backmatter {
+            backmatter.append(&mut RawBytes::from(back));
+        };
+        let backmatter = subblock(2, subblock(1, backmatter));
+
+        let position = [value.pos_x.to_le_bytes(), value.pos_y.to_le_bytes()].concat();
+
+        [
+            put_id(1, value.id_field_0),
+            subblock(2, [chunks, backmatter].concat()),
+            subblock(3, position),
+            put_f32(4, value.width),
+            value.unknown_unsized,
+        ].concat()
+    }
+}
+
+impl From<TextChunk> for RawBytes {
+    fn from(value: TextChunk) -> Self {
+        let mut body = [
+            put_id(2, value.chunk_id),
+            put_id(3, value.other_chunk_id_0),
+            put_id(4, value.other_chunk_id_1),
+            put_u32(5, value.deleted_length),
+        ].concat();
+
+        if value.deleted_length == 0 {
+            let mut rest = put_string(value.text);
+            if let Some(format) = value.inline_format {
+                rest.append(&mut put_u32(2, format));
+            };
+            body.append(&mut subblock(6, rest));
+        };
+
+        subblock(0, body)
+    }
+}
+
+impl From<ItemHeader> for RawBytes {
+    fn from(value: ItemHeader) -> Self {
+        [
+            put_id(1, value.parent_id),
+            put_id(2, value.item_id),
+            put_id(3, value.left_id),
+            put_id(4, value.right_id),
+            put_u32(5, value.deleted_length),
+        ].concat()
+    }
+}
+
+impl From<Glyph> for RawBytes {
+    fn from(value: Glyph) -> Self {
+        let mut out = RawBytes::from(value.header);
+        if let Some(glyph) = value.value {
+            let mut rects = varuint(glyph.rectangles.len() as u64);
+            for r in glyph.rectangles {
+                for v in [r.x, r.y, r.w, r.h] {
+                    rects.extend(v.to_le_bytes());
                 };
+            };
 
-                dbg!(rest.len());
-                out.append(&mut (rest.len() as u32).to_le_bytes().to_vec());
-                out.append(&mut rest);
-            },
+            let mut rest = vec!(0x01);
+            if let Some(start) = glyph.start {
+                rest.append(&mut put_u32(2, start));
+            };
+            rest.append(&mut put_u32(3, glyph.length));
+            rest.append(&mut put_u32(4, glyph.color.into()));
+            rest.append(&mut subblock(5, put_string(glyph.text)));
+            rest.append(&mut subblock(6, rects));
+            out.append(&mut subblock(6, rest));
+        };
+        out.extend(value.unknown_unsized);
+        out
+    }
+}
+
+impl From<TextItem> for RawBytes {
+    fn from(value: TextItem) -> Self {
+        let mut out = RawBytes::from(value.header);
+        if let Some(raw) = value.value {
+            out.append(&mut subblock(6, raw));
+        };
+        out
+    }
+}
+
+impl From<MigrationInfo> for RawBytes {
+    fn from(value: MigrationInfo) -> Self {
+        let mut out = [
+            put_id(1, value.migration_id),
+            put_u8(2, value.is_device as u8),
+        ].concat();
+        if let Some(unknown) = value.unknown {
+            out.append(&mut put_u8(3, unknown as u8));
+        };
+        out
+    }
+}
+
+impl From<AuthorIds> for RawBytes {
+    fn from(value: AuthorIds) -> Self {
+        let mut out = varuint(value.authors.len() as u64);
+        for author in value.authors {
+            let body = [
+                varuint(author.uuid.len() as u64),
+                author.uuid,
+                author.author.to_le_bytes().to_vec(),
+            ].concat();
+            out.append(&mut subblock(0, body));
         };
+        out
+    }
+}
 
-        out.append(&mut vec!(0x6f, 0, 1));
-        
+impl From<PageInfo> for RawBytes {
+    fn from(value: PageInfo) -> Self {
+        let mut out = [
+            put_u32(1, value.loads_count),
+            put_u32(2, value.merges_count),
+            put_u32(3, value.text_chars_count),
+            put_u32(4, value.text_lines_count),
+        ].concat();
+        if let Some(count) = value.type_folio_use_count {
+            out.append(&mut put_u32(5, count));
+        };
         out
     }
 }
 
+impl From<SceneInfo> for RawBytes {
+    fn from(value: SceneInfo) -> Self {
+        let current = value.current_layer;
+        let mut out = lww(1, current.timestamp, put_id(2, current.value));
+        if let Some(v) = value.background_visible {
+            out.append(&mut lww(2, v.timestamp, put_u8(2, v.value as u8)));
+        };
+        if let Some(v) = value.root_document_visible {
+            out.append(&mut lww(3, v.timestamp, put_u8(2, v.value as u8)));
+        };
+        if let Some((w, h)) = value.paper_size {
+            out.append(&mut subblock(5, [w.to_le_bytes(), h.to_le_bytes()].concat()));
+        };
+        out
+    }
+}
+
+impl From<TextBackmatter> for RawBytes {
+    fn from(value: TextBackmatter) -> Self {
+        [
+            RawBytes::from(value.char_id),
+            put_id(1, value.timestamp),
+            subblock(2, vec!(TEXT_FORMAT_MARKER, value.format)),
+        ].concat()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -421,8 +1405,8 @@ mod test {
     fn dump_line() {
         let point = 
             Point {
-                x: -351.6873779296875,
-                y: 321.1515197753906,
+                x: -351.687_38,
+                y: 321.151_52,
                 speed: 1,
                 width: 16,
                 direction: 0,
@@ -431,20 +1415,25 @@ mod test {
 
         let line = 
             Line {
-               layer_id: IdField([0x00, 0x0b, 0x00]), 
-               line_id: IdField([0x01, 0x0e, 0x00]), 
-               last_line_id: IdField([0x00,0x00,0x00]), 
-               id_field_0: IdField([0x00,0x00,0x00]), 
-               pen_type: Some(17),
-               color: Some(0),
+               layer_id: CrdtId::new(0, 11), 
+               line_id: CrdtId::new(1, 14), 
+               last_line_id: CrdtId::new(0, 0), 
+               id_field_0: CrdtId::new(0, 0), 
+               deleted_length: 0,
+               pen_type: Some(PenType::Fineliner2),
+               color: Some(PenColor::Black),
                brush_size: Some(2.0),
+               starting_length: 0.0,
                points: vec!(point),
+               timestamp: CrdtId::new(0, 1),
+               move_id: None,
+               unknown_unsized: Vec::new(),
             };
 
         let correct = vec!( 
             0x1f, 0x00, 0x0b, 0x2f, 0x01, 0x0e, 0x3f, 0x00, 
             0x00, 0x4f, 0x00, 0x00, 0x54, 0x00, 0x00, 0x00, 
-            0x00, 0x6c, 0x2c, 0x00, 0x00, 0x00, 0x03, 0x14, 
+            0x00, 0x6c, 0x2f, 0x00, 0x00, 0x00, 0x03, 0x14, 
             0x11, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00, 0x00, 
             0x00, 0x38, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 
             0x00, 0x40, 0x44, 0x00, 0x00, 0x00, 0x00, 0x5c, 
@@ -457,6 +1446,111 @@ mod test {
 
 
     }
-}
 
+    #[test]
+    fn crdt_id_order() {
+        let mut ids = vec!(CrdtId::new(1, 3), CrdtId::new(0, 200), CrdtId::new(1, 2));
+        ids.sort();
+        assert_eq!(ids, vec!(CrdtId::new(0, 200), CrdtId::new(1, 2), CrdtId::new(1, 3)));
+        assert_eq!(CrdtId::new(1, 300).to_string(), "1:300");
+        assert_eq!(RawBytes::from(CrdtId::new(1, 300)), vec!(0x01, 0xac, 0x02));
+    }
+
+    #[test]
+    fn fresh_ids_dont_collide() {
+        let bytes = std::fs::read(crate::parse::TEST_FILE_01).unwrap();
+        let mut notebook = crate::parse::parse_full(&bytes).unwrap();
+        let max = notebook.max_id().unwrap();
+
+        let existing: Vec<CrdtId> = notebook.blocks.iter().flat_map(Block::ids).collect();
+        notebook.push_lines(vec!(Line::default(), Line::default()));
 
+        let new: Vec<&Line> = notebook.blocks.iter().rev().take(2).filter_map(|b| match b {
+            Block::Line(l) => Some(l),
+            _ => None,
+        }).collect();
+        assert_eq!(new.len(), 2);
+        for line in &new {
+            assert!(line.line_id.counter > max.counter);
+            assert!(!existing.contains(&line.line_id));
+        };
+        assert_ne!(new[0].line_id, new[1].line_id);
+        assert_eq!(new[0].last_line_id, new[1].line_id);
+    }
+
+    #[test]
+    fn push_after_glyph() {
+        let mut notebook = Notebook::blank(AuthorIds { authors: Vec::new() });
+        let layer_id = CrdtId::new(0, FIRST_LAYER_ID);
+        let line = Line { layer_id, line_id: CrdtId::new(1, 4), ..Line::default() };
+        // a highlight after the line, though it comes first in the file
+        let glyph = Glyph {
+            header: ItemHeader {
+                parent_id: layer_id,
+                item_id: CrdtId::new(1, 5),
+                left_id: line.line_id,
+                ..ItemHeader::default()
+            },
+            value: None,
+            unknown_unsized: Vec::new(),
+        };
+        notebook.blocks.push(Block::Glyph(glyph));
+        notebook.blocks.push(Block::Line(line));
+
+        notebook.push_lines(vec!(Line { layer_id, ..Line::default() }, Line { layer_id, ..Line::default() }));
+        let pushed: Vec<(CrdtId, CrdtId)> = notebook.blocks.iter().rev().take(2).filter_map(|b| match b {
+            Block::Line(l) => Some((l.line_id, l.last_line_id)),
+            _ => None,
+        }).collect();
+        assert_eq!(pushed[1].1, CrdtId::new(1, 5));
+        assert_eq!(pushed[0].1, pushed[1].0);
+    }
+
+    #[test]
+    fn push_text() {
+        let bytes = std::fs::read(crate::parse::TEST_FILE_01).unwrap();
+        let mut notebook = crate::parse::parse_full(&bytes).unwrap();
+        assert!(!notebook.blocks.iter().any(|b| matches!(b, Block::TextDef(_))));
+        let max = notebook.max_id().unwrap();
+
+        notebook.push_text("hello");
+        notebook.push_text("wörld");
+
+        let bytes = RawBytes::from(notebook);
+        let notebook = crate::parse::parse_full(&bytes).unwrap();
+        let def = notebook.blocks.iter().find_map(|b| match b {
+            Block::TextDef(def) => Some(def),
+            _ => None,
+        }).unwrap();
+
+        assert_eq!(def.plain_text(), "hello\nwörld");
+        assert_eq!((def.pos_x, def.pos_y, def.width), (TEXT_BOX_X, TEXT_BOX_Y, TEXT_BOX_WIDTH));
+        assert_eq!(def.backmatter[0].char_id, CrdtId::default());
+        assert_eq!(def.backmatter[0].format, PLAIN_FORMAT);
+
+        let (first, second) = (&def.texts[0], &def.texts[1]);
+        assert!(def.backmatter[0].timestamp.counter > max.counter);
+        assert!(first.chunk_id.counter > def.backmatter[0].timestamp.counter);
+        assert_eq!(first.other_chunk_id_0, CrdtId::default());
+        assert_eq!(second.other_chunk_id_0, first.chunk_id.offset(4));
+        assert_eq!(second.chunk_id, first.chunk_id.offset(5));
+        assert_eq!(def.last_char_id(), second.chunk_id.offset(5));
+    }
+
+    #[test]
+    fn pen_names() {
+        assert_eq!("fineliner".parse::<PenType>().unwrap(), PenType::Fineliner2);
+        assert_eq!("Mechanical_Pencil".parse::<PenType>().unwrap(), PenType::MechanicalPencil2);
+        assert_eq!("4".parse::<PenType>().unwrap(), PenType::Fineliner1);
+        assert_eq!("99".parse::<PenType>().unwrap(), PenType::Unknown(99));
+        assert!("quill".parse::<PenType>().is_err());
+        assert_eq!(PenType::Ballpoint1.to_string(), "ballpoint-1");
+        assert_eq!(u32::from(PenType::from(23)), 23);
+        assert_eq!(u32::from(PenType::Unknown(40)), 40);
+
+        assert_eq!("Blue".parse::<PenColor>().unwrap(), PenColor::Blue);
+        assert_eq!("grey".parse::<PenColor>().unwrap(), PenColor::Gray);
+        assert_eq!(PenColor::from(13), PenColor::Yellow2);
+        assert_eq!(PenColor::from(50).to_string(), "50");
+    }
+}
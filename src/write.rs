@@ -0,0 +1,79 @@
+
+use std::io::Write;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, Context};
+
+use super::types::*;
+
+/// Write a whole Notebook (frontmatter, then every block) as a `.rm` file.
+///
+/// Only v6 can be written; Notebooks read from legacy files are refused rather than
+/// written out with a header that doesn't match their blocks.
+pub fn write_blocks<W: Write>(notebook: Notebook, mut writer: W) -> Result<()> {
+    if notebook.frontmatter.is_legacy() {
+        Err(RMError::UnsupportedVersion(notebook.frontmatter.version))?
+    };
+    let bytes = RawBytes::from(notebook);
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write a Notebook to `file`, replacing it if it exists.
+///
+/// The bytes go to a temporary file next to `file` first, which is then renamed over
+/// the target. A crash part way through leaves the original page untouched.
+pub fn write_blocks_to_rm_file<P: AsRef<Path>>(notebook: Notebook, file: P) -> Result<PathBuf> {
+    let file = file.as_ref();
+    let tmp = temp_path_for(file);
+
+    let written = File::create(&tmp)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut out| {
+            write_blocks(notebook, &mut out)?;
+            out.sync_all()?;
+            Ok(())
+        })
+        .and_then(|_| fs::rename(&tmp, file).map_err(anyhow::Error::from));
+
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).context(format!("Failed to write {}", file.display()));
+    };
+
+    Ok(file.to_path_buf())
+}
+
+fn temp_path_for(file: &Path) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_owned();
+    name.push(".rmconvert-tmp");
+    file.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::*;
+
+    use std::fs::read;
+
+    #[test]
+    fn write_file_round_trip() {
+        let bytes = read(TEST_FILE_01).unwrap();
+        let notebook = parse_full(&bytes).unwrap();
+
+        let mut target = std::env::temp_dir();
+        target.push(format!("rmconvert-write-test-{}.rm", std::process::id()));
+        fs::write(&target, b"not a notebook").unwrap();
+
+        let written = write_blocks_to_rm_file(notebook, &target).unwrap();
+
+        assert_eq!(written, target);
+        assert_eq!(read(&target).unwrap(), bytes);
+        assert!(!temp_path_for(&target).exists());
+
+        fs::remove_file(&target).unwrap();
+    }
+}
@@ -22,3 +22,297 @@ pub fn test_help(){
         .stderr(help_pred);
 }
 
+
+const TEST_FILE_01: &str = "assets/test_notebooks/213001cb-42c0-4628-8ed0-8320c15da2a8/110b4d92-e42e-4b78-a0cb-ebd40862f2f0.rm";
+
+#[test]
+pub fn test_extract_layer(){
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "--skip-text", "--layer", "Layer 1", "-i", TEST_FILE_01])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"inkscape:label="Layer 1""#));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "--skip-text", "--layer", "No Such Layer", "-i", TEST_FILE_01])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no layer named"));
+}
+
+/// A v5 page, as firmware 2.x wrote them: one layer with one fineliner stroke.
+fn legacy_page() -> Vec<u8> {
+    let mut out = format!("{:<43}", "reMarkable .lines file, version=5").into_bytes();
+    for v in [1u32, 1, 4, 0, 0] {
+        out.extend(v.to_le_bytes());
+    };
+    out.extend(2.0f32.to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    out.extend(2u32.to_le_bytes());
+    for (x, y) in [(100.0f32, 100.0f32), (300.0, 200.0)] {
+        for v in [x, y, 0.5, 0.0, 2.0, 1.0] {
+            out.extend(v.to_le_bytes());
+        };
+    };
+    out
+}
+
+#[test]
+pub fn test_extract_legacy(){
+    let mut target = std::env::temp_dir();
+    target.push(format!("rmconvert-legacy-{}.rm", std::process::id()));
+    std::fs::write(&target, legacy_page()).unwrap();
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-t", "svg", "-i", target.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"inkscape:label="Layer 1""#)
+                .and(predicate::str::contains("<path")));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-t", "json", "-i", target.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""name":"Layer 1""#)
+                .and(predicate::str::contains(r#""pen":"fineliner-1""#)));
+
+    std::fs::remove_file(target).unwrap();
+}
+
+#[test]
+pub fn test_draw_shapes(){
+    let mut target = std::env::temp_dir();
+    target.push(format!("rmconvert-draw-shapes-{}.rm", std::process::id()));
+    std::fs::copy(TEST_FILE_01, &target).unwrap();
+
+    let shapes = r#"<svg viewBox="0 0 1404 1872">
+  <title>A diagram</title>
+  <rect x="100" y="100" width="200" height="80" rx="10"/>
+  <circle cx="400" cy="140" r="40"/>
+  <line x1="300" y1="140" x2="360" y2="140"/>
+</svg>"#;
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["draw", "--svg", "-", "-o"])
+        .arg(&target)
+        .write_stdin(shapes)
+        .assert()
+        .success();
+
+    let extracted = Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "--skip-text", "-i"])
+        .arg(&target)
+        .assert()
+        .success();
+    let before = Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "--skip-text", "-i", TEST_FILE_01])
+        .assert()
+        .success();
+
+    let count = |out: &[u8]| String::from_utf8_lossy(out).matches("<path").count();
+    assert_eq!(count(&extracted.get_output().stdout), count(&before.get_output().stdout) + 3);
+
+    std::fs::remove_file(&target).unwrap();
+}
+
+#[test]
+pub fn test_draw_text(){
+    let mut target = std::env::temp_dir();
+    target.push(format!("rmconvert-draw-text-{}.rm", std::process::id()));
+    std::fs::copy(TEST_FILE_01, &target).unwrap();
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["draw", "--text", "typed from the command line", "-o"])
+        .arg(&target)
+        .assert()
+        .success();
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "--format", "markdown", "-i"])
+        .arg(&target)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("typed from the command line"));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-i"])
+        .arg(&target)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"<text[^>]*>\s*typed from the command line\s*</text>").unwrap());
+
+    std::fs::remove_file(&target).unwrap();
+}
+
+#[test]
+pub fn test_extract_formats(){
+    const FORMATTED: &str = "assets/test_notebooks/e9453e30-cd4a-4a9b-aef4-41e04c047ff5/5b6148bf-2906-4281-adb5-a428d83de792.rm";
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "--format", "markdown", "-i", FORMATTED])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("# i am big text\n"));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-t", "json", "-i", FORMATTED])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(r#"{"schema":"rmconvert-page","schema_version":1,"#));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-t", "json", "--pretty", "--skip-lines", "-i", FORMATTED])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("{\n  \"schema\"").and(predicate::str::contains(r#""strokes": []"#)));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-t", "debug", "-i", FORMATTED])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("TextDef"));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-t", "bytes", "-i", TEST_FILE_01])
+        .assert()
+        .success()
+        .stdout(predicate::eq(std::fs::read(TEST_FILE_01).unwrap()));
+
+    let mut target = std::env::temp_dir();
+    target.push(format!("rmconvert-extract-{}.svg", std::process::id()));
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-t", "svg", "--border", "-i", FORMATTED, "-o"])
+        .arg(&target)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+    let svg = std::fs::read_to_string(&target).unwrap();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains(r#"class="border""#));
+    assert!(svg.contains("i am big text"));
+    std::fs::remove_file(&target).unwrap();
+}
+
+#[test]
+pub fn test_extract_document(){
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "tester3", "--page", "3", "-t", "markdown"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("# i am big text\n"));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "tester3", "-t", "svg"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"viewBox="0 0 1404 5616""#)
+                .and(predicate::str::contains(r#"id="page-3""#)));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "tester3", "-t", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(r#"{"schema":"rmconvert-document","schema_version":1,"#));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "tester3", "-t", "pdf"])
+        .assert()
+        .success()
+        .stdout(predicate::function(|out: &[u8]| out.starts_with(b"%PDF-1.7")));
+
+    // a notebook has no original PDF to draw onto
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "tester3", "-t", "pdf", "--annotate"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no original PDF"));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "tester3", "-t", "bytes"])
+        .assert()
+        .failure();
+
+    // page 12 of tester2 names its layer "layer 1 name", so it's empty in "Layer 1"
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "tester2", "--layer", "Layer 1", "-t", "svg"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"id="page-12""#)
+                .and(predicate::str::contains(r#"id="page-21""#)));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "tester2", "--layer", "No Such Layer"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no layer named"));
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["-r", "assets/test_notebooks", "extract", "-d", "Work/tester3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("nothing named \"Work\""));
+}
+
+#[test]
+pub fn test_draw_blank_page(){
+    // a notebook whose one page has never been written on, so has no .rm file yet
+    let root = std::env::temp_dir().join(format!("rmconvert-blank-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("blank.metadata"),
+                   r#"{"visibleName": "Blank", "parent": "", "type": "DocumentType"}"#).unwrap();
+    std::fs::write(root.join("blank.content"), r#"{
+        "fileType": "notebook",
+        "formatVersion": 2,
+        "cPages": {
+            "pages": [{"id": "first", "idx": {"timestamp": "1:2", "value": "ba"}}],
+            "uuids": [{"first": "eb2fac80-fb2e-598d-a714-df4ec5650b8e", "second": 1}]
+        }
+    }"#).unwrap();
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .arg("-r")
+        .arg(&root)
+        .args(["draw", "-d", "Blank", "--page", "1", "--path", "M 100 100 L 300 200", "--text", "a fresh page"])
+        .assert()
+        .success();
+    assert!(root.join("blank/first.rm").is_file());
+
+    Command::cargo_bin("rmconvert")
+        .unwrap()
+        .args(["extract", "-t", "svg", "-i"])
+        .arg(root.join("blank/first.rm"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"inkscape:label="Layer 1""#)
+                .and(predicate::str::contains("<path"))
+                .and(predicate::str::contains("a fresh page")));
+
+    std::fs::remove_dir_all(&root).unwrap();
+}